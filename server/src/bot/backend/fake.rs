@@ -0,0 +1,49 @@
+use futures::future::BoxFuture;
+use std::sync::{Arc, Mutex};
+
+use super::{Address, Backend, Error, File, Incoming};
+
+/// keeps everything send through it in memory, receives lines formatted as:
+/// <fake address id> <sender> <text>
+#[derive(Clone, Default)]
+pub struct Fake {
+	texts: Arc<Mutex<Vec<(Address, String)>>>,
+	files: Arc<Mutex<Vec<(Address, String)>>>,
+}
+
+impl Fake {
+	pub fn texts(&self) -> Vec<(Address, String)> {
+		self.texts.lock().unwrap().clone()
+	}
+
+	/// the address and file name of every file send
+	pub fn files(&self) -> Vec<(Address, String)> {
+		self.files.lock().unwrap().clone()
+	}
+}
+
+impl Backend for Fake {
+	fn send_text<'a>(&'a self, to: &'a Address, text: String) -> BoxFuture<'a, Result<(), Error>> {
+		self.texts.lock().unwrap().push((to.clone(), text));
+		Box::pin(async { Ok(()) })
+	}
+
+	fn send_file<'a>(&'a self, to: &'a Address, file: File) -> BoxFuture<'a, Result<(), Error>> {
+		self.files.lock().unwrap().push((to.clone(), file.name.to_owned()));
+		Box::pin(async { Ok(()) })
+	}
+
+	fn receive(&self, body: &[u8]) -> Vec<Incoming> {
+		String::from_utf8_lossy(body)
+			.lines()
+			.filter_map(|line| {
+				let mut parts = line.splitn(3, ' ');
+				Some(Incoming {
+					chat: Address::Fake(parts.next()?.parse().ok()?),
+					sender: parts.next()?.to_owned(),
+					text: parts.next()?.to_owned(),
+				})
+			})
+			.collect()
+	}
+}
@@ -0,0 +1,249 @@
+use futures::future::BoxFuture;
+use log::{info, warn};
+use reqwest::header::{AUTHORIZATION, CONTENT_TYPE};
+use reqwest::Url;
+use serde::Deserialize;
+use serde_json::json;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+use super::{Address, Backend, Error, File, Incoming};
+
+/// path the homeserver delivers events to, see the application service api
+pub const ENDPOINT: &str = "matrix";
+
+/// talks to a homeserver as an application service, messages are send using
+/// the client api
+#[derive(Clone)]
+pub struct Matrix {
+	/// for example https://matrix.example.org
+	homeserver: Url,
+	/// the bots own user, its messages are not commands
+	user_id: String,
+	access_token: String,
+	/// the token the homeserver uses when it sends us events
+	hs_token: String,
+	client: reqwest::Client,
+	next_txn: Arc<AtomicU64>,
+}
+
+#[derive(Deserialize)]
+struct Transaction {
+	events: Vec<Event>,
+}
+
+#[derive(Deserialize)]
+struct Event {
+	#[serde(rename = "type")]
+	kind: String,
+	room_id: Option<String>,
+	sender: String,
+	#[serde(default)]
+	content: serde_json::Value,
+}
+
+#[derive(Deserialize)]
+struct Uploaded {
+	content_uri: String,
+}
+
+impl Matrix {
+	/// fails if the homeserver is not a valid http(s) url
+	pub fn new(
+		homeserver: &str,
+		user_id: String,
+		access_token: String,
+		hs_token: String,
+	) -> Result<Self, Error> {
+		let homeserver = Url::parse(homeserver)
+			.ok()
+			.filter(|url| url.scheme().starts_with("http"))
+			.ok_or_else(|| Error::InvalidUrl(homeserver.to_owned()))?;
+		Ok(Self {
+			homeserver,
+			user_id,
+			access_token,
+			hs_token,
+			client: reqwest::Client::new(),
+			// transaction ids must be unique per access token, also across
+			// restarts
+			next_txn: Arc::new(AtomicU64::new(chrono::Utc::now().timestamp_millis() as u64)),
+		})
+	}
+
+	/// url of an api endpoint, the segments are percent encoded so a room id
+	/// can not change the path
+	fn url(&self, segments: &[&str]) -> Url {
+		let mut url = self.homeserver.clone();
+		url.path_segments_mut()
+			.expect("http urls have a path")
+			.pop_if_empty()
+			.extend(segments);
+		url
+	}
+
+	/// true if the request was made by our homeserver
+	pub fn authorized(&self, token: &str) -> bool {
+		ring::constant_time::verify_slices_are_equal(token.as_bytes(), self.hs_token.as_bytes())
+			.is_ok()
+	}
+
+	fn room(to: &Address) -> Result<&str, Error> {
+		match to {
+			Address::Matrix(room) => Ok(room),
+			_ => Err(Error::WrongBackend(to.clone())),
+		}
+	}
+
+	async fn send_event(&self, room: &str, content: serde_json::Value) -> Result<(), Error> {
+		let txn = self.next_txn.fetch_add(1, Ordering::Relaxed).to_string();
+		let url = self.url(&[
+			"_matrix",
+			"client",
+			"r0",
+			"rooms",
+			room,
+			"send",
+			"m.room.message",
+			&txn,
+		]);
+		let resp = self
+			.client
+			.put(url)
+			.header(AUTHORIZATION, format!("Bearer {}", self.access_token))
+			.header(CONTENT_TYPE, "application/json")
+			.body(content.to_string())
+			.send()
+			.await?;
+		if !resp.status().is_success() {
+			return Err(Error::Status(resp.status()));
+		}
+		Ok(())
+	}
+
+	async fn upload(&self, file: &File) -> Result<String, Error> {
+		let mut url = self.url(&["_matrix", "media", "r0", "upload"]);
+		url.query_pairs_mut().append_pair("filename", file.name);
+		let resp = self
+			.client
+			.post(url)
+			.header(AUTHORIZATION, format!("Bearer {}", self.access_token))
+			.header(CONTENT_TYPE, file.mime)
+			.body(file.bytes.clone())
+			.send()
+			.await?;
+		if !resp.status().is_success() {
+			return Err(Error::Status(resp.status()));
+		}
+		let uploaded: Uploaded = serde_json::from_slice(&resp.bytes().await?)?;
+		Ok(uploaded.content_uri)
+	}
+}
+
+impl Backend for Matrix {
+	fn send_text<'a>(&'a self, to: &'a Address, text: String) -> BoxFuture<'a, Result<(), Error>> {
+		Box::pin(async move {
+			let content = json!({ "msgtype": "m.text", "body": text });
+			self.send_event(Self::room(to)?, content).await
+		})
+	}
+
+	fn send_file<'a>(&'a self, to: &'a Address, file: File) -> BoxFuture<'a, Result<(), Error>> {
+		Box::pin(async move {
+			let room = Self::room(to)?;
+			let uri = self.upload(&file).await?;
+			let msgtype = if file.inline { "m.image" } else { "m.file" };
+			let content = json!({
+				"msgtype": msgtype,
+				"body": file.name,
+				"url": uri,
+				"info": { "mimetype": file.mime, "size": file.bytes.len() },
+			});
+			self.send_event(room, content).await
+		})
+	}
+
+	/// text messages in a transaction the homeserver pushed to us
+	fn receive(&self, body: &[u8]) -> Vec<Incoming> {
+		let transaction: Transaction = match serde_json::from_slice(body) {
+			Ok(transaction) => transaction,
+			Err(e) => {
+				warn!("could not parse matrix transaction: {:?}", e);
+				return Vec::new();
+			}
+		};
+		transaction
+			.events
+			.into_iter()
+			.filter(|event| event.kind == "m.room.message")
+			.filter(|event| event.sender != self.user_id)
+			.filter(|event| event.content["msgtype"] == "m.text")
+			.filter_map(|event| {
+				Some(Incoming {
+					chat: Address::Matrix(event.room_id?),
+					text: event.content["body"].as_str()?.to_owned(),
+					sender: event.sender,
+				})
+			})
+			.collect()
+	}
+
+	/// application services are registered in the homeserver configuration
+	fn register_endpoint<'a>(&'a self, url: &'a str) -> BoxFuture<'a, Result<(), Error>> {
+		Box::pin(async move {
+			info!("the matrix application service registration should use url: {}", url);
+			Ok(())
+		})
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn matrix() -> Matrix {
+		Matrix::new(
+			"https://example.org/",
+			String::from("@bot:example.org"),
+			String::from("as"),
+			String::from("hs"),
+		)
+		.unwrap()
+	}
+
+	#[test]
+	fn room_is_escaped() {
+		let url = matrix().url(&["rooms", "!a/../b?c#d:example.org", "send"]);
+		assert_eq!(
+			url.as_str(),
+			"https://example.org/rooms/!a%2F..%2Fb%3Fc%23d:example.org/send"
+		);
+		assert!(Matrix::new("example.org", String::new(), String::new(), String::new()).is_err());
+	}
+
+	#[test]
+	fn receive_transaction() {
+		let body = br#"{"events":[
+			{"type":"m.room.message","room_id":"!a:example.org","sender":"@bob:example.org",
+				"content":{"msgtype":"m.text","body":"/plot 3_0 24h"}},
+			{"type":"m.room.member","room_id":"!a:example.org","sender":"@bob:example.org",
+				"content":{"membership":"join"}},
+			{"type":"m.room.message","room_id":"!a:example.org","sender":"@bob:example.org",
+				"content":{"msgtype":"m.image","body":"cat.png"}},
+			{"type":"m.room.message","room_id":"!a:example.org","sender":"@bot:example.org",
+				"content":{"msgtype":"m.text","body":"unknown command"}}
+		]}"#;
+		let matrix = matrix();
+		let incoming = matrix.receive(body);
+		assert_eq!(
+			incoming,
+			vec![Incoming {
+				chat: Address::Matrix(String::from("!a:example.org")),
+				sender: String::from("@bob:example.org"),
+				text: String::from("/plot 3_0 24h"),
+			}]
+		);
+		assert!(matrix.authorized("hs"));
+		assert!(!matrix.authorized("as"));
+	}
+}
@@ -0,0 +1,260 @@
+use error_level::ErrorLevel;
+use futures::future::BoxFuture;
+use log::info;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use telegram_bot::types::refs::ChatId;
+
+#[cfg(test)]
+pub mod fake;
+pub mod matrix;
+pub mod ntfy;
+pub mod telegram;
+
+pub use matrix::Matrix;
+pub use ntfy::Ntfy;
+pub use telegram::Telegram;
+
+#[derive(ErrorLevel, thiserror::Error, Debug)]
+pub enum Error {
+	#[report(warn)] //could just be the service is down
+	#[error("could not reach the messaging service: {0}")]
+	Request(reqwest::Error),
+	#[report(warn)]
+	#[error("the messaging service responded with: {0}")]
+	Status(reqwest::StatusCode),
+	#[report(warn)]
+	#[error("unexpected response from the messaging service: {0}")]
+	Decode(#[from] serde_json::Error),
+	#[report(debug)]
+	#[error("{0} is not set up on this server")]
+	NotConfigured(&'static str),
+	#[report(debug)]
+	#[error("this only works on telegram")]
+	TelegramOnly,
+	#[report(error)]
+	#[error("message for {0} was passed to the wrong backend")]
+	WrongBackend(Address),
+	#[report(error)]
+	#[error("\"{0}\" is not a valid http(s) url")]
+	InvalidUrl(String),
+}
+
+/// the url is dropped as it can contain credentials such as the telegram
+/// bot token, errors end up in the log
+impl From<reqwest::Error> for Error {
+	fn from(err: reqwest::Error) -> Self {
+		Error::Request(err.without_url())
+	}
+}
+
+/// where a message goes, each variant is handled by its own backend
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Address {
+	Telegram(ChatId),
+	/// a room id such as !abc:example.org
+	Matrix(String),
+	/// a topic on the ntfy server
+	Ntfy(String),
+	#[cfg(test)]
+	Fake(u64),
+}
+
+impl Address {
+	/// parses the backend name and id as used in /link
+	pub fn parse(backend: &str, id: &str) -> Option<Self> {
+		let topic_char = |c: char| c.is_ascii_alphanumeric() || c == '_' || c == '-';
+		match backend {
+			"matrix" if id.starts_with('!') && id.contains(':') => Some(Address::Matrix(id.to_owned())),
+			"ntfy" if !id.is_empty() && id.chars().all(topic_char) => {
+				Some(Address::Ntfy(id.to_owned()))
+			}
+			#[cfg(test)]
+			"fake" => id.parse().ok().map(Address::Fake),
+			_ => None,
+		}
+	}
+
+	/// name of the backend that handles the address
+	pub fn backend(&self) -> &'static str {
+		match self {
+			Address::Telegram(_) => "telegram",
+			Address::Matrix(_) => "matrix",
+			Address::Ntfy(_) => "ntfy",
+			#[cfg(test)]
+			Address::Fake(_) => "fake",
+		}
+	}
+
+	pub fn telegram(&self) -> Option<ChatId> {
+		match self {
+			Address::Telegram(chat_id) => Some(*chat_id),
+			_ => None,
+		}
+	}
+}
+
+impl fmt::Display for Address {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			Address::Telegram(chat_id) => write!(f, "telegram {}", chat_id),
+			Address::Matrix(room) => write!(f, "matrix {}", room),
+			Address::Ntfy(topic) => write!(f, "ntfy {}", topic),
+			#[cfg(test)]
+			Address::Fake(id) => write!(f, "fake {}", id),
+		}
+	}
+}
+
+/// a file to send such as a plot
+pub struct File {
+	pub bytes: Vec<u8>,
+	pub mime: &'static str,
+	pub name: &'static str,
+	/// show the file as picture instead of as attachment
+	pub inline: bool,
+}
+
+/// a command someone send to the bot
+#[derive(Debug, PartialEq)]
+pub struct Incoming {
+	/// where to send the reply
+	pub chat: Address,
+	/// the id of the sender on the backend
+	pub sender: String,
+	pub text: String,
+}
+
+/// a service messages can be send through, backends that can only send
+/// (push notifications) use the default receive and register_endpoint
+pub trait Backend: Send + Sync {
+	fn send_text<'a>(&'a self, to: &'a Address, text: String) -> BoxFuture<'a, Result<(), Error>>;
+	fn send_file<'a>(&'a self, to: &'a Address, file: File) -> BoxFuture<'a, Result<(), Error>>;
+	/// the commands in a request the service made to our endpoint
+	fn receive(&self, _body: &[u8]) -> Vec<Incoming> {
+		Vec::new()
+	}
+	/// tells the service to deliver messages for the bot to url
+	fn register_endpoint<'a>(&'a self, _url: &'a str) -> BoxFuture<'a, Result<(), Error>> {
+		Box::pin(async { Ok(()) })
+	}
+}
+
+/// all configured backends, telegram is always present
+#[derive(Clone)]
+pub struct Backends {
+	pub telegram: Telegram,
+	pub matrix: Option<Matrix>,
+	pub ntfy: Option<Ntfy>,
+	#[cfg(test)]
+	pub fake: Option<fake::Fake>,
+}
+
+impl Backends {
+	pub fn get(&self, to: &Address) -> Result<&dyn Backend, Error> {
+		match to {
+			Address::Telegram(_) => Ok(&self.telegram),
+			Address::Matrix(_) => self
+				.matrix
+				.as_ref()
+				.map(|b| b as &dyn Backend)
+				.ok_or(Error::NotConfigured("matrix")),
+			Address::Ntfy(_) => self
+				.ntfy
+				.as_ref()
+				.map(|b| b as &dyn Backend)
+				.ok_or(Error::NotConfigured("ntfy")),
+			#[cfg(test)]
+			Address::Fake(_) => self
+				.fake
+				.as_ref()
+				.map(|b| b as &dyn Backend)
+				.ok_or(Error::NotConfigured("fake")),
+		}
+	}
+
+	pub async fn send_text<T: Into<String>>(&self, to: &Address, text: T) -> Result<(), Error> {
+		self.get(to)?.send_text(to, text.into()).await
+	}
+
+	pub async fn send_file(&self, to: &Address, file: File) -> Result<(), Error> {
+		self.get(to)?.send_file(to, file).await
+	}
+
+	/// the chat and telegram backend, for features only telegram has such
+	/// as keyboards
+	pub fn telegram_chat(&self, to: &Address) -> Result<(ChatId, &Telegram), Error> {
+		let chat_id = to.telegram().ok_or(Error::TelegramOnly)?;
+		Ok((chat_id, &self.telegram))
+	}
+
+	/// registers the endpoint of every backend that receives commands,
+	/// they are served at <base_url>/<path>. Telegram is skipped if we
+	/// long poll it for updates instead
+	pub async fn register_endpoints(&self, base_url: &str, poll_telegram: bool) -> Result<(), Error> {
+		if !poll_telegram {
+			let url = format!("{}/{}", base_url, self.telegram.endpoint());
+			self.telegram.register_endpoint(&url).await?;
+		}
+		if let Some(matrix) = &self.matrix {
+			let url = format!("{}/{}", base_url, matrix::ENDPOINT);
+			matrix.register_endpoint(&url).await?;
+		}
+		info!("registered messaging endpoints at {}", base_url);
+		Ok(())
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use futures::executor::block_on;
+
+	fn backends(fake: fake::Fake) -> Backends {
+		Backends {
+			telegram: Telegram::new(String::from("token"), telegram::DEFAULT_API.to_owned()),
+			matrix: None,
+			ntfy: None,
+			fake: Some(fake),
+		}
+	}
+
+	#[test]
+	fn routes_by_address() {
+		let fake = fake::Fake::default();
+		let backends = backends(fake.clone());
+		block_on(backends.send_text(&Address::Fake(1), "hi")).unwrap();
+		let file = File {
+			bytes: vec![1, 2, 3],
+			mime: "image/png",
+			name: "plot.png",
+			inline: true,
+		};
+		block_on(backends.send_file(&Address::Fake(2), file)).unwrap();
+
+		assert_eq!(fake.texts(), vec![(Address::Fake(1), String::from("hi"))]);
+		assert_eq!(fake.files(), vec![(Address::Fake(2), String::from("plot.png"))]);
+		let res = block_on(backends.send_text(&Address::Ntfy(String::from("alerts")), "hi"));
+		assert!(matches!(res, Err(Error::NotConfigured("ntfy"))));
+	}
+
+	#[test]
+	fn parse_address() {
+		assert_eq!(
+			Address::parse("matrix", "!abc:example.org"),
+			Some(Address::Matrix(String::from("!abc:example.org")))
+		);
+		assert_eq!(Address::parse("matrix", "abc"), None);
+		assert_eq!(Address::parse("ntfy", "a/b"), None);
+		assert_eq!(Address::parse("ntfy", "a?b"), None);
+		assert_eq!(Address::parse("email", "x"), None);
+	}
+
+	#[test]
+	fn errors_hide_token() {
+		let url = "http://127.0.0.1:1/bot1234:secret-token/getUpdates";
+		let err: Error = reqwest::blocking::get(url).unwrap_err().into();
+		assert!(!format!("{:?}", err).contains("secret-token"));
+		assert!(!err.to_string().contains("secret-token"));
+	}
+}
@@ -0,0 +1,65 @@
+use futures::future::BoxFuture;
+use reqwest::header::{HeaderValue, AUTHORIZATION};
+
+use super::{Address, Backend, Error, File};
+
+/// push notifications through an ntfy server, anyone who knows the topic
+/// can read them. Can not receive commands.
+#[derive(Clone)]
+pub struct Ntfy {
+	/// for example https://ntfy.sh
+	server: String,
+	/// send as bearer token if the server requires authentication
+	token: Option<String>,
+	client: reqwest::Client,
+}
+
+impl Ntfy {
+	pub fn new(server: String, token: Option<String>) -> Self {
+		Self {
+			server: server.trim_end_matches('/').to_owned(),
+			token,
+			client: reqwest::Client::new(),
+		}
+	}
+
+	fn topic_url(&self, to: &Address) -> Result<String, Error> {
+		match to {
+			Address::Ntfy(topic) => Ok(format!("{}/{}", self.server, topic)),
+			_ => Err(Error::WrongBackend(to.clone())),
+		}
+	}
+
+	async fn publish(&self, request: reqwest::RequestBuilder) -> Result<(), Error> {
+		let request = match &self.token {
+			Some(token) => request.header(AUTHORIZATION, format!("Bearer {}", token)),
+			None => request,
+		};
+		let resp = request.send().await?;
+		if !resp.status().is_success() {
+			return Err(Error::Status(resp.status()));
+		}
+		Ok(())
+	}
+}
+
+impl Backend for Ntfy {
+	fn send_text<'a>(&'a self, to: &'a Address, text: String) -> BoxFuture<'a, Result<(), Error>> {
+		Box::pin(async move {
+			let request = self.client.post(&self.topic_url(to)?).body(text);
+			self.publish(request).await
+		})
+	}
+
+	/// ntfy takes the file as body of a put, the file name as header
+	fn send_file<'a>(&'a self, to: &'a Address, file: File) -> BoxFuture<'a, Result<(), Error>> {
+		Box::pin(async move {
+			let request = self
+				.client
+				.put(&self.topic_url(to)?)
+				.header("Filename", HeaderValue::from_static(file.name))
+				.body(file.bytes);
+			self.publish(request).await
+		})
+	}
+}
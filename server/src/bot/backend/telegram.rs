@@ -0,0 +1,226 @@
+use futures::future::BoxFuture;
+use log::{info, warn};
+use reqwest::multipart::{Form, Part};
+use serde::Deserialize;
+use telegram_bot::types::message::MessageKind;
+use telegram_bot::types::refs::ChatId;
+use telegram_bot::types::update::{Update, UpdateKind};
+
+use super::{Address, Backend, Error, File, Incoming};
+
+pub const DEFAULT_API: &str = "https://api.telegram.org";
+
+#[derive(Clone)]
+pub struct Telegram {
+	pub token: String,
+	/// base url of the bot api, can point to a local bot api server or mock
+	api: String,
+	client: reqwest::Client,
+}
+
+#[derive(Deserialize)]
+struct Updates {
+	result: Vec<serde_json::Value>,
+}
+
+#[derive(Deserialize)]
+struct UpdateId {
+	update_id: i64,
+}
+
+/// the id of each update and the update itself if we understand it, an
+/// update we can not parse should still move the offset past it
+fn parse_updates(body: &[u8]) -> Result<Vec<(i64, Option<Update>)>, Error> {
+	let updates: Updates = serde_json::from_slice(body)?;
+	let mut parsed = Vec::with_capacity(updates.result.len());
+	for raw in updates.result {
+		let UpdateId { update_id } = serde_json::from_value(raw.clone())?;
+		let update = serde_json::from_value(raw)
+			.map_err(|e| warn!("could not parse update {}: {:?}", update_id, e))
+			.ok();
+		parsed.push((update_id, update));
+	}
+	Ok(parsed)
+}
+
+impl Telegram {
+	pub fn new(token: String, api: String) -> Self {
+		Self {
+			token,
+			api: api.trim_end_matches('/').to_owned(),
+			client: reqwest::Client::new(),
+		}
+	}
+
+	/// telegram posts updates to a path only it and we know
+	pub fn endpoint(&self) -> &str {
+		&self.token
+	}
+
+	fn url(&self, method: &str) -> String {
+		format!("{}/bot{}/{}", self.api, self.token, method)
+	}
+
+	/// telegram refuses getUpdates while a webhook is set
+	pub async fn delete_webhook(&self) -> Result<(), Error> {
+		self.call("deleteWebhook", Form::new()).await
+	}
+
+	/// long polls for updates starting at offset, returns after timeout
+	/// seconds if there are none
+	pub async fn get_updates(
+		&self,
+		offset: i64,
+		timeout: u64,
+	) -> Result<Vec<(i64, Option<Update>)>, Error> {
+		let params = [
+			("offset", offset.to_string()),
+			("timeout", timeout.to_string()),
+		];
+		let resp = self
+			.client
+			.post(&self.url("getUpdates"))
+			.form(&params)
+			.send()
+			.await?;
+		if resp.status() != reqwest::StatusCode::OK {
+			return Err(Error::Status(resp.status()));
+		}
+		parse_updates(&resp.bytes().await?)
+	}
+
+	/// calls a method of the bot api, used directly for telegram only
+	/// features such as keyboards
+	pub async fn call(&self, method: &str, form: Form) -> Result<(), Error> {
+		let resp = self.client.post(&self.url(method)).multipart(form).send().await?;
+		if resp.status() != reqwest::StatusCode::OK {
+			Err(Error::Status(resp.status()))
+		} else {
+			Ok(())
+		}
+	}
+
+	/// the text commands in an update, other updates are ignored
+	pub fn receive_update(&self, update: Update) -> Vec<Incoming> {
+		let message = match update.kind {
+			UpdateKind::Message(message) => message,
+			_ => {
+				warn!("unhandled update from telegram: {:?}", update);
+				return Vec::new();
+			}
+		};
+		match message.kind {
+			MessageKind::Text { data, entities: _ } => vec![Incoming {
+				chat: Address::Telegram(message.chat.id()),
+				sender: message.from.id.to_string(),
+				text: data,
+			}],
+			_ => {
+				warn!("unhandled message kind");
+				Vec::new()
+			}
+		}
+	}
+}
+
+fn chat_id(to: &Address) -> Result<ChatId, Error> {
+	to.telegram().ok_or_else(|| Error::WrongBackend(to.clone()))
+}
+
+impl Backend for Telegram {
+	fn send_text<'a>(&'a self, to: &'a Address, text: String) -> BoxFuture<'a, Result<(), Error>> {
+		Box::pin(async move {
+			let form = Form::new()
+				.text("chat_id", chat_id(to)?.to_string())
+				.text("text", text);
+			self.call("sendMessage", form).await?;
+			info!("send message");
+			Ok(())
+		})
+	}
+
+	/// inline files are send as photo others as document
+	fn send_file<'a>(&'a self, to: &'a Address, file: File) -> BoxFuture<'a, Result<(), Error>> {
+		Box::pin(async move {
+			let (method, field) = if file.inline {
+				("sendPhoto", "photo")
+			} else {
+				("sendDocument", "document")
+			};
+			let part = Part::bytes(file.bytes)
+				.mime_str(file.mime)
+				.unwrap()
+				.file_name(file.name);
+			let form = Form::new()
+				.text("chat_id", chat_id(to)?.to_string())
+				.part(field, part);
+			self.call(method, form).await
+		})
+	}
+
+	fn receive(&self, body: &[u8]) -> Vec<Incoming> {
+		match serde_json::from_slice(body) {
+			Ok(update) => self.receive_update(update),
+			Err(e) => {
+				warn!("could not parse update from telegram: {:?}", e);
+				Vec::new()
+			}
+		}
+	}
+
+	fn register_endpoint<'a>(&'a self, url: &'a str) -> BoxFuture<'a, Result<(), Error>> {
+		Box::pin(async move {
+			let params = [("url", url)];
+			let resp = self
+				.client
+				.post(&self.url("setWebhook"))
+				.form(&params)
+				.send()
+				.await?;
+			if resp.status() != reqwest::StatusCode::OK {
+				return Err(Error::Status(resp.status()));
+			}
+			info!("set webhook to: {}", url);
+			Ok(())
+		})
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn receive_text_message() {
+		let update = br#"{"update_id":1,"message":{"message_id":5,"date":0,
+			"chat":{"id":42,"type":"private","first_name":"a"},
+			"from":{"id":42,"is_bot":false,"first_name":"a"},
+			"text":"/show 3_0"}}"#;
+		let telegram = Telegram::new(String::from("token"), DEFAULT_API.to_owned());
+		let incoming = telegram.receive(update);
+		assert_eq!(
+			incoming,
+			vec![Incoming {
+				chat: Address::Telegram(ChatId::new(42)),
+				sender: String::from("42"),
+				text: String::from("/show 3_0"),
+			}]
+		);
+	}
+
+	#[test]
+	fn unparsable_update_is_skipped() {
+		let body = br#"{"ok":true,"result":[
+			{"update_id":7,"message":{"message_id":5,"date":0,
+				"chat":{"id":42,"type":"private","first_name":"a"},
+				"from":{"id":42,"is_bot":false,"first_name":"a"},
+				"text":"/help"}},
+			{"update_id":8,"message":"not a message"}
+		]}"#;
+		let updates = parse_updates(body).unwrap();
+		let ids: Vec<_> = updates.iter().map(|(id, _)| *id).collect();
+		assert_eq!(ids, vec![7, 8]);
+		assert!(updates[0].1.is_some());
+		assert!(updates[1].1.is_none());
+	}
+}
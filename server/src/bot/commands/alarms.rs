@@ -1,24 +1,32 @@
 pub const USAGE: &str = "/alarm";
-pub const DESCRIPTION: &str = "list, add and remove sensor notifications";
+pub const DESCRIPTION: &str = "list, add, remove, snooze and acknowledge sensor notifications, \
+	including alarms on sensors that stopped sending";
 
 use std::collections::{HashMap, HashSet};
 use std::time::Duration;
 
-use crate::data_store::data_router::{AddAlarm, RemoveAlarm};
-use crate::data_store::data_router::{Alarm, DataRouterState, NotifyVia};
-use crate::data_store::DatasetId;
-use crate::database::{AlarmDbError, User};
+use crate::data_store::data_router::{AddAlarm, RemoveAlarm, ReplaceAlarm};
+use crate::data_store::data_router::{backtest, BacktestError, Firing};
+use crate::data_store::data_router::{windows, Alarm, DataRouterState, NotifyVia};
+use crate::data_store::{names, Data, DatasetId};
+use crate::database::{Access, AlarmDbError, AlarmId, User};
+use crate::notify::webhook::{self, Webhook};
 use bitspec::FieldId;
-use chrono::{self, Weekday};
+use chrono::{self, Utc, Weekday};
 use evalexpr::{build_operator_tree, EvalexprError};
 use log::error;
 use regex::{Captures, Regex};
-use telegram_bot::types::refs::ChatId;
+use crate::bot::backend::{Address, Backends};
 use error_level::ErrorLevel;
 
 use super::super::send_text_reply;
 use super::super::Error as botError;
 
+mod escalation;
+mod silence;
+use escalation::HELP_ACK;
+use silence::{HELP_MAINTENANCE, HELP_MUTE, HELP_SNOOZE};
+
 #[derive(ErrorLevel, thiserror::Error, Debug)]
 pub enum Error {
     #[report(debug)]
@@ -45,6 +53,9 @@ pub enum Error {
     #[report(debug)]
 	#[error("I could not understand the alarms condition. Cause: {0}")]
 	Expression(#[from] EvalexprError),
+    #[report(debug)]
+	#[error("I could not understand a function in the alarms condition: {0}")]
+	Window(#[from] windows::Error),
     #[report(debug)]
 	#[error("I could not understand what day this is: {0}")]
 	InvalidDay(String),
@@ -52,18 +63,60 @@ pub enum Error {
 	#[error("can not set more then 255 alarms")]
 	TooManyAlarms,
     #[report(debug)]
-	#[error("Could not recognise an alarm number in: {0}")]
-	NotAnAlarmNumber(String),
+	#[error("This \"{0}\" is not a valid dataset id")]
+	InvalidDatasetId(String),
+    #[report(debug)]
+	#[error("Not enough arguments, type /alarm for help")]
+	NotEnoughArguments,
+    #[report(debug)]
+	#[error("Only admins can do this")]
+	AdminOnly,
+    #[report(debug)]
+	#[error("You need to own every field of dataset {0} to plan its maintenance")]
+	NotDatasetOwner(DatasetId),
+    #[report(debug)]
+	#[error("There is no maintenance planned for dataset {0} at that time")]
+	NoMaintenance(DatasetId),
+    #[report(debug)]
+	#[error("I could not understand this date or time: {0}")]
+	InvalidTime(String),
+	#[error("{0}")]
+	Names(#[from] names::Error),
+    #[report(debug)]
+	#[error("Missing the time to test over\nuse: {}", HELP_TEST)]
+	NoTestDuration,
+	#[error("Could not test the alarm: {0}")]
+	Backtest(#[from] BacktestError),
     #[report(error)]
 	#[error("Could not save alarm")]
 	Db(#[from] AlarmDbError),
+    #[report(debug)]
+	#[error("{0}")]
+	Lookup(AlarmDbError),
+    #[report(debug)]
+	#[error("I could not understand this escalation chain: \"{0}\", it should look like: \"15m alice,bob 1h carol\"")]
+	InvalidEscalation(String),
+    #[report(debug)]
+	#[error("There is no user named: {0}")]
+	UnknownUser(String),
+    #[report(debug)]
+	#[error("Escalation steps can be at most a year after the alarm went off, not: {0}")]
+	EscalationTooLate(String),
+    #[report(debug)]
+	#[error("Durations can be at most a hundred years, not: {0}")]
+	DurationTooLong(String),
+    #[report(debug)]
+	#[error("I can not use this webhook: {0}")]
+	Webhook(#[from] webhook::Error),
 }
 
 pub const HELP_ADD: &str = "/alarm add [options] \"condition\"\n\
 	example: add \"3_0> 3_1 & t>9:30 & t<12:00\" -d [Sunday,Saturday] -p 5h -c /plotables\
 	\ncondition; a boolean statement that may use these binairy operators: \
-	^ * / % + - < > == != && || on sensor fields (see /plotables for options) \
-	or time (use the symbole \"t\")\
+	^ * / % + - < > == != && || on sensor fields (see /plotables for options, \
+	a field can also be given as dataset_name.field_name) or time (use the symbole \"t\"). The functions avg, max, min, delta \
+	and rate (change per second) work on the values of a field over the \
+	given time, for example: avg(3_0, 1h) > 70 or delta(3_1, 10m) > 5\
 	\npossible options:\n\
 	-d [Weekday, .... , Weekday]\n\
 	days on which the condition should be evaluated \
@@ -76,18 +129,49 @@ pub const HELP_ADD: &str = "/alarm add [options] \"condition\"\n\
 	here command should be a valid telegram command \
 	for this bot. If the command is more then one word \
 	long it should be enclosed in quotes\n\
+	-e <email address>\n\
+	also send the notification to this email address\n\
+	-w <url>\n\
+	also post the notification as json to this webhook, it must \
+	use https and be reachable from the internet\n\
+	-s <secret>\n\
+	sign the webhooks json using HMAC-SHA256 with this secret, \
+	the signature is in the X-Dataserver-Signature header\n\
+	-n <name>\n\
+	a name to refer to the alarm by, must start with a letter\n\
+	-x \"<number><unit> <user,user> ... <number><unit> <user>\"\n\
+	escalation chain, if nobody acknowledged the alarm (see /alarm ack) the \
+	given time after it went off the listed users are notified as well\n\
 	-i <percentage>\n\
 	prevent alarm from being triggerd continuesly, once \
 	an alarm is triggerd disarm and set an inverse \
 	alarm that will re-enable it once one of the values \
 	it watches deviates the given percentage from the alarm \
 	activation value\n";
+pub const HELP_STALE: &str = "/alarm stale <dataset id> <number><unit> [options]\n\
+	notifies you if the dataset sent no data for longer then the given time \
+	and again once data comes in. The time unit can be s,m,h,d or w. \
+	The options -c, -e, -w, -s and -m work as they do for add\n\
+	example: stale 3 15m -e me@example.com\n";
+pub const HELP_TEST: &str = "/alarm test \"condition\" <number><unit> [options]\n\
+	replays the data of the given time ago till now through the alarm and \
+	reports when it would have gone off. Takes the same options as add, \
+	the time unit can be s,m,h,d or w\n\
+	example: test \"3_0 > 25\" 1w -p 5h\n";
+pub const HELP_HISTORY: &str = "/alarm history <name|id>\n\
+	shows when the alarm went off or was re-enabled and the values it \
+	watched at that moment, the full history is on the alarm_history page \
+	of the website\n";
 pub const HELP_LIST: &str = "/alarm list\n\
-	shows for all set alarms their: id, condition, timezone and action\
+	shows for all set alarms their: id, name, condition, timezone and action\
 	performed when the condition is satisfied.\n";
-pub const HELP_REMOVE: &str = "/alarm remove list_numb_1 list_numb_2....list_numb_n\n\
+pub const HELP_REMOVE: &str = "/alarm remove <name|id> <name|id>....<name|id>\n\
 	removes one or multiple active alarms, space seperate list \
-	of the numbers in front of the expressions of the alarm list.";
+	of the names or ids of the alarms as shown in the alarm list.";
+pub const HELP_EDIT: &str = "/alarm edit <name|id> [options] \"condition\"\n\
+	replaces the condition and options of an alarm by the given ones, \
+	see add. The alarm keeps its id, its name unless -n is given and is reset \
+	as if it was just added\n";
 
 //alarm will fire on a Sunday or Saturday
 //between 9:30 and 12am if the value of
@@ -102,6 +186,11 @@ struct Arguments {
 	period: Option<Duration>,
 	message: Option<String>,
 	command: Option<String>,
+	email: Option<String>,
+	webhook: Option<String>,
+	secret: Option<String>,
+	name: Option<String>,
+	escalation: Option<String>,
 	fields: HashMap<DatasetId, Vec<FieldId>>,
 }
 
@@ -147,11 +236,39 @@ pub fn format_time_human_readable(expression: String) -> String {
 		.to_string()
 }
 
-fn parse_arguments(args: &str) -> Result<Arguments, Error> {
+/// durations are capped so adding them to a date can not overflow
+const MAX_DURATION: u64 = 60 * 60 * 24 * 365 * 100;
+
+fn parse_duration(numb: &str, unit: &str) -> Result<Duration, Error> {
+	let numb = numb.parse::<u64>()?;
+	let seconds = match unit {
+		"s" => 1,
+		"m" => 60,
+		"h" => 60 * 60,
+		"d" => 60 * 60 * 24,
+		"w" => 60 * 60 * 24 * 7,
+		_ => {
+			return Err(Error::IncorrectTimeUnit(unit.to_owned()));
+		}
+	};
+	numb.checked_mul(seconds)
+		.filter(|seconds| *seconds <= MAX_DURATION)
+		.map(Duration::from_secs)
+		.ok_or_else(|| Error::DurationTooLong(format!("{}{}", numb, unit)))
+}
+
+fn parse_arguments(args: &str, data: &Data, access: &Access) -> Result<Arguments, Error> {
 	dbg!(&args); //FIXME problem seems to be no spaces anymore here
+	//quoted message and command options could be mistaken for the expression
+	let quoted_options_re = Regex::new(r#"-[mcx] "[^"]*""#).unwrap();
+	let without_options = quoted_options_re.replace_all(args, "");
 	let exp_re = Regex::new(r#""(.*)""#).unwrap();
-	let expression = exp_re.find(args).ok_or(Error::NoExpression)?.as_str();
-	let expression = expression.get(1..expression.len() - 1).unwrap().to_owned();
+	let expression = exp_re
+		.find(&without_options)
+		.ok_or(Error::NoExpression)?
+		.as_str();
+	let expression = expression.get(1..expression.len() - 1).unwrap();
+	let expression = names::resolve(expression, data, access)?;
 	dbg!("exp");
 	dbg!(&expression);
 	let expression = format_time_to_seconds(expression);
@@ -178,36 +295,57 @@ fn parse_arguments(args: &str) -> Result<Arguments, Error> {
 
 	let period_re = Regex::new(r#"-p (\d+)([smhdw])"#).unwrap();
 	let period = if let Some(caps) = period_re.captures(args) {
-		let numb = caps.get(1).unwrap().as_str().parse::<u64>()?;
-		let unit = caps.get(2).unwrap().as_str();
-		if numb == 0 {
+		let duration = parse_duration(&caps[1], &caps[2])?;
+		if duration.as_secs() == 0 {
 			None
 		} else {
-			Some(match unit {
-				"s" => Duration::from_secs(numb),
-				"m" => Duration::from_secs(numb * 60),
-				"h" => Duration::from_secs(numb * 60 * 60),
-				"d" => Duration::from_secs(numb * 60 * 60 * 24),
-				"w" => Duration::from_secs(numb * 60 * 60 * 24 * 7),
-				_ => {
-					return Err(Error::IncorrectTimeUnit(unit.to_owned()));
-				}
-			})
+			Some(duration)
 		}
 	} else {
 		Some(Duration::from_secs(60 * 60))
 	};
 
-	let message_re = Regex::new(r#"-m "(.+)""#).unwrap();
-	let message = message_re.find(args).map(|mat| mat.as_str().to_owned());
+	let message_re = Regex::new(r#"-m "([^"]+)""#).unwrap();
+	let message = message_re
+		.captures(args)
+		.map(|caps| caps.get(1).unwrap().as_str().to_owned());
+
+	let command_re = Regex::new(r#"-c ([^"\-\s]+|"[^"]+")"#).unwrap();
+	let command = command_re
+		.captures(args)
+		.map(|caps| caps.get(1).unwrap().as_str().trim_matches('"').to_owned());
+
+	let email_re = Regex::new(r#"-e ([^"\s]+@[^"\s]+)"#).unwrap();
+	let email = email_re
+		.captures(args)
+		.map(|caps| caps.get(1).unwrap().as_str().to_owned());
 
-	let command_re = Regex::new(r#"-c ([^"\-\s]+|".+")"#).unwrap();
-	let command = command_re.find(args).map(|mat| mat.as_str().to_owned());
+	let webhook_re = Regex::new(r#"-w (https?://[^"\s]+)"#).unwrap();
+	let secret_re = Regex::new(r#"-s ([^"\s]+)"#).unwrap();
+	let webhook = webhook_re.captures(args).map(|caps| caps[1].to_owned());
+	let secret = secret_re.captures(args).map(|caps| caps[1].to_owned());
+
+	//names can not be numbers as alarms can also be refered to by their id
+	let name_re = Regex::new(r#"-n ([A-Za-z][\w\-]*)"#).unwrap();
+	let name = name_re
+		.captures(args)
+		.map(|caps| caps.get(1).unwrap().as_str().to_owned());
+
+	let escalation_re = Regex::new(r#"-x "([^"]+)""#).unwrap();
+	let escalation = escalation_re
+		.captures(args)
+		.map(|caps| caps[1].to_owned());
 
 	let counter_expr = args.contains("-bc");
 
 	let mut fields: HashMap<DatasetId, Vec<FieldId>> = HashMap::new();
-	let re = Regex::new(r#"\d+_\d+"#).unwrap();
+	//the age of a sets data needs access to the set but not to a field
+	let age_re = Regex::new(r#"age_(\d+)"#).unwrap();
+	for caps in age_re.captures_iter(&expression) {
+		let set_id = caps[1].parse::<DatasetId>()?;
+		fields.entry(set_id).or_default();
+	}
+	let re = Regex::new(r#"\b\d+_\d+"#).unwrap();
 	for ids_str in re.find_iter(&expression).map(|s| s.as_str()) {
 		dbg!(&ids_str);
 
@@ -236,6 +374,11 @@ fn parse_arguments(args: &str) -> Result<Arguments, Error> {
 		period,
 		message,
 		command,
+		email,
+		webhook,
+		secret,
+		name,
+		escalation,
 		fields,
 	})
 }
@@ -258,41 +401,219 @@ fn authorized(needed_fields: &HashMap<DatasetId, Vec<FieldId>>, user: &User) ->
 }
 
 async fn add(
-	chat_id: ChatId,
-	token: &str,
+	chat: &Address,
+	backends: &Backends,
 	args: &str,
 	user: User,
 	state: &DataRouterState,
 ) -> Result<(), botError> {
+	let arguments =
+		parse_arguments(args, &state.data.read().unwrap(), &user.timeseries_with_access)?;
+	let inv_expr = if arguments.counter_expr {
+		Some(get_inverse_expression(&arguments.expression, 0.05))
+	} else {
+		None
+	};
+	register(chat, arguments, inv_expr, user, state).await?;
+	send_text_reply(chat, backends, "alarm is set").await?;
+	Ok(())
+}
+
+/// sets an alarm that goes off if a dataset sends no data for a while, it
+/// re-enables (and notifies) once data comes in again
+async fn add_stale(
+	chat: &Address,
+	backends: &Backends,
+	args: &str,
+	user: User,
+	state: &DataRouterState,
+) -> Result<(), botError> {
+	let mut args = args.trim_start().splitn(3, ' ');
+	let set_arg = args
+		.next()
+		.filter(|a| !a.is_empty())
+		.ok_or(Error::NotEnoughArguments)?;
+	let set_id = set_arg
+		.parse::<DatasetId>()
+		.map_err(|_| Error::InvalidDatasetId(set_arg.to_owned()))?;
+	let duration_arg = args.next().ok_or(Error::NotEnoughArguments)?;
+	let duration_re = Regex::new(r#"^(\d+)([a-z])$"#).unwrap();
+	let caps = duration_re
+		.captures(duration_arg)
+		.ok_or_else(|| Error::IncorrectTimeUnit(duration_arg.to_owned()))?;
+	let max_age = parse_duration(&caps[1], &caps[2])?;
+	let options = args.next().unwrap_or_default();
+
+	let expression = format!("age_{} > {}", set_id, max_age.as_secs());
+	let inv_expr = format!("age_{} < {}", set_id, max_age.as_secs());
+	let args = format!("\"{}\" {}", expression, options);
+	let mut arguments =
+		parse_arguments(&args, &state.data.read().unwrap(), &user.timeseries_with_access)?;
+	//only the inverse expression should re-enable a staleness alarm
+	if !options.contains("-p ") {
+		arguments.period = None;
+	}
+	register(chat, arguments, Some(inv_expr), user, state).await?;
+
+	let text = format!(
+		"you will be notified if dataset {} sends no data for {:?}",
+		set_id, max_age
+	);
+	send_text_reply(chat, backends, text).await?;
+	Ok(())
+}
+
+/// number of firings shown by the history subcommand
+const HISTORY_LEN: usize = 20;
+
+async fn history(
+	chat: &Address,
+	backends: &Backends,
+	args: &str,
+	user: User,
+	state: &DataRouterState,
+) -> Result<(), botError> {
+	let target = args
+		.split_whitespace()
+		.next()
+		.ok_or(Error::NotEnoughArguments)?;
+	let alarm_id = state
+		.alarm_db
+		.find_history(user.id, target)
+		.map_err(Error::Lookup)?;
+
+	let records = state.alarm_db.history(user.id, alarm_id, HISTORY_LEN);
+	if records.is_empty() {
+		send_text_reply(chat, backends, "this alarm has not gone off yet").await?;
+		return Ok(());
+	}
+
+	let mut text = String::default();
+	for record in records {
+		let event = if record.inverted {
+			"re-enabled"
+		} else {
+			"fired"
+		};
+		let values: Vec<String> = record
+			.values
+			.iter()
+			.map(|(var, value)| format!("{}={}", var, value))
+			.collect();
+		let values = names::to_names(&values.join(", "), &state.data.read().unwrap());
+		text.push_str(&format!(
+			"{} {}: {}\n",
+			silence::format_time(&record.at, &user),
+			event,
+			values
+		));
+	}
+	send_text_reply(chat, backends, text).await?;
+	Ok(())
+}
+
+/// maximum number of moments listed in the reply to a test
+const MAX_LISTED_FIRINGS: usize = 40;
+
+async fn test(
+	chat: &Address,
+	backends: &Backends,
+	args: &str,
+	user: User,
+	state: &DataRouterState,
+) -> Result<(), botError> {
+	let duration_re = Regex::new(r#""\s+(\d+)([a-z])\b"#).unwrap();
+	let caps = duration_re.captures(args).ok_or(Error::NoTestDuration)?;
+	let duration = parse_duration(&caps[1], &caps[2])?;
+
+	let arguments =
+		parse_arguments(args, &state.data.read().unwrap(), &user.timeseries_with_access)?;
+	let inv_expr = if arguments.counter_expr {
+		Some(get_inverse_expression(&arguments.expression, 0.05))
+	} else {
+		None
+	};
+	let alarm = check_and_build(chat, arguments, inv_expr, &user, &state.webhooks.key())?.0;
+
+	let to = Utc::now();
+	let from = to - chrono::Duration::from_std(duration).unwrap();
+	let data = state.data.clone();
+	let user_id = user.id;
+	let firings = actix_threadpool::run(move || backtest(alarm, user_id, &data, from, to))
+		.await
+		.map_err(|e| match e {
+			actix_threadpool::BlockingError::Error(e) => e,
+			actix_threadpool::BlockingError::Canceled => {
+				panic!("error in actix_threadpool, execution was canceld")
+			}
+		})
+		.map_err(Error::from)?;
+
+	let text = format_firings(&firings, &user);
+	send_text_reply(chat, backends, text).await?;
+	Ok(())
+}
+
+fn format_firings(firings: &[Firing], user: &User) -> String {
+	let fired = firings.iter().filter(|f| !f.inverted).count();
+	if fired == 0 {
+		return "the alarm would not have gone off".to_owned();
+	}
+
+	let mut text = format!("the alarm would have gone off {} times:\n", fired);
+	for firing in firings.iter().take(MAX_LISTED_FIRINGS) {
+		let at = silence::format_time(&firing.at, user);
+		if firing.inverted {
+			text.push_str(&format!("{} re-enabled\n", at));
+		} else {
+			text.push_str(&format!("{} fired\n", at));
+		}
+	}
+	if firings.len() > MAX_LISTED_FIRINGS {
+		text.push_str("...");
+	}
+	text
+}
+
+/// checks access and the alarms syntax, returns the alarm and the sets it
+/// watches
+fn check_and_build(
+	chat: &Address,
+	arguments: Arguments,
+	inv_expr: Option<String>,
+	user: &User,
+	key: &webhook::SecretKey,
+) -> Result<(Alarm, Vec<DatasetId>), Error> {
 	let Arguments {
 		expression,
-		counter_expr,
+		counter_expr: _,
 		day,
 		period,
 		message,
 		command,
+		email,
+		webhook,
+		secret,
+		name: _,
+		escalation: _,
 		fields,
-	} = parse_arguments(args)?;
-	authorized(&fields, &user)?;
-	dbg!();
+	} = arguments;
+	authorized(&fields, user)?;
 
 	//this tests the alarm syntax
-	build_operator_tree(&expression).map_err(|e| {
+	let (rewritten, _) = windows::rewrite(&expression)?;
+	build_operator_tree(&rewritten).map_err(|e| {
 		error!("could not build operator tree: {}", e);
 		Error::from(e)
 	})?;
-	dbg!();
-	let tz_offset = user.timezone_offset;
+	let webhook = webhook
+		.map(|url| Webhook::new(&url, secret.as_deref(), key))
+		.transpose()?;
 	let notify = NotifyVia {
-		email: None,
-		telegram: Some(chat_id),
-	};
-	let inv_expr = if counter_expr {
-		Some(get_inverse_expression(&expression, 0.05))
-	} else {
-		None
+		email,
+		telegram: chat.telegram(),
+		webhook,
 	};
-	dbg!();
 	let alarm = Alarm {
 		expression,
 		inv_expr,
@@ -300,33 +621,120 @@ async fn add(
 		period,
 		message,
 		command,
-		tz_offset,
+		timezone: user.timezone,
 		notify,
 	};
-	dbg!();
+	Ok((alarm, fields.keys().copied().collect()))
+}
+
+async fn edit(
+	chat: &Address,
+	backends: &Backends,
+	args: &str,
+	user: User,
+	state: &DataRouterState,
+) -> Result<(), botError> {
+	let mut args = args.trim_start().splitn(2, ' ');
+	let target = args
+		.next()
+		.filter(|a| !a.is_empty())
+		.ok_or(Error::NotEnoughArguments)?;
 	let alarm_id = state
 		.alarm_db
-		.add(&alarm, user.id)
+		.find(user.id, target)
+		.map_err(Error::Lookup)?;
+
+	let args = args.next().unwrap_or_default();
+	let arguments =
+		parse_arguments(args, &state.data.read().unwrap(), &user.timeseries_with_access)?;
+	let inv_expr = if arguments.counter_expr {
+		Some(get_inverse_expression(&arguments.expression, 0.05))
+	} else {
+		None
+	};
+	let name = arguments.name.clone();
+	let chain = arguments
+		.escalation
+		.as_ref()
+		.map(|spec| escalation::parse_chain(spec, |name| state.db_lookup.by_name(name).ok()))
+		.transpose()?;
+	let (alarm, sets) = check_and_build(chat, arguments, inv_expr, &user, &state.webhooks.key())?;
+	if let Some(name) = name {
+		state
+			.alarm_db
+			.set_name(user.id, alarm_id, &name)
+			.map_err(Error::Lookup)?;
+	}
+	if let Some(chain) = chain {
+		state
+			.alarm_db
+			.set_escalation_chain(user.id, alarm_id, &chain)
+			.map_err(Error::from)?;
+	}
+	let old = state
+		.alarm_db
+		.replace(user.id, alarm_id, &alarm)
 		.map_err(Error::from)?;
+	state
+		.data_router_addr
+		.send(ReplaceAlarm {
+			alarm,
+			user_id: user.id,
+			alarm_id,
+			old_sets: old.watched_sets(),
+			sets,
+		})
+		.await
+		.unwrap();
+
+	send_text_reply(chat, backends, "alarm updated").await?;
+	Ok(())
+}
+
+/// checks access, stores the alarm and activates it
+async fn register(
+	chat: &Address,
+	arguments: Arguments,
+	inv_expr: Option<String>,
+	user: User,
+	state: &DataRouterState,
+) -> Result<(), Error> {
+	let name = arguments.name.clone();
+	if let Some(name) = &name {
+		if state.alarm_db.names(user.id).values().any(|n| n == name) {
+			return Err(Error::Lookup(AlarmDbError::NameTaken(name.to_owned())));
+		}
+	}
+	let chain = match &arguments.escalation {
+		Some(spec) => escalation::parse_chain(spec, |name| state.db_lookup.by_name(name).ok())?,
+		None => Vec::new(),
+	};
+	let (alarm, sets) = check_and_build(chat, arguments, inv_expr, &user, &state.webhooks.key())?;
+	let alarm_id = state.alarm_db.add(&alarm, user.id)?;
+	if let Some(name) = name {
+		state
+			.alarm_db
+			.set_name(user.id, alarm_id, &name)
+			.map_err(Error::Lookup)?;
+	}
+	state.alarm_db.set_escalation_chain(user.id, alarm_id, &chain)?;
 	state
 		.data_router_addr
 		.send(AddAlarm {
 			alarm,
 			user_id: user.id,
 			alarm_id,
-			sets: fields.keys().copied().collect(),
+			sets,
 		})
 		.await
 		.unwrap() // never seems to arrive
 		.map_err(|_| Error::TooManyAlarms)?;
-	dbg!();
-	send_text_reply(chat_id, token, "alarm is set").await?;
 	Ok(())
 }
 
 pub async fn handle(
-	chat_id: ChatId,
-	token: &str,
+	chat: &Address,
+	backends: &Backends,
 	text: String,
 	user: User,
 	state: &DataRouterState,
@@ -336,17 +744,37 @@ pub async fn handle(
 	let args = text.next().unwrap_or_default();
 
 	match subcommand {
-		"add" => add(chat_id, token, args, user, state).await,
-		"list" => list(chat_id, token, user, state).await,
-		"remove" => remove(chat_id, token, args, user, state).await,
+		"add" => add(chat, backends, args, user, state).await,
+		"stale" => add_stale(chat, backends, args, user, state).await,
+		"test" => test(chat, backends, args, user, state).await,
+		"snooze" => silence::snooze(chat, backends, args, user, state).await,
+		"unsnooze" => silence::unsnooze(chat, backends, args, user, state).await,
+		"mute" => silence::mute(chat, backends, args, user, state).await,
+		"unmute" => silence::unmute(chat, backends, user, state).await,
+		"maintenance" => silence::maintenance(chat, backends, args, user, state).await,
+		"list" => list(chat, backends, user, state).await,
+		"remove" => remove(chat, backends, args, user, state).await,
+		"edit" => edit(chat, backends, args, user, state).await,
+		"history" => history(chat, backends, args, user, state).await,
+		"ack" => escalation::ack(chat, backends, args, user, state).await,
 		_ => {
 			send_text_reply(
-				chat_id,
-				token,
+				chat,
+				backends,
 				format!(
 					"Could not recognise the \
-			subcommand, see documentation: \n{}\n{}\n{}",
-					HELP_LIST, HELP_ADD, HELP_REMOVE
+			subcommand, see documentation: \n{}\n{}\n{}\n{}\n{}\n{}\n{}\n{}\n{}\n{}\n{}",
+					HELP_LIST,
+					HELP_HISTORY,
+					HELP_ACK,
+					HELP_ADD,
+					HELP_EDIT,
+					HELP_STALE,
+					HELP_TEST,
+					HELP_REMOVE,
+					HELP_SNOOZE,
+					HELP_MUTE,
+					HELP_MAINTENANCE
 				),
 			)
 			.await
@@ -355,25 +783,58 @@ pub async fn handle(
 }
 
 async fn list(
-	chat_id: ChatId,
-	token: &str,
+	chat: &Address,
+	backends: &Backends,
 	user: User,
 	state: &DataRouterState,
 ) -> Result<(), botError> {
 	let entries = state.alarm_db.list_users_alarms(user.id);
 	if entries.is_empty() {
-		send_text_reply(chat_id, token, "I have no alarms for you").await?;
+		send_text_reply(chat, backends, "I have no alarms for you").await?;
 		return Ok(());
 	}
 
+	let now = chrono::Utc::now();
 	let mut list = String::default();
-	for (counter, alarm) in entries {
+	if let Some(until) = state.alarm_db.mute().filter(|until| *until > now) {
+		list.push_str(&format!(
+			"all alarms are muted until {}\n",
+			silence::format_time(&until, &user)
+		));
+	}
+	let snoozes: HashMap<Option<AlarmId>, _> = state
+		.alarm_db
+		.snoozes()
+		.filter(|(user_id, _, until)| *user_id == user.id && *until > now)
+		.map(|(_, alarm_id, until)| (alarm_id, until))
+		.collect();
+	if let Some(until) = snoozes.get(&None) {
+		list.push_str(&format!(
+			"all your alarms are snoozed until {}\n",
+			silence::format_time(until, &user)
+		));
+	}
+
+	let names = state.alarm_db.names(user.id);
+	for (alarm_id, alarm) in entries {
+		let id = match names.get(&alarm_id) {
+			Some(name) => format!("{} ({})", name, alarm_id),
+			None => alarm_id.to_string(),
+		};
+		let expression = names::to_names(&alarm.expression, &state.data.read().unwrap());
 		list.push_str(&format!(
 			"{}\texpr: {}\n",
-			counter,
-			format_time_human_readable(alarm.expression)
+			id,
+			format_time_human_readable(expression)
 		));
 
+		if let Some(until) = snoozes.get(&Some(alarm_id)) {
+			list.push_str(&format!(
+				"snoozed until: {}\n",
+				silence::format_time(until, &user)
+			));
+		}
+
 		if let Some(days) = alarm.weekday {
 			let valid_days: String = days
 				.iter()
@@ -382,6 +843,8 @@ async fn list(
 			list.push_str(&format!("valid on: [{}]\n", valid_days));
 		}
 
+		list.push_str(&format!("timezone: {}\n", alarm.timezone.name()));
+
 		if let Some(period) = alarm.period {
 			list.push_str(&format!("cooldown: {:?}\n", period)); //FIXME custom format funct
 		}
@@ -391,6 +854,7 @@ async fn list(
 		}
 
 		if let Some(inv) = alarm.inv_expr {
+			let inv = names::to_names(&inv, &state.data.read().unwrap());
 			list.push_str(&format!(
 				"reactivating if: {}\n",
 				format_time_human_readable(inv)
@@ -398,28 +862,30 @@ async fn list(
 		}
 	}
 	dbg!(&list);
-	send_text_reply(chat_id, token, list).await?;
+	send_text_reply(chat, backends, list).await?;
 	Ok(())
 }
 
 async fn remove(
-	chat_id: ChatId,
-	token: &str,
+	chat: &Address,
+	backends: &Backends,
 	args: &str,
 	user: User,
 	state: &DataRouterState,
 ) -> Result<(), botError> {
-	let numbs: Result<Vec<usize>, _> = args
+	let alarm_ids: Result<Vec<AlarmId>, _> = args
 		.split_whitespace()
-		.map(|s| s.parse().map_err(|_| Error::NotAnAlarmNumber(s.to_owned())))
+		.map(|s| state.alarm_db.find(user.id, s).map_err(Error::Lookup))
 		.collect();
-	let mut numbs = numbs?;
-	numbs.sort_unstable();
+	let alarm_ids = alarm_ids?;
+	if alarm_ids.is_empty() {
+		return Err(Error::NotEnoughArguments.into());
+	}
 
-	for numb in numbs.iter().rev() {
-		let (alarm, alarm_id) = state
+	for alarm_id in alarm_ids.iter().copied() {
+		let alarm = state
 			.alarm_db
-			.remove(user.id, *numb)
+			.remove(user.id, alarm_id)
 			.map_err(Error::from)?;
 		let sets = alarm.watched_sets();
 
@@ -434,10 +900,10 @@ async fn remove(
 			.unwrap();
 	}
 
-	if numbs.len() > 1 {
-		send_text_reply(chat_id, token, "alarms removed").await?;
+	if alarm_ids.len() > 1 {
+		send_text_reply(chat, backends, "alarms removed").await?;
 	} else {
-		send_text_reply(chat_id, token, "alarm removed").await?;
+		send_text_reply(chat, backends, "alarm removed").await?;
 	}
 	Ok(())
 }
@@ -485,6 +951,17 @@ fn get_inverse_expression(expression: &str, percentage: f32) -> String {
 mod tests {
 	use super::*;
 
+	#[test]
+	fn durations_are_bounded() {
+		assert_eq!(parse_duration("2", "h").unwrap(), Duration::from_secs(7200));
+		assert!(matches!(parse_duration("2", "y"), Err(Error::IncorrectTimeUnit(_))));
+		assert!(matches!(parse_duration("6000", "w"), Err(Error::DurationTooLong(_))));
+		assert!(matches!(
+			parse_duration("99999999999999999", "w"),
+			Err(Error::DurationTooLong(_))
+		));
+	}
+
 	#[test]
 	fn test_invert() {
 		let inverse = get_inverse_expression("13_11 > 21.53", 0.1);
@@ -0,0 +1,174 @@
+use chrono::Utc;
+use regex::Regex;
+use std::time::Duration;
+
+use crate::bot::backend::{Address, Backends};
+use crate::data_store::data_router::{
+	format_escalation_id, notify_users, parse_escalation_id, Acknowledged, DataRouterState,
+	EscalationStep,
+};
+use crate::database::{AlarmId, User, UserId};
+
+use super::super::super::send_text_reply;
+use super::super::super::Error as botError;
+use super::{parse_duration, Error};
+
+pub const HELP_ACK: &str = "/alarm ack [name|id]\n\
+	acknowledges an alarm that went off, stopping its escalation chain (see \
+	the -x option of add). Without argument acknowledges every escalating alarm \
+	you are part of. Alarms of others are acknowledged using the id given in \
+	the escalation message\n";
+
+/// longest time after an alarm went off an escalation step can be at
+const MAX_DELAY: Duration = Duration::from_secs(60 * 60 * 24 * 365);
+
+/// parses a chain such as: 15m alice,bob 1h carol. Times are counted from
+/// the moment the alarm went off, users are looked up by name
+pub fn parse_chain(
+	spec: &str,
+	lookup: impl Fn(&str) -> Option<UserId>,
+) -> Result<Vec<EscalationStep>, Error> {
+	let step_re = Regex::new(r#"^(\d+)([a-z])$"#).unwrap();
+	let mut args = spec.split_whitespace();
+	let mut chain = Vec::new();
+	while let Some(time) = args.next() {
+		let caps = step_re
+			.captures(time)
+			.ok_or_else(|| Error::InvalidEscalation(spec.to_owned()))?;
+		let after = parse_duration(&caps[1], &caps[2])?;
+		if after > MAX_DELAY {
+			return Err(Error::EscalationTooLate(time.to_owned()));
+		}
+		let users = args
+			.next()
+			.ok_or_else(|| Error::InvalidEscalation(spec.to_owned()))?
+			.split(',')
+			.filter(|name| !name.is_empty())
+			.map(|name| lookup(name).ok_or_else(|| Error::UnknownUser(name.to_owned())))
+			.collect::<Result<Vec<UserId>, Error>>()?;
+		chain.push(EscalationStep { after, users });
+	}
+	chain.sort_by_key(|step| step.after);
+	Ok(chain)
+}
+
+/// owner and id of the alarm to acknowledge, an alarm of someone else is
+/// given as <owner id>_<alarm id>
+fn parse_target(
+	arg: &str,
+	user: &User,
+	state: &DataRouterState,
+) -> Result<(UserId, AlarmId), Error> {
+	if let Some((owner, alarm_id)) = parse_escalation_id(arg) {
+		let chain = state.alarm_db.escalation_chain(owner, alarm_id)?;
+		let in_chain = chain.iter().any(|step| step.users.contains(&user.id));
+		if owner == user.id || in_chain {
+			return Ok((owner, alarm_id));
+		}
+	}
+	let alarm_id = state.alarm_db.find(user.id, arg).map_err(Error::Lookup)?;
+	Ok((user.id, alarm_id))
+}
+
+/// the escalating alarms the user owns or was notified about
+fn involved_in(user: &User, state: &DataRouterState) -> Result<Vec<(UserId, AlarmId)>, Error> {
+	let mut alarms = Vec::new();
+	for (owner, alarm_id, escalation) in state.alarm_db.escalations() {
+		if escalation.acknowledged.is_some() {
+			continue;
+		}
+		let chain = state.alarm_db.escalation_chain(owner, alarm_id)?;
+		if escalation.involved(owner, &chain).contains(&user.id) {
+			alarms.push((owner, alarm_id));
+		}
+	}
+	Ok(alarms)
+}
+
+pub async fn ack(
+	chat: &Address,
+	backends: &Backends,
+	args: &str,
+	user: User,
+	state: &DataRouterState,
+) -> Result<(), botError> {
+	let targets = match args.split_whitespace().next() {
+		Some(arg) => vec![parse_target(arg, &user, state)?],
+		None => involved_in(&user, state)?,
+	};
+	if targets.is_empty() {
+		send_text_reply(chat, backends, "there are no alarms to acknowledge").await?;
+		return Ok(());
+	}
+
+	for (owner, alarm_id) in targets {
+		let ack = Acknowledged {
+			by: user.id,
+			name: user.name.clone(),
+			at: Utc::now(),
+		};
+		let escalation = state
+			.alarm_db
+			.acknowledge(owner, alarm_id, ack)
+			.map_err(Error::Lookup)?;
+		let chain = state
+			.alarm_db
+			.escalation_chain(owner, alarm_id)
+			.map_err(Error::from)?;
+
+		let others = escalation
+			.involved(owner, &chain)
+			.into_iter()
+			.filter(|id| *id != user.id)
+			.collect();
+		let text = format!(
+			"{} acknowledged alarm {}: {}",
+			user.name,
+			format_escalation_id(owner, alarm_id),
+			escalation.text
+		);
+		notify_users(others, text, state);
+	}
+	send_text_reply(chat, backends, "acknowledged, the escalation is stopped").await?;
+	Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn lookup(name: &str) -> Option<UserId> {
+		match name {
+			"alice" => Some(1),
+			"bob" => Some(2),
+			"carol" => Some(3),
+			_ => None,
+		}
+	}
+
+	#[test]
+	fn chain() {
+		let chain = parse_chain("1h carol 15m alice,bob", lookup).unwrap();
+		assert_eq!(
+			chain,
+			vec![
+				EscalationStep {
+					after: Duration::from_secs(15 * 60),
+					users: vec![1, 2],
+				},
+				EscalationStep {
+					after: Duration::from_secs(60 * 60),
+					users: vec![3],
+				},
+			]
+		);
+		assert!(matches!(parse_chain("15m dave", lookup), Err(Error::UnknownUser(_))));
+		assert!(matches!(parse_chain("15m", lookup), Err(Error::InvalidEscalation(_))));
+		assert!(matches!(parse_chain("soon alice", lookup), Err(Error::InvalidEscalation(_))));
+		assert!(matches!(parse_chain("53w alice", lookup), Err(Error::EscalationTooLate(_))));
+		assert!(matches!(
+			parse_chain("99999999999999999w alice", lookup),
+			Err(Error::DurationTooLong(_))
+		));
+	}
+}
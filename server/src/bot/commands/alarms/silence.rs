@@ -0,0 +1,282 @@
+use chrono::{DateTime, NaiveDate, NaiveTime, TimeZone, Utc};
+use regex::Regex;
+
+use crate::bot::backend::{Address, Backends};
+use crate::data_store::data_router::{DataRouterState, Maintenance, SilencesChanged};
+use crate::data_store::{Authorisation, DatasetId};
+use crate::database::{AlarmId, User};
+
+use super::super::super::send_text_reply;
+use super::super::super::Error as botError;
+use super::{parse_duration, Error};
+
+pub const HELP_SNOOZE: &str = "/alarm snooze <name|id|all> <number><unit>\n\
+	silences an alarm, or with all every alarm you set, for the given time. \
+	The time unit can be s,m,h,d or w. Undo using: /alarm unsnooze <name|id|all>\n";
+pub const HELP_MUTE: &str = "/alarm mute <number><unit>\n\
+	(admins only) silences all alarms of everyone, undo using: /alarm unmute\n";
+pub const HELP_MAINTENANCE: &str =
+	"/alarm maintenance <dataset id> [yyyy-mm-dd] <hh:mm> <number><unit>\n\
+	no alarm watching the dataset will go off during the maintenance window \
+	starting at the given time (today if no date is given) lasting the given time. \
+	Only possible for datasets you own. Without arguments lists the planned \
+	maintenance for your datasets. Cancel using: /alarm maintenance cancel \
+	<dataset id> [[yyyy-mm-dd] <hh:mm>], without a time all maintenance planned \
+	for the dataset is cancelled\n";
+
+fn parse_length(arg: Option<&str>) -> Result<chrono::Duration, Error> {
+	let arg = arg.ok_or(Error::NotEnoughArguments)?;
+	let duration_re = Regex::new(r#"^(\d+)([a-z])$"#).unwrap();
+	let caps = duration_re
+		.captures(arg)
+		.ok_or_else(|| Error::IncorrectTimeUnit(arg.to_owned()))?;
+	let duration = parse_duration(&caps[1], &caps[2])?;
+	Ok(chrono::Duration::from_std(duration).unwrap())
+}
+
+fn parse_until(arg: Option<&str>) -> Result<DateTime<Utc>, Error> {
+	Ok(Utc::now() + parse_length(arg)?)
+}
+
+/// the alarm with the given name or id, None means all alarms of the user
+fn parse_target(
+	arg: Option<&str>,
+	user: &User,
+	state: &DataRouterState,
+) -> Result<Option<AlarmId>, Error> {
+	let arg = arg.ok_or(Error::NotEnoughArguments)?;
+	if arg == "all" {
+		return Ok(None);
+	}
+	let alarm_id = state.alarm_db.find(user.id, arg).map_err(Error::Lookup)?;
+	Ok(Some(alarm_id))
+}
+
+pub fn format_time(time: &DateTime<Utc>, user: &User) -> String {
+	time.with_timezone(&user.timezone)
+		.format("%a %d-%m %H:%M %Z")
+		.to_string()
+}
+
+pub async fn snooze(
+	chat: &Address,
+	backends: &Backends,
+	args: &str,
+	user: User,
+	state: &DataRouterState,
+) -> Result<(), botError> {
+	let mut args = args.split_whitespace();
+	let target = parse_target(args.next(), &user, state)?;
+	let until = parse_until(args.next())?;
+
+	state
+		.alarm_db
+		.snooze(user.id, target, Some(until))
+		.map_err(Error::from)?;
+	state.data_router_addr.send(SilencesChanged).await.unwrap();
+
+	let text = match target {
+		Some(_) => format!("alarm snoozed until {}", format_time(&until, &user)),
+		None => format!(
+			"all your alarms are snoozed until {}",
+			format_time(&until, &user)
+		),
+	};
+	send_text_reply(chat, backends, text).await
+}
+
+pub async fn unsnooze(
+	chat: &Address,
+	backends: &Backends,
+	args: &str,
+	user: User,
+	state: &DataRouterState,
+) -> Result<(), botError> {
+	let target = parse_target(args.split_whitespace().next(), &user, state)?;
+	state
+		.alarm_db
+		.snooze(user.id, target, None)
+		.map_err(Error::from)?;
+	state.data_router_addr.send(SilencesChanged).await.unwrap();
+	send_text_reply(chat, backends, "snooze removed").await
+}
+
+pub async fn mute(
+	chat: &Address,
+	backends: &Backends,
+	args: &str,
+	user: User,
+	state: &DataRouterState,
+) -> Result<(), botError> {
+	if !state.admins.contains(&user.id) {
+		return Err(Error::AdminOnly.into());
+	}
+	let until = parse_until(args.split_whitespace().next())?;
+	state.alarm_db.set_mute(Some(until)).map_err(Error::from)?;
+	state.data_router_addr.send(SilencesChanged).await.unwrap();
+
+	let text = format!("all alarms are muted until {}", format_time(&until, &user));
+	send_text_reply(chat, backends, text).await
+}
+
+pub async fn unmute(
+	chat: &Address,
+	backends: &Backends,
+	user: User,
+	state: &DataRouterState,
+) -> Result<(), botError> {
+	if !state.admins.contains(&user.id) {
+		return Err(Error::AdminOnly.into());
+	}
+	state.alarm_db.set_mute(None).map_err(Error::from)?;
+	state.data_router_addr.send(SilencesChanged).await.unwrap();
+	send_text_reply(chat, backends, "alarms are no longer muted").await
+}
+
+fn parse_start(date: Option<&str>, time: &str, user: &User) -> Result<DateTime<Utc>, Error> {
+	let timezone = user.timezone;
+	let time = NaiveTime::parse_from_str(time, "%H:%M")
+		.map_err(|_| Error::InvalidTime(time.to_owned()))?;
+	let date = match date {
+		Some(date) => NaiveDate::parse_from_str(date, "%Y-%m-%d")
+			.map_err(|_| Error::InvalidTime(date.to_owned()))?,
+		None => Utc::now().with_timezone(&timezone).date().naive_local(),
+	};
+	let start = timezone
+		.from_local_datetime(&date.and_time(time))
+		.single()
+		.ok_or_else(|| Error::InvalidTime(time.to_string()))?;
+	Ok(start.with_timezone(&Utc))
+}
+
+/// maintenance silences the alarms of everyone watching the dataset, thus
+/// it can only be planned by those owning every field of it
+fn owned_dataset(arg: &str, user: &User, state: &DataRouterState) -> Result<DatasetId, Error> {
+	let set_id = arg
+		.parse::<DatasetId>()
+		.map_err(|_| Error::InvalidDatasetId(arg.to_owned()))?;
+	let access = user
+		.timeseries_with_access
+		.get(&set_id)
+		.ok_or(Error::NoAccessToDataSet(set_id))?;
+	let n_fields = state
+		.data
+		.read()
+		.unwrap()
+		.sets
+		.get(&set_id)
+		.map(|set| set.metadata.fields.len())
+		.ok_or(Error::NoAccessToDataSet(set_id))?;
+
+	let owned = access
+		.iter()
+		.filter(|auth| matches!(auth, Authorisation::Owner(_)))
+		.count();
+	if owned < n_fields {
+		return Err(Error::NotDatasetOwner(set_id));
+	}
+	Ok(set_id)
+}
+
+pub async fn maintenance(
+	chat: &Address,
+	backends: &Backends,
+	args: &str,
+	user: User,
+	state: &DataRouterState,
+) -> Result<(), botError> {
+	let args: Vec<&str> = args.split_whitespace().collect();
+	if args.is_empty() {
+		return list_maintenance(chat, backends, user, state).await;
+	}
+	if args[0] == "cancel" {
+		return cancel_maintenance(chat, backends, &args[1..], user, state).await;
+	}
+
+	let set_id = owned_dataset(args[0], &user, state)?;
+	let (date, time, duration) = match args.len() {
+		3 => (None, args[1], args[2]),
+		4 => (Some(args[1]), args[2], args[3]),
+		_ => return Err(Error::NotEnoughArguments.into()),
+	};
+	let from = parse_start(date, time, &user)?;
+	let until = from + parse_length(Some(duration))?;
+
+	let window = Maintenance { from, until };
+	state
+		.alarm_db
+		.add_maintenance(set_id, &window)
+		.map_err(Error::from)?;
+	state.data_router_addr.send(SilencesChanged).await.unwrap();
+
+	let text = format!(
+		"planned maintenance for dataset {} from {} till {}",
+		set_id,
+		format_time(&from, &user),
+		format_time(&until, &user)
+	);
+	send_text_reply(chat, backends, text).await
+}
+
+async fn cancel_maintenance(
+	chat: &Address,
+	backends: &Backends,
+	args: &[&str],
+	user: User,
+	state: &DataRouterState,
+) -> Result<(), botError> {
+	let set_id = args.first().ok_or(Error::NotEnoughArguments)?;
+	let set_id = owned_dataset(set_id, &user, state)?;
+	let from = match args.len() {
+		1 => None,
+		2 => Some(parse_start(None, args[1], &user)?),
+		3 => Some(parse_start(Some(args[1]), args[2], &user)?),
+		_ => return Err(Error::NotEnoughArguments.into()),
+	};
+
+	let removed = state
+		.alarm_db
+		.remove_maintenance(set_id, from)
+		.map_err(Error::from)?;
+	if removed == 0 {
+		return Err(Error::NoMaintenance(set_id).into());
+	}
+	state.data_router_addr.send(SilencesChanged).await.unwrap();
+
+	let text = match from {
+		Some(from) => format!(
+			"cancelled the maintenance of dataset {} starting {}",
+			set_id,
+			format_time(&from, &user)
+		),
+		None => format!("cancelled all maintenance of dataset {}", set_id),
+	};
+	send_text_reply(chat, backends, text).await
+}
+
+async fn list_maintenance(
+	chat: &Address,
+	backends: &Backends,
+	user: User,
+	state: &DataRouterState,
+) -> Result<(), botError> {
+	let now = Utc::now();
+	let mut text = String::default();
+	for (set_id, window) in state
+		.alarm_db
+		.maintenance()
+		.filter(|(set_id, _)| user.timeseries_with_access.contains_key(set_id))
+		.filter(|(_, window)| window.until > now)
+	{
+		text.push_str(&format!(
+			"dataset {}: {} till {}\n",
+			set_id,
+			format_time(&window.from, &user),
+			format_time(&window.until, &user)
+		));
+	}
+	if text.is_empty() {
+		text.push_str("there is no maintenance planned for your datasets");
+	}
+	send_text_reply(chat, backends, text).await
+}
@@ -1,39 +1,176 @@
 pub const USAGE: &str = "/alias <name> <exiting command>";
-pub const DESCRIPTION: &str = "this defines an new command that can be used to call an existing command with or without arguments. Leave the <existing command> paramater empty to remove an existing alias";
+pub const DESCRIPTION: &str = "this defines an new command that can be used to call an existing command with or without arguments. Leave the <existing command> paramater empty to remove an existing alias. Use $1, $2.. in the command for the arguments the alias is called with or $@ for all of them, without these the arguments are added to the end. Separate commands with ; to run several, for example: /alias temp \"/plot 3_0 $1; /show 3_0\"";
+
+use std::collections::HashMap;
 
 use crate::data_store::data_router::DataRouterState;
 use crate::database::User;
 use log::error;
-use telegram_bot::types::refs::ChatId;
+use crate::bot::backend::{Address, Backends};
 use error_level::ErrorLevel;
 
 use super::super::send_text_reply;
 use super::super::Error as botError;
+use super::super::is_command;
+
+/// how many aliases deep an alias may call other aliases
+const MAX_DEPTH: usize = 8;
+/// most commands and aliases a single call may expand into
+const MAX_COMMANDS: usize = 64;
 
 #[derive(ErrorLevel, thiserror::Error, Debug)]
 pub enum Error {
     #[report(debug)]
 	#[error("not enough arguments")]
 	NotEnoughArguments,
+	#[report(debug)]
+	#[error("{0} is a command, it can not be used as alias")]
+	IsCommand(String),
+	#[report(debug)]
+	#[error("aliases call each other in a loop: {0}")]
+	Loop(String),
+	#[report(debug)]
+	#[error("aliases call each other more then {} deep: {0}", MAX_DEPTH)]
+	TooDeep(String),
+	#[report(debug)]
+	#[error("{0} expands to more then {} commands", MAX_COMMANDS)]
+	TooMany(String),
+	#[report(debug)]
+	#[error("alias {0} needs argument ${1}")]
+	MissingArgument(String, usize),
 	#[error("could not update database during setting of alias")]
 	DbError(crate::database::UserDbError),
 }
 
+/// the commands in an alias body, separated by ; outside of quotes
+fn split_commands(body: &str) -> Vec<&str> {
+	let mut commands = Vec::new();
+	let mut quoted = false;
+	let mut start = 0;
+	for (i, c) in body.char_indices() {
+		match c {
+			'"' => quoted = !quoted,
+			';' if !quoted => {
+				commands.push(&body[start..i]);
+				start = i + 1;
+			}
+			_ => (),
+		}
+	}
+	commands.push(&body[start..]);
+	commands
+}
+
+/// fills in $1..$9 and $@ with the arguments, if the alias has no
+/// placeholders the arguments are appended
+fn substitute(name: &str, body: &str, args: &[&str]) -> Result<String, Error> {
+	let mut out = String::with_capacity(body.len());
+	let mut used_args = false;
+	let mut chars = body.chars().peekable();
+	while let Some(c) = chars.next() {
+		if c != '$' {
+			out.push(c);
+			continue;
+		}
+		match chars.peek().copied() {
+			Some('@') => {
+				chars.next();
+				used_args = true;
+				out.push_str(&args.join(" "));
+			}
+			Some(n @ '1'..='9') => {
+				chars.next();
+				used_args = true;
+				let n = n.to_digit(10).unwrap() as usize;
+				let arg = args
+					.get(n - 1)
+					.ok_or_else(|| Error::MissingArgument(name.to_owned(), n))?;
+				out.push_str(arg);
+			}
+			_ => out.push(c),
+		}
+	}
+
+	if !used_args && !args.is_empty() {
+		out.push(' ');
+		out.push_str(&args.join(" "));
+	}
+	Ok(out)
+}
+
+fn expand_into(
+	text: &str,
+	aliases: &HashMap<String, String>,
+	chain: &mut Vec<String>,
+	commands: &mut Vec<String>,
+	expanded: &mut usize,
+) -> Result<(), Error> {
+	let mut words = text.split_whitespace();
+	let name = match words.next() {
+		Some(name) => name,
+		None => return Ok(()),
+	};
+	*expanded += 1;
+	if *expanded > MAX_COMMANDS {
+		let called = chain.first().map(String::as_str).unwrap_or(name);
+		return Err(Error::TooMany(called.to_owned()));
+	}
+	let body = match aliases.get(name) {
+		Some(body) if !is_command(name) => body,
+		_ => {
+			commands.push(text.trim().to_owned());
+			return Ok(());
+		}
+	};
+
+	chain.push(name.to_owned());
+	if chain[..chain.len() - 1].iter().any(|called| called == name) {
+		return Err(Error::Loop(chain.join(" -> ")));
+	}
+	if chain.len() > MAX_DEPTH {
+		return Err(Error::TooDeep(chain.join(" -> ")));
+	}
+
+	let args: Vec<&str> = words.collect();
+	let body = substitute(name, body, &args)?;
+	for command in split_commands(&body) {
+		expand_into(command, aliases, chain, commands, expanded)?;
+	}
+	chain.pop();
+	Ok(())
+}
+
+/// the commands text stands for after replacing all aliases, aliases may
+/// call other aliases but not in a loop
+pub fn expand(text: &str, aliases: &HashMap<String, String>) -> Result<Vec<String>, Error> {
+	let mut commands = Vec::new();
+	expand_into(text, aliases, &mut Vec::new(), &mut commands, &mut 0)?;
+	Ok(commands)
+}
+
 pub async fn send(
-	chat_id: ChatId,
+	chat: &Address,
 	state: &DataRouterState,
-	token: &str,
+	backends: &Backends,
 	args: String,
 	mut user: User,
 ) -> Result<(), botError> {
-	let mut args = args.split_whitespace();
-	let alias_name = args.next().ok_or(Error::NotEnoughArguments)?.to_owned();
-
-	let mut command = String::default();
-	args.for_each(|arg| {
-		command.push_str(arg);
-		command.push(' ')
-	});
+	let args = args.trim();
+	let split = args.find(char::is_whitespace).unwrap_or_else(|| args.len());
+	let (alias_name, command) = args.split_at(split);
+	if alias_name.is_empty() {
+		return Err(Error::NotEnoughArguments.into());
+	}
+	if is_command(alias_name) {
+		return Err(Error::IsCommand(alias_name.to_owned()).into());
+	}
+	let alias_name = alias_name.to_owned();
+	let command = command.trim();
+	let command = command
+		.strip_prefix('"')
+		.and_then(|c| c.strip_suffix('"'))
+		.unwrap_or(command)
+		.to_owned();
 
 	let mut text = String::default();
 	if command.is_empty() {
@@ -63,6 +200,82 @@ pub async fn send(
 			.map_err(Error::DbError)?;
 	}
 
-	send_text_reply(chat_id, token, text).await?;
+	send_text_reply(chat, backends, text).await?;
 	Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn aliases(list: &[(&str, &str)]) -> HashMap<String, String> {
+		list.iter()
+			.map(|(name, body)| (name.to_string(), body.to_string()))
+			.collect()
+	}
+
+	#[test]
+	fn parameters_and_chains() {
+		let aliases = aliases(&[
+			("temp", "/plot 3_0 $1; /show 3_0"),
+			("t", "temp"),
+			("all", "/plot $@"),
+		]);
+		assert_eq!(
+			expand("t 24h", &aliases).unwrap(),
+			vec!["/plot 3_0 24h", "/show 3_0"]
+		);
+		assert_eq!(expand("all 3_0 3_1", &aliases).unwrap(), vec!["/plot 3_0 3_1"]);
+		assert!(matches!(
+			expand("temp", &aliases),
+			Err(Error::MissingArgument(_, 1))
+		));
+	}
+
+	#[test]
+	fn split_outside_quotes() {
+		let aliases = aliases(&[(
+			"warm",
+			"/alarm add \"3_0 > 20\" -m \"warm; open a window\"; /show 3_0",
+		)]);
+		assert_eq!(
+			expand("warm", &aliases).unwrap(),
+			vec![
+				"/alarm add \"3_0 > 20\" -m \"warm; open a window\"",
+				"/show 3_0"
+			]
+		);
+	}
+
+	#[test]
+	fn loops_are_detected() {
+		let aliases = aliases(&[("a", "b"), ("b", "/show 3_0; a"), ("/plot", "a")]);
+		assert!(matches!(expand("a", &aliases), Err(Error::Loop(chain)) if chain == "a -> b -> a"));
+		// commands are never replaced by aliases
+		assert_eq!(expand("/plot 3_0", &aliases).unwrap(), vec!["/plot 3_0"]);
+
+		let deep: Vec<_> = (0..MAX_DEPTH + 1)
+			.map(|i| (format!("a{}", i), format!("a{}", i + 1)))
+			.collect();
+		let deep: HashMap<_, _> = deep.into_iter().collect();
+		assert!(matches!(expand("a0", &deep), Err(Error::TooDeep(_))));
+	}
+
+	#[test]
+	fn expansion_is_bounded() {
+		// just within the depth limit, expands to 8^7 commands
+		let leaf = MAX_DEPTH - 1;
+		let fan_out: Vec<_> = (0..leaf)
+			.map(|i| (format!("a{}", i), vec![format!("a{}", i + 1); 8].join(";")))
+			.chain(std::iter::once((
+				format!("a{}", leaf),
+				String::from("/show 3_0"),
+			)))
+			.collect();
+		let fan_out: HashMap<_, _> = fan_out.into_iter().collect();
+		assert!(matches!(expand("a0", &fan_out), Err(Error::TooMany(name)) if name == "a0"));
+
+		let aliases = aliases(&[("b", "/show 3_0; /show 3_1"), ("a", "b; b; b")]);
+		assert_eq!(expand("a", &aliases).unwrap().len(), 6);
+	}
+}
@@ -1,33 +1,42 @@
 use crate::bot::Error;
 use crate::database::User;
-use telegram_bot::types::refs::ChatId;
+use crate::bot::backend::{Address, Backends};
 
 use super::super::send_text_reply;
-use super::{alarms, alias, keyboard, plotables, show};
+use super::{alarms, alias, incidents, keyboard, link, plotables, reports, share, show, status, timezone};
 
 use super::plot;
 
 const USAGE: &str = "/help";
 const DESCRIPTION: &str = "shows this list";
-pub async fn send(chat_id: ChatId, user_info: &User, token: &str) -> Result<(), Error> {
+pub async fn send(chat: &Address, user_info: &User, backends: &Backends) -> Result<(), Error> {
 	let aliasses = &user_info.aliases;
 
-	let mut text = format!("{}\n\t{}\n\t{}\n\t{}\n\t{}\n\t{}\n\t{}\n\t{}\n{}\n\t{}\n{}\n\t{}\n{}\n\t{}\n{}\n\t{}\n{}\n\t{}\n",
+	let mut text = format!("{}\n\t{}\n\t{}\n\t{}\n\t{}\n\t{}\n\t{}\n\t{}\n{}\n\t{}\n{}\n\t{}\n{}\n\t{}\n{}\n\t{}\n{}\n\t{}\n{}\n\t{}\n{}\n\t{}\n{}\n\t{}\n{}\n\t{}\n{}\n\t{}\n{}\n\t{}\n{}\n\t{}\n{}\n\t{}\n{}\n\t{}\n",
 		USAGE, DESCRIPTION,
 		plot::USAGE, plot::DESCRIPTION,
 		plotables::USAGE, plotables::DESCRIPTION,
 		show::USAGE, show::DESCRIPTION,
+		status::USAGE, status::DESCRIPTION,
 		alias::USAGE, alias::DESCRIPTION,
 		keyboard::USAGE_SHOW, keyboard::DESCRIPTION_SHOW,
 		keyboard::USAGE_ADD, keyboard::DESCRIPTION_ADD,
 		keyboard::USAGE_REMOVE, keyboard::DESCRIPTION_REMOVE,
 		alarms::USAGE, alarms::DESCRIPTION,
+		incidents::USAGE_ACK, incidents::DESCRIPTION_ACK,
+		incidents::USAGE_ERRORS, incidents::DESCRIPTION_ERRORS,
+		timezone::USAGE, timezone::DESCRIPTION,
+		reports::USAGE, reports::DESCRIPTION,
+		link::USAGE_LINK, link::DESCRIPTION_LINK,
+		link::USAGE_UNLINK, link::DESCRIPTION_UNLINK,
+		share::USAGE_SHARE, share::DESCRIPTION_SHARE,
+		share::USAGE_UNSHARE, share::DESCRIPTION_UNSHARE,
 		);
 
 	text.push_str("\nconfigured aliasses:\n");
 	for (alias, alias_expanded) in aliasses.iter() {
 		text.push_str(&format!(" {}: {}\n", alias, alias_expanded));
 	}
-	send_text_reply(chat_id, token, text).await?;
+	send_text_reply(chat, backends, text).await?;
 	Ok(())
 }
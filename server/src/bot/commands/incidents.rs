@@ -0,0 +1,240 @@
+pub const USAGE_ACK: &str = "/ack <incident id>";
+pub const DESCRIPTION_ACK: &str = "claim an incident, others subscribed to it will no longer be notified";
+pub const USAGE_ERRORS: &str = "/errors [resolve <incident id>|subscribe <dataset id> [email address] [webhook url [secret]]|unsubscribe <dataset id>]";
+pub const DESCRIPTION_ERRORS: &str = "lists open incidents for your datasets, resolve them or \
+ (un)subscribe from notifications about a dataset's errors";
+
+use error_level::ErrorLevel;
+
+use crate::bot::backend::{Address, Backends};
+use crate::data_store::data_router::DataRouterState;
+use crate::data_store::error_router::{self, incidents, ErrorSpecificKey, NotifyOptions};
+use crate::data_store::DatasetId;
+use crate::database::User;
+use crate::notify::webhook::{self, Webhook};
+
+use super::super::send_text_reply;
+use super::super::Error as botError;
+
+#[derive(ErrorLevel, thiserror::Error, Debug)]
+pub enum Error {
+	#[report(debug)]
+	#[error("This \"{0}\" is not a valid incident id, it should look like: 3_1_20")]
+	InvalidIncidentId(String),
+	#[report(debug)]
+	#[error("This \"{0}\" is not a valid dataset id")]
+	InvalidDatasetId(String),
+	#[report(debug)]
+	#[error("You do not have access to dataset: {0}")]
+	NoAccessToDataSet(DatasetId),
+	#[report(debug)]
+	#[error("You do not have access to incident: {0}")]
+	NoAccessToIncident(String),
+	#[report(debug)]
+	#[error("Not enough arguments\nuse: {}", USAGE_ACK)]
+	NotEnoughArguments,
+	#[error("{0}")]
+	Incident(#[from] incidents::Error),
+	#[report(error)]
+	#[error("Could not update notification settings")]
+	Subscribe(crate::error::DataserverError),
+	#[report(debug)]
+	#[error("I can not use this webhook: {0}")]
+	Webhook(#[from] webhook::Error),
+}
+
+fn may_access(key: ErrorSpecificKey, user: &User, state: &DataRouterState) -> bool {
+	let admin = state.admins.contains(&user.id);
+	incidents::may_access(key, &user.timeseries_with_access, admin)
+}
+
+/// an optional email address followed by an optional webhook url and the
+/// secret to sign its payload with
+fn parse_channels<'a>(
+	args: impl Iterator<Item = &'a str>,
+	key: &webhook::SecretKey,
+) -> Result<(Option<String>, Option<Webhook>), Error> {
+	let mut email = None;
+	let mut url = None;
+	let mut secret = None;
+	for arg in args {
+		if arg.starts_with("http://") || arg.starts_with("https://") {
+			url = Some(arg);
+		} else if url.is_some() {
+			secret = Some(arg);
+		} else {
+			email = Some(arg.to_owned());
+		}
+	}
+	let webhook = url.map(|url| Webhook::new(url, secret, key)).transpose()?;
+	Ok((email, webhook))
+}
+
+fn parse_incident_id(
+	arg: &str,
+	user: &User,
+	state: &DataRouterState,
+) -> Result<ErrorSpecificKey, Error> {
+	let key =
+		incidents::parse_id(arg).ok_or_else(|| Error::InvalidIncidentId(arg.to_owned()))?;
+	if !may_access(key, user, state) {
+		return Err(Error::NoAccessToIncident(arg.to_owned()));
+	}
+	Ok(key)
+}
+
+pub async fn ack(
+	chat: &Address,
+	backends: &Backends,
+	args: String,
+	user: &User,
+	state: &DataRouterState,
+) -> Result<(), botError> {
+	let arg = args.split_whitespace().next().ok_or(Error::NotEnoughArguments)?;
+	let key = parse_incident_id(arg, user, state)?;
+
+	state
+		.error_router_addr
+		.send(error_router::AckIncident {
+			key,
+			user_id: user.id,
+			user_name: user.name.clone(),
+		})
+		.await
+		.unwrap()
+		.map_err(Error::from)?;
+
+	let text = format!("you claimed incident {}", incidents::format_id(key));
+	send_text_reply(chat, backends, text).await?;
+	Ok(())
+}
+
+pub async fn handle(
+	chat: &Address,
+	backends: &Backends,
+	args: String,
+	user: &User,
+	state: &DataRouterState,
+) -> Result<(), botError> {
+	let mut args = args.split_whitespace();
+	match args.next() {
+		None => list(chat, backends, user, state).await,
+		Some("resolve") => {
+			let arg = args.next().ok_or(Error::NotEnoughArguments)?;
+			let key = parse_incident_id(arg, user, state)?;
+			state
+				.error_router_addr
+				.send(error_router::ResolveIncident { key })
+				.await
+				.unwrap()
+				.map_err(Error::from)?;
+			let text = format!("incident {} resolved", incidents::format_id(key));
+			send_text_reply(chat, backends, text).await
+		}
+		Some("subscribe") => {
+			let set_id = parse_dataset_id(args.next(), user)?;
+			let (email, webhook) = parse_channels(args, state.webhooks.key())?;
+			let options = NotifyOptions {
+				user_id: user.id,
+				email,
+				chat: Some(chat.clone()),
+				webhook,
+			};
+			state
+				.error_router_addr
+				.send(error_router::Subscribe {
+					keys: subscription_keys(set_id, user),
+					options,
+				})
+				.await
+				.unwrap()
+				.map_err(Error::Subscribe)?;
+			let text = format!("you will be notified about errors in dataset {}", set_id);
+			send_text_reply(chat, backends, text).await
+		}
+		Some("unsubscribe") => {
+			let set_id = parse_dataset_id(args.next(), user)?;
+			state
+				.error_router_addr
+				.send(error_router::Unsubscribe {
+					keys: subscription_keys(set_id, user),
+					user_id: user.id,
+				})
+				.await
+				.unwrap()
+				.map_err(Error::Subscribe)?;
+			let text = format!(
+				"you will no longer be notified about errors in dataset {}",
+				set_id
+			);
+			send_text_reply(chat, backends, text).await
+		}
+		Some(_) => {
+			send_text_reply(
+				chat,
+				backends,
+				format!(
+					"Could not recognise the subcommand, use: {}\n{}",
+					USAGE_ERRORS, USAGE_ACK
+				),
+			)
+			.await
+		}
+	}
+}
+
+fn parse_dataset_id(arg: Option<&str>, user: &User) -> Result<DatasetId, Error> {
+	let arg = arg.ok_or(Error::NotEnoughArguments)?;
+	let set_id = arg
+		.parse::<DatasetId>()
+		.map_err(|_| Error::InvalidDatasetId(arg.to_owned()))?;
+	if !user.timeseries_with_access.contains_key(&set_id) {
+		return Err(Error::NoAccessToDataSet(set_id));
+	}
+	Ok(set_id)
+}
+
+/// a subscription covers every field the user can access and the errors
+/// concerning the dataset as a whole
+fn subscription_keys(set_id: DatasetId, user: &User) -> Vec<error_router::FieldSpecificKey> {
+	user.timeseries_with_access
+		.get(&set_id)
+		.into_iter()
+		.flatten()
+		.map(|auth| *auth.as_ref())
+		.chain(std::iter::once(u8::max_value()))
+		.map(|field_id| error_router::to_field_specific_key(set_id, field_id))
+		.collect()
+}
+
+async fn list(
+	chat: &Address,
+	backends: &Backends,
+	user: &User,
+	state: &DataRouterState,
+) -> Result<(), botError> {
+	let incidents = state
+		.error_router_addr
+		.send(error_router::ListIncidents {
+			access: user.timeseries_with_access.clone(),
+			admin: state.admins.contains(&user.id),
+			include_resolved: false,
+		})
+		.await
+		.unwrap();
+
+	if incidents.is_empty() {
+		send_text_reply(chat, backends, "there are no open incidents").await?;
+		return Ok(());
+	}
+
+	let mut text = String::default();
+	for info in incidents {
+		text.push_str(&format!(
+			"{} [{}, seen {} times]\n\t{}\n",
+			info.id, info.incident.state, info.incident.occurrences, info.description
+		));
+	}
+	send_text_reply(chat, backends, text).await?;
+	Ok(())
+}
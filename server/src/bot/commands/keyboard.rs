@@ -13,7 +13,7 @@ use error_level::ErrorLevel;
 use crate::data_store::data_router::DataRouterState;
 use crate::database::User;
 use log::error;
-use telegram_bot::types::refs::ChatId;
+use crate::bot::backend::{Address, Backends};
 
 use crate::bot::Error as botError;
 
@@ -34,11 +34,11 @@ pub enum Error {
 	Db(crate::database::UserDbError),
 }
 
-pub async fn show(chat_id: ChatId, token: &str, user: User) -> Result<(), botError> {
-	reload(chat_id, token, user, "showing the user keyboard").await
+pub async fn show(chat: &Address, backends: &Backends, user: User) -> Result<(), botError> {
+	reload(chat, backends, user, "showing the user keyboard").await
 }
 
-async fn reload(chat_id: ChatId, token: &str, user: User, text: &str) -> Result<(), botError> {
+async fn reload(chat: &Address, backends: &Backends, user: User, text: &str) -> Result<(), botError> {
 	let keyboard_json = user.keyboard.ok_or(Error::NoKeyboardSet)?;
 	let reply_markup = format!(
 		"{{\"keyboard\":{},\"resize_keyboard\": true}}",
@@ -46,29 +46,22 @@ async fn reload(chat_id: ChatId, token: &str, user: User, text: &str) -> Result<
 	);
 
 	dbg!(&reply_markup);
-	let url = format!("https://api.telegram.org/bot{}/sendMessage", token);
+	let (chat_id, telegram) = backends.telegram_chat(chat)?;
 	let form = reqwest::multipart::Form::new()
 		.text("chat_id", chat_id.to_string())
 		.text("text", String::from(text))
 		.text("reply_markup", reply_markup);
 
-	let client = reqwest::Client::new();
-	let resp = client.post(&url).multipart(form).send().await?;
-
-	dbg!(&resp);
-	if resp.status() != reqwest::StatusCode::OK {
-		Err(botError::InvalidServerResponse(resp))
-	} else {
-		Ok(())
-	}
+	telegram.call("sendMessage", form).await?;
+	Ok(())
 }
 
 //replykeyboardmarkup
 type Keyboard = Vec<Vec<String>>;
 pub async fn add_button(
-	chat_id: ChatId,
+	chat: &Address,
 	state: &DataRouterState,
-	token: &str,
+	backends: &Backends,
 	args: String,
 	mut user: User,
 ) -> Result<(), botError> {
@@ -112,14 +105,14 @@ pub async fn add_button(
 		.map_err(Error::Db)?;
 
 	//update users keyboard
-	reload(chat_id, token, user, "updated keyboard").await?;
+	reload(chat, backends, user, "updated keyboard").await?;
 	Ok(())
 }
 
 pub async fn remove_button(
-	chat_id: ChatId,
+	chat: &Address,
 	state: &DataRouterState,
-	token: &str,
+	backends: &Backends,
 	args: String,
 	mut user: User,
 ) -> Result<(), botError> {
@@ -153,6 +146,6 @@ pub async fn remove_button(
 		.map_err(Error::Db)?;
 
 	//update users keyboard
-	reload(chat_id, token, user, "updated keyboard").await?;
+	reload(chat, backends, user, "updated keyboard").await?;
 	Ok(())
 }
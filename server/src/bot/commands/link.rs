@@ -0,0 +1,318 @@
+pub const USAGE_LINK: &str = "/link [<code>|matrix <room id>|ntfy <topic>]";
+pub const DESCRIPTION_LINK: &str = "with a code from the settings page links the \
+ telegram or matrix account you send it from to your account. Otherwise receive \
+ alarms in a matrix room, or as ntfy push notifications. A code is send there, \
+ send it back with /link <code> to finish linking. Without arguments lists the \
+ linked chats and accounts";
+pub const USAGE_UNLINK: &str = "/unlink [matrix <room id>|ntfy <topic>]";
+pub const DESCRIPTION_UNLINK: &str = "stops using a linked chat, without arguments \
+ unlinks your account on the service you send it from, such as matrix";
+
+use chrono::{DateTime, Duration, Utc};
+use error_level::ErrorLevel;
+use rand::Rng;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use telegram_bot::types::refs::UserId as TelegramUserId;
+
+use crate::bot::backend::{Address, Backends};
+use crate::data_store::data_router::DataRouterState;
+use crate::database::{LinkDbError, User, UserDbError, UserId};
+
+use super::super::Error as botError;
+use super::super::{send_text_reply, telegram_sender, user_for};
+
+#[derive(ErrorLevel, thiserror::Error, Debug)]
+pub enum Error {
+	#[report(debug)]
+	#[error("Not enough arguments\nuse: {}", USAGE_LINK)]
+	NotEnoughArguments,
+	#[report(debug)]
+	#[error("\"{0} {1}\" is not a matrix room id (!room:server) or ntfy topic")]
+	InvalidAddress(String, String),
+	#[report(debug)]
+	#[error("unknown or expired code, generate a new one on the settings page")]
+	InvalidCode,
+	#[report(debug)]
+	#[error("unknown or expired code, send /link {0} again for a new one")]
+	InvalidTargetCode(Address),
+	#[report(debug)]
+	#[error("this telegram account is already linked to another account")]
+	TelegramTaken,
+	#[error("{0}")]
+	Db(#[from] LinkDbError),
+	#[error("could not link telegram account: {0}")]
+	UserDb(#[from] UserDbError),
+}
+
+const CODE_LEN: usize = 6;
+/// no 0/O or 1/I so codes are easy to type over
+const CODE_CHARS: &[u8] = b"ABCDEFGHJKLMNPQRSTUVWXYZ23456789";
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct PendingLink {
+	pub user_id: UserId,
+	/// chat the code was send to, None for codes from the settings page that
+	/// link the account the code is send from
+	pub target: Option<Address>,
+}
+
+/// one time codes that link an account or chat once send to the bot, they
+/// are kept in memory only
+#[derive(Clone, Default)]
+pub struct LinkCodes {
+	codes: Arc<Mutex<HashMap<String, (PendingLink, DateTime<Utc>)>>>,
+}
+
+impl LinkCodes {
+	pub const VALID_FOR: i64 = 10; // minutes
+
+	/// code a logged in web user can send to the bot to link their account
+	pub fn generate(&self, user_id: UserId, now: DateTime<Utc>) -> String {
+		self.insert(
+			PendingLink {
+				user_id,
+				target: None,
+			},
+			now,
+		)
+	}
+
+	/// code that links the target chat once it is send back by the user
+	pub fn generate_for(&self, user_id: UserId, target: Address, now: DateTime<Utc>) -> String {
+		let target = Some(target);
+		self.insert(PendingLink { user_id, target }, now)
+	}
+
+	fn insert(&self, pending: PendingLink, now: DateTime<Utc>) -> String {
+		let mut rng = rand::thread_rng();
+		let mut codes = self.codes.lock().unwrap();
+		codes.retain(|_, (_, expires)| *expires > now);
+		let code = loop {
+			let code: String = (0..CODE_LEN)
+				.map(|_| CODE_CHARS[rng.gen_range(0, CODE_CHARS.len())] as char)
+				.collect();
+			if !codes.contains_key(&code) {
+				break code;
+			}
+		};
+		let expires = now + Duration::minutes(Self::VALID_FOR);
+		codes.insert(code.clone(), (pending, expires));
+		code
+	}
+
+	/// what the code links, a code can be used once
+	pub fn redeem(&self, code: &str, now: DateTime<Utc>) -> Option<PendingLink> {
+		let code = code.to_uppercase();
+		let mut codes = self.codes.lock().unwrap();
+		match codes.remove(&code) {
+			Some((pending, expires)) if expires > now => Some(pending),
+			_ => None,
+		}
+	}
+}
+
+/// the code if the text is /link <code>, linking a matrix room or ntfy
+/// topic takes two arguments
+pub fn code(text: &str) -> Option<&str> {
+	let mut parts = text.split_whitespace();
+	if parts.next()? != "/link" {
+		return None;
+	}
+	let code = parts.next()?;
+	if parts.next().is_some() {
+		return None;
+	}
+	Some(code)
+}
+
+/// finishes linking, a code from the settings page links the senders
+/// account which does not need to be known yet. A code send to a chat links
+/// that chat if the sender is the user that asked for it.
+pub async fn redeem(
+	code: &str,
+	chat: &Address,
+	sender: &str,
+	state: &DataRouterState,
+) -> Result<(), botError> {
+	let pending = state
+		.link_codes
+		.redeem(code, Utc::now())
+		.ok_or(Error::InvalidCode)?;
+	match pending.target {
+		None if chat.telegram().is_some() => {
+			let sender = telegram_sender(chat, sender)?;
+			link_telegram(pending.user_id, chat, sender, state).await
+		}
+		None => {
+			state
+				.link_db
+				.link_sender(pending.user_id, chat, sender)
+				.map_err(Error::Db)?;
+			let user = state
+				.user_db
+				.get_user(pending.user_id)
+				.map_err(Error::UserDb)?;
+			let text = format!(
+				"this {} account is now linked to {}",
+				chat.backend(),
+				user.name
+			);
+			send_text_reply(chat, &state.backends, text).await
+		}
+		Some(target) => {
+			let user = user_for(chat, sender, state)?;
+			if user.id != pending.user_id {
+				return Err(Error::InvalidTargetCode(target).into());
+			}
+			state
+				.link_db
+				.link(user.id, target.clone())
+				.map_err(Error::Db)?;
+			let text = format!("this chat is now linked to the account of {}", user.name);
+			send_text_reply(&target, &state.backends, text).await?;
+			send_text_reply(chat, &state.backends, format!("linked {}", target)).await
+		}
+	}
+}
+
+async fn link_telegram(
+	user_id: UserId,
+	chat: &Address,
+	sender: TelegramUserId,
+	state: &DataRouterState,
+) -> Result<(), botError> {
+	match state.db_lookup.by_telegram_id(&sender) {
+		Ok(owner) if owner == user_id => {
+			let text = "this telegram account is already linked to your account";
+			return send_text_reply(chat, &state.backends, text).await;
+		}
+		Ok(_) => return Err(Error::TelegramTaken.into()),
+		Err(_) => (),
+	}
+
+	let old = state.user_db.get_user(user_id).map_err(Error::UserDb)?;
+	let mut user = old.clone();
+	user.telegram_id = Some(sender);
+	state
+		.user_db
+		.set_user(user.clone())
+		.await
+		.map_err(Error::UserDb)?;
+	state.db_lookup.update(&old, &user);
+
+	let text = format!("this telegram account is now linked to {}", user.name);
+	send_text_reply(chat, &state.backends, text).await
+}
+
+fn parse_address(args: &str) -> Result<Option<Address>, Error> {
+	let mut args = args.split_whitespace();
+	let backend = match args.next() {
+		Some(backend) => backend,
+		None => return Ok(None),
+	};
+	let id = args.next().ok_or(Error::NotEnoughArguments)?;
+	Address::parse(backend, id)
+		.map(Some)
+		.ok_or_else(|| Error::InvalidAddress(backend.to_owned(), id.to_owned()))
+}
+
+/// sends a code to the chat to link, it is linked once the code is send back
+pub async fn link(
+	chat: &Address,
+	backends: &Backends,
+	args: String,
+	user: &User,
+	state: &DataRouterState,
+) -> Result<(), botError> {
+	let address = match parse_address(&args)? {
+		Some(address) => address,
+		None => {
+			let mut text = String::from("linked chats:\n");
+			for address in state.link_db.links(user.id) {
+				text.push_str(&format!("- {}\n", address));
+			}
+			text.push_str("linked accounts:\n");
+			for account in state.link_db.senders(user.id) {
+				text.push_str(&format!("- {}\n", account));
+			}
+			return send_text_reply(chat, backends, text).await;
+		}
+	};
+	let owner = state.link_db.owner(&address).map_err(Error::Db)?;
+	if owner.filter(|owner| *owner != user.id).is_some() {
+		return Err(Error::Db(LinkDbError::Taken(address)).into());
+	}
+
+	let code = state
+		.link_codes
+		.generate_for(user.id, address.clone(), Utc::now());
+	let text = format!(
+		"{} wants to receive notifications here, to allow this send: /link {} to the bot \
+		from a chat where it knows you. The code is valid for {} minutes",
+		user.name,
+		code,
+		LinkCodes::VALID_FOR
+	);
+	send_text_reply(&address, backends, text).await?;
+	let text = format!(
+		"send the code I send to {} back here as: /link <code> to finish linking",
+		address
+	);
+	send_text_reply(chat, backends, text).await
+}
+
+pub async fn unlink(
+	chat: &Address,
+	backends: &Backends,
+	args: String,
+	user: &User,
+	state: &DataRouterState,
+) -> Result<(), botError> {
+	let address = match parse_address(&args)? {
+		Some(address) => address,
+		None if chat.telegram().is_none() => {
+			state
+				.link_db
+				.unlink_senders(user.id, chat)
+				.map_err(Error::Db)?;
+			let text = format!("unlinked your {} account", chat.backend());
+			return send_text_reply(chat, backends, text).await;
+		}
+		None => return Err(Error::NotEnoughArguments.into()),
+	};
+
+	state.link_db.unlink(user.id, &address).map_err(Error::Db)?;
+	send_text_reply(chat, backends, format!("unlinked {}", address)).await
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn codes_are_single_use_and_expire() {
+		let codes = LinkCodes::default();
+		let now = Utc::now();
+		let code = codes.generate(7, now);
+		assert_eq!(code.len(), CODE_LEN);
+		let pending = PendingLink {
+			user_id: 7,
+			target: None,
+		};
+		assert_eq!(codes.redeem(&code.to_lowercase(), now), Some(pending));
+		assert_eq!(codes.redeem(&code, now), None);
+
+		let code = codes.generate(7, now);
+		let later = now + Duration::minutes(LinkCodes::VALID_FOR + 1);
+		assert_eq!(codes.redeem(&code, later), None);
+	}
+
+	#[test]
+	fn code_only_with_single_argument() {
+		assert_eq!(code("/link AB12CD"), Some("AB12CD"));
+		assert_eq!(code("/link matrix !a:example.org"), None);
+		assert_eq!(code("/link"), None);
+		assert_eq!(code("/plot AB12CD"), None);
+	}
+}
@@ -0,0 +1,312 @@
+use error_level::ErrorLevel;
+use itertools::Itertools;
+use reqwest::multipart::Form;
+use serde::Serialize;
+use serde_json::json;
+use telegram_bot::types::callback_query::CallbackQuery;
+use telegram_bot::types::message::MessageOrChannelPost;
+use telegram_bot::types::refs::{ChatId, MessageId, UserId as TelegramUserId};
+
+use crate::data_store::data_router::DataRouterState;
+use crate::data_store::{Data, DatasetId};
+use crate::database::User;
+use bitspec::FieldId;
+
+use crate::bot::backend::{Address, Backends, Telegram};
+use crate::bot::Error as botError;
+
+const MAX_COLUMN: usize = 3;
+/// time ranges offered for a plot, formatted as /plot expects them
+const RANGES: [&str; 6] = ["1h", "6h", "24h", "3d", "1w", "4w"];
+
+#[derive(ErrorLevel, thiserror::Error, Debug)]
+pub enum Error {
+	#[report(warn)]
+	#[error("sorry I do not understand that button, try the command again")]
+	InvalidCallback(String),
+	#[report(debug)]
+	#[error("that menu is too old, try the command again")]
+	NoMessage,
+	#[report(debug)]
+	#[error("You do not have access to dataset: {0}")]
+	NoAccessToDataSet(DatasetId),
+}
+
+/// the command the menu builds up
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Action {
+	Plot,
+	Show,
+}
+
+impl Action {
+	fn prefix(self) -> &'static str {
+		match self {
+			Action::Plot => "p",
+			Action::Show => "s",
+		}
+	}
+	fn command(self) -> &'static str {
+		match self {
+			Action::Plot => "/plot",
+			Action::Show => "/show",
+		}
+	}
+}
+
+/// the choices made so far, stored in the callback data of a button as:
+/// <action>:<dataset>[:<field>[:<range>]]
+#[derive(Debug, PartialEq)]
+struct Selection {
+	action: Action,
+	set_id: Option<DatasetId>,
+	field_id: Option<FieldId>,
+	range: Option<String>,
+}
+
+impl Selection {
+	fn parse(data: &str) -> Option<Self> {
+		let mut parts = data.split(':');
+		let action = match parts.next()? {
+			"p" => Action::Plot,
+			"s" => Action::Show,
+			_ => return None,
+		};
+		let set_id = parts.next().map(str::parse).transpose().ok()?;
+		let field_id = parts.next().map(str::parse).transpose().ok()?;
+		let range = parts.next().map(str::to_owned);
+		Some(Selection {
+			action,
+			set_id,
+			field_id,
+			range,
+		})
+	}
+
+	fn encode(&self) -> String {
+		let mut data = self.action.prefix().to_owned();
+		if let Some(set_id) = self.set_id {
+			data.push_str(&format!(":{}", set_id));
+		}
+		if let Some(field_id) = self.field_id {
+			data.push_str(&format!(":{}", field_id));
+		}
+		if let Some(range) = &self.range {
+			data.push_str(&format!(":{}", range));
+		}
+		data
+	}
+
+	/// the command to run once everything has been picked
+	fn finished(&self) -> Option<String> {
+		let plotable = format!("{}_{}", self.set_id?, self.field_id?);
+		let command = self.action.command();
+		match (self.action, &self.range) {
+			(Action::Show, _) => Some(format!("{} {}", command, plotable)),
+			(Action::Plot, Some(range)) => Some(format!("{} {} {}", command, plotable, range)),
+			(Action::Plot, None) => None,
+		}
+	}
+}
+
+#[derive(Serialize)]
+struct Button {
+	text: String,
+	callback_data: String,
+}
+
+type Keyboard = Vec<Vec<Button>>;
+
+fn into_rows(buttons: Vec<Button>) -> Keyboard {
+	buttons
+		.into_iter()
+		.chunks(MAX_COLUMN)
+		.into_iter()
+		.map(|chunk| chunk.collect())
+		.collect()
+}
+
+fn button(text: impl Into<String>, selection: Selection) -> Button {
+	Button {
+		text: text.into(),
+		callback_data: selection.encode(),
+	}
+}
+
+/// the text and buttons for the next choice to make
+fn next_step(selection: &Selection, user: &User, data: &Data) -> Result<(String, Keyboard), Error> {
+	let action = selection.action;
+	let set_id = match selection.set_id {
+		None => {
+			let mut sets: Vec<_> = user.timeseries_with_access.keys().copied().collect();
+			sets.sort_unstable();
+			let buttons = sets
+				.into_iter()
+				.filter_map(|id| data.sets.get(&id).map(|set| (id, set)))
+				.map(|(id, set)| {
+					let selection = Selection {
+						action,
+						set_id: Some(id),
+						field_id: None,
+						range: None,
+					};
+					button(&set.metadata.name, selection)
+				})
+				.collect();
+			return Ok((String::from("pick a dataset"), into_rows(buttons)));
+		}
+		Some(set_id) => set_id,
+	};
+
+	let selected = |set_id, field_id| Selection {
+		action,
+		set_id,
+		field_id,
+		range: None,
+	};
+	let fields = user
+		.timeseries_with_access
+		.get(&set_id)
+		.ok_or(Error::NoAccessToDataSet(set_id))?;
+	let set = data
+		.sets
+		.get(&set_id)
+		.ok_or(Error::NoAccessToDataSet(set_id))?;
+
+	if let Some(field_id) = selection.field_id {
+		let mut buttons: Vec<Button> = RANGES
+			.iter()
+			.map(|range| {
+				let selection = Selection {
+					action,
+					set_id: Some(set_id),
+					field_id: Some(field_id),
+					range: Some((*range).to_owned()),
+				};
+				button(*range, selection)
+			})
+			.collect();
+		buttons.push(button("« back", selected(Some(set_id), None)));
+		let name = data
+			.name_of_field(set_id, field_id)
+			.unwrap_or_else(|| format!("{}_{}", set_id, field_id));
+		return Ok((format!("plot {} over the last", name), into_rows(buttons)));
+	}
+
+	let mut buttons: Vec<Button> = fields
+		.iter()
+		.map(FieldId::from)
+		.filter_map(|id| set.metadata.fields.get(id as usize).map(|field| (id, field)))
+		.map(|(id, field)| button(&field.name, selected(Some(set_id), Some(id))))
+		.collect();
+	buttons.push(button("« back", selected(None, None)));
+	let text = format!("pick a field of {}", set.metadata.name);
+	Ok((text, into_rows(buttons)))
+}
+
+fn markup(keyboard: Keyboard) -> String {
+	json!({ "inline_keyboard": keyboard }).to_string()
+}
+
+/// opens a menu to pick the arguments of the command by tapping buttons
+pub async fn start(
+	chat: &Address,
+	backends: &Backends,
+	action: Action,
+	user: &User,
+	state: &DataRouterState,
+) -> Result<(), botError> {
+	let (chat_id, telegram) = backends.telegram_chat(chat)?;
+	let selection = Selection {
+		action,
+		set_id: None,
+		field_id: None,
+		range: None,
+	};
+	let (text, keyboard) = next_step(&selection, user, &state.data.read().unwrap())?;
+
+	let form = Form::new()
+		.text("chat_id", chat_id.to_string())
+		.text("text", text)
+		.text("reply_markup", markup(keyboard));
+	telegram.call("sendMessage", form).await?;
+	Ok(())
+}
+
+async fn edit(
+	chat_id: ChatId,
+	message_id: MessageId,
+	telegram: &Telegram,
+	text: String,
+	keyboard: Option<Keyboard>,
+) -> Result<(), botError> {
+	let mut form = Form::new()
+		.text("chat_id", chat_id.to_string())
+		.text("message_id", message_id.to_string())
+		.text("text", text);
+	if let Some(keyboard) = keyboard {
+		form = form.text("reply_markup", markup(keyboard));
+	}
+	telegram.call("editMessageText", form).await?;
+	Ok(())
+}
+
+/// stops the loading animation on the button that was pressed
+async fn answer(query: &CallbackQuery, telegram: &Telegram) -> Result<(), botError> {
+	let form = Form::new().text("callback_query_id", query.id.to_string());
+	telegram.call("answerCallbackQuery", form).await?;
+	Ok(())
+}
+
+/// the chat the pressed button is in and who pressed it
+pub fn ids(query: &CallbackQuery) -> Result<(ChatId, MessageId, TelegramUserId), Error> {
+	match &query.message {
+		Some(MessageOrChannelPost::Message(message)) => {
+			Ok((message.chat.id(), message.id, query.from.id))
+		}
+		_ => Err(Error::NoMessage),
+	}
+}
+
+/// moves the menu to the next step, returns the command to run once the
+/// user picked everything
+pub async fn callback(
+	query: CallbackQuery,
+	user: &User,
+	state: &DataRouterState,
+) -> Result<Option<String>, botError> {
+	let telegram = &state.backends.telegram;
+	answer(&query, telegram).await?;
+
+	let (chat_id, message_id, _) = ids(&query)?;
+	let data = query.data.unwrap_or_default();
+	let selection = Selection::parse(&data).ok_or(Error::InvalidCallback(data))?;
+
+	if let Some(command) = selection.finished() {
+		edit(chat_id, message_id, telegram, command.clone(), None).await?;
+		return Ok(Some(command));
+	}
+	let (text, keyboard) = next_step(&selection, user, &state.data.read().unwrap())?;
+	edit(chat_id, message_id, telegram, text, Some(keyboard)).await?;
+	Ok(None)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn selection_round_trip() {
+		let data = "p:3:1:24h";
+		let selection = Selection::parse(data).unwrap();
+		assert_eq!(selection.encode(), data);
+		assert_eq!(selection.finished(), Some(String::from("/plot 3_1 24h")));
+
+		let selection = Selection::parse("p:3:1").unwrap();
+		assert_eq!(selection.finished(), None);
+		let selection = Selection::parse("s:3:1").unwrap();
+		assert_eq!(selection.finished(), Some(String::from("/show 3_1")));
+		assert!(Selection::parse("p:x").is_none());
+		assert!(Selection::parse("q:3").is_none());
+	}
+}
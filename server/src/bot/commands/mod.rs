@@ -1,8 +1,15 @@
 pub mod alarms;
 pub mod alias;
 pub mod help;
+pub mod incidents;
 pub mod keyboard;
+pub mod link;
+pub mod menu;
 pub mod plotables;
+pub mod reports;
+pub mod share;
 pub mod show;
+pub mod status;
+pub mod timezone;
 
 pub mod plot;
@@ -1,26 +1,41 @@
-use chrono::{DateTime, Duration, NaiveDateTime, Utc, Local, TimeZone};
+use chrono::{DateTime, Duration, NaiveDate, NaiveDateTime, NaiveTime, Utc, TimeZone};
+use chrono_tz::Tz;
+use plotters::coord::Shift;
 use plotters::prelude::*;
-use plotters::style::colors::{BLACK, RED, WHITE};
+use plotters::style::colors::{BLACK, WHITE};
 
 use image::{png::PngEncoder, ColorType};
 use log::{error, warn};
+use regex::Regex;
 
 use crate::data_store::data_router::DataRouterState;
-use crate::data_store::{Data, DatasetId, FieldDecoder};
+use crate::data_store::{names, Data, DatasetId, FieldDecoder};
 use crate::database::{User, UserDbError};
 use bitspec::FieldId;
 use error_level::ErrorLevel;
 
+use crate::bot::backend::{Address, Backends, File};
 use crate::bot::Error as botError;
-use telegram_bot::types::refs::ChatId;
 
 use std::collections::HashMap;
 use std::sync::{Arc, RwLock};
 
-pub const USAGE: &str = "/plot <plotable_id> <number><s|m|h|d|w|monthes|years>";
-pub const DESCRIPTION: &str = "send a line graph of a sensor value aka plotable, \
- from a given time ago till now. Optionally adding <start:stop> allows to \
- specify the start and stop value for the y-axis";
+mod overlay;
+use overlay::Threshold;
+
+pub const USAGE: &str = "/plot <plotable_id|dataset_name.field_name> ... \
+ <number><s|m|h|d|w|months|years>|<from>..<to> [-svg] [-csv] [-size <width>x<height>] \
+ [-band] [-avg <number><unit>] [-alarms]";
+pub const DESCRIPTION: &str = "send a line graph of one or more sensor values aka \
+ plotables, from a given time ago till now or between two moments written as \
+ yyyy-mm-dd, yyyy-mm-ddThh:mm or hh:mm (to may be left out for now). Lines that \
+ differ a lot in scale get their own y-axis on the right, add @r to a \
+ plotable to place it there yourself. Optionally adding <start:stop> allows to \
+ specify the start and stop value for the left y-axis. Options: -svg sends an svg \
+ file instead of an image, -csv also sends the plotted points as csv, -size sets the \
+ image size, -band draws the min and max around each point, -avg adds the moving \
+ average over the given time and -alarms draws the values your alarms on the fields \
+ compare against. Without arguments a menu lets you pick what to plot";
 
 #[derive(ErrorLevel, thiserror::Error, Debug)]
 pub enum Error {
@@ -57,6 +72,11 @@ pub enum Error {
     #[report(debug)]
 	#[error("Not enough arguments \nuse: {}", USAGE)]
 	NotEnoughArguments,
+    #[report(debug)]
+	#[error("There is no data in this time range")]
+	NoData,
+	#[error("{0}")]
+	Names(#[from] names::Error),
     #[report(error)]
 	#[error("Error getting data: {0}")]
 	DataSet(#[from] byteseries::Error),
@@ -71,86 +91,130 @@ fn unwrap_threadpool_err<E: std::fmt::Debug>(e: actix_threadpool::BlockingError<
 }
 
 pub async fn send(
-	chat_id: ChatId,
+	chat: &Address,
 	state: &DataRouterState,
-	token: &str,
+	backends: &Backends,
 	args: String,
 	user: &User,
 ) -> Result<(), botError> {
+	let access = &user.timeseries_with_access;
+	let args = names::resolve(&args, &state.data.read().unwrap(), access).map_err(Error::from)?;
 	let args: Vec<String> = args.split_whitespace().map(|s| s.to_owned()).collect();
 
     let user = user.clone();
     let state = state.clone();
     let plot_job = move || plot(args, state, user);
-    let plot = actix_threadpool::run(plot_job).await
+    let rendered = actix_threadpool::run(plot_job).await
         .map_err(unwrap_threadpool_err)?;
 
-	let photo_part = reqwest::multipart::Part::bytes(plot)
-		.mime_str("image/png")
-		.unwrap()
-		.file_name("testplot.png");
+	let (mime, name) = match rendered.format {
+		Format::Png => ("image/png", "plot.png"),
+		Format::Svg => ("image/svg+xml", "plot.svg"),
+	};
+	let image = File {
+		bytes: rendered.image,
+		mime,
+		name,
+		inline: rendered.format == Format::Png,
+	};
+	backends.send_file(chat, image).await?;
+	if let Some(csv) = rendered.csv {
+		let csv = File {
+			bytes: csv.into_bytes(),
+			mime: "text/csv",
+			name: "plot.csv",
+			inline: false,
+		};
+		backends.send_file(chat, csv).await?;
+	}
+	Ok(())
+}
 
-	let url = format!("https://api.telegram.org/bot{}/sendPhoto", token);
+/// points read per dataset for a plot
+const PLOT_POINTS: usize = 1000;
+/// points read per dataset for a plot with min/max bands, they are
+/// aggregated into overlay::BUCKETS buckets
+const BAND_POINTS: usize = 20_000;
+const MAX_DIMENSION: u32 = 4000;
+const MIN_DIMENSION: u32 = 200;
+
+/// a line in the plot, points are unix timestamps with a value
+struct Line {
+	label: String,
+	points: Vec<(i64, f32)>,
+	secondary: bool,
+	/// the minimum and maximum around each point, empty if not requested
+	band: Vec<(i64, f32, f32)>,
+}
 
-	let form = reqwest::multipart::Form::new()
-		.text("chat_id", chat_id.to_string())
-		.part("photo", photo_part);
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Format {
+	Png,
+	Svg,
+}
 
-	let client = reqwest::Client::new();
-	let resp = client.post(&url).multipart(form).send().await?;
+#[derive(Debug, PartialEq)]
+struct Options {
+	format: Format,
+	csv: bool,
+	dimensions: (u32, u32),
+	band: bool,
+	/// window of the moving average in seconds
+	moving_average: Option<i64>,
+	thresholds: bool,
+}
 
-	if resp.status() != reqwest::StatusCode::OK {
-		Err(botError::InvalidServerResponse(resp))
-	} else {
-		Ok(())
+impl Default for Options {
+	fn default() -> Self {
+		Options {
+			format: Format::Png,
+			csv: false,
+			dimensions: (900, 900),
+			band: false,
+			moving_average: None,
+			thresholds: false,
+		}
 	}
 }
 
-type PlotData = (Vec<i64>, Vec<f32>, Vec<(FieldId, String)>);
-fn xlimits_from_data(data: &[PlotData]) -> Result<(DateTime<Local>, DateTime<Local>), Error> {
-    assert!(!data.is_empty());
-    let mut min_ts = std::i64::MAX; //initialization does not matter as data.len > 0   
-    let mut max_ts = std::i64::MIN;
-	for (time, _values, _meta) in data {
-        min_ts = min_ts.min(*time.first().unwrap());
-        max_ts = min_ts.max(*time.last().unwrap());
-    }
-	assert!(min_ts < max_ts);
+struct Rendered {
+	image: Vec<u8>,
+	format: Format,
+	csv: Option<String>,
+}
+
+/// a field to plot, on the secondary (right) y-axis if marked with @r
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Plotable {
+	set_id: DatasetId,
+	field_id: FieldId,
+	secondary: bool,
+}
+
+fn xlimits_from_data(lines: &[Line], tz: Tz) -> (DateTime<Tz>, DateTime<Tz>) {
+	let timestamps = lines.iter().flat_map(|line| line.points.iter().map(|p| p.0));
+	let mut min_ts = timestamps.clone().min().unwrap();
+	let mut max_ts = timestamps.max().unwrap();
 	if min_ts == max_ts {
 		min_ts -= 1;
 		max_ts += 1;
 	}
 
-	let min = Local.from_utc_datetime(&NaiveDateTime::from_timestamp(min_ts, 0));
-	let max = Local.from_utc_datetime(&NaiveDateTime::from_timestamp(max_ts, 0));
-	Ok((min, max))
+	let min = tz.from_utc_datetime(&NaiveDateTime::from_timestamp(min_ts, 0));
+	let max = tz.from_utc_datetime(&NaiveDateTime::from_timestamp(max_ts, 0));
+	(min, max)
 }
 
-fn ylimits_from_data(data: &[PlotData]) -> (f32, f32) {
-	let mut min = *data
-		.iter()
-		.map(|data_set| {
-			data_set
-				.1
-				.iter()
-				.min_by(|a, b| a.partial_cmp(b).expect("NAN in plot data"))
-				.unwrap()
-		})
-		.min_by(|a, b| a.partial_cmp(b).expect("NAN in plot data"))
-		.unwrap();
-	let mut max = *data
-		.iter()
-		.map(|data_set| {
-			data_set
-				.1
-				.iter()
-				.max_by(|a, b| a.partial_cmp(b).expect("NAN in plot data"))
-				.unwrap()
-		})
-		.max_by(|a, b| a.partial_cmp(b).expect("NAN in plot data"))
-		.unwrap();
+fn ylimits_from_data<'a>(lines: impl Iterator<Item = &'a Line>) -> (f32, f32) {
+	let values = lines.flat_map(|line| line.points.iter().map(|p| p.1));
+	let (mut min, mut max) = values.fold((f32::MAX, f32::MIN), |(min, max), v| {
+		(min.min(v), max.max(v))
+	});
 
-	if (min - max).abs() < f32::EPSILON {
+	if min > max {
+		//no lines on this axis
+		return (0.0, 1.0);
+	} else if (min - max).abs() < f32::EPSILON {
 		min -= 0.1;
 		max += 0.1;
 	} else {
@@ -160,7 +224,34 @@ fn ylimits_from_data(data: &[PlotData]) -> (f32, f32) {
 	(min, max)
 }
 
-fn format_str_from_limits(from: &DateTime<Local>, to: &DateTime<Local>) -> &'static str {
+/// the largest absolute value in a line, used to guess if lines share a unit
+fn magnitude(line: &Line) -> f32 {
+	line.points
+		.iter()
+		.map(|p| p.1.abs())
+		.fold(0f32, f32::max)
+		.max(f32::EPSILON)
+}
+
+/// the server does not know the units of fields, lines that differ more
+/// then a factor 10 in magnitude from the first line likely have a different
+/// unit and are moved to the secondary axis unless the user placed a line
+/// there already
+fn assign_axes(lines: &mut [Line]) {
+	if lines.iter().any(|line| line.secondary) {
+		return;
+	}
+	let reference = match lines.first() {
+		Some(line) => magnitude(line),
+		None => return,
+	};
+	for line in lines.iter_mut().skip(1) {
+		let ratio = magnitude(line) / reference;
+		line.secondary = !(0.1..=10.0).contains(&ratio);
+	}
+}
+
+fn format_str_from_limits(from: &DateTime<Tz>, to: &DateTime<Tz>) -> &'static str {
 	let duration = *to - *from;
 
 	if duration < Duration::minutes(1) {
@@ -180,62 +271,123 @@ fn format_str_from_limits(from: &DateTime<Local>, to: &DateTime<Local>) -> &'sta
 	}
 }
 
-fn plot(args: Vec<String>, state: DataRouterState, user: User) -> Result<Vec<u8>, Error> {
-	const DIMENSIONS: (u32, u32) = (900u32, 900u32);
-	let (timerange, set_id, field_id, scaling_args) = parse_plot_arguments(args)?;
-	let selected_datasets = select_data(set_id, vec![field_id], &user)?;
+/// everything drawn in the plot
+struct Figure {
+	lines: Vec<Line>,
+	averages: Vec<Line>,
+	thresholds: Vec<Threshold>,
+	scaling: Option<(f32, f32)>,
+}
 
-	//collect data for plotting
-	let plot_data: Result<Vec<PlotData>, Error> = selected_datasets
-		.into_iter()
-		.map(|sel| read_data(sel, &state.data, timerange))
-		.collect();
-	let plot_data = plot_data?;
+fn to_local(timestamp: i64, tz: Tz) -> DateTime<Tz> {
+	tz.from_utc_datetime(&NaiveDateTime::from_timestamp(timestamp, 0))
+}
 
-	let (from_date, to_date) = xlimits_from_data(&plot_data)?;
-	let x_label_formatstr = format_str_from_limits(&from_date, &to_date);
-	let (y_min, y_max) = if let Some(manual) = scaling_args {
-		manual
-	} else {
-		ylimits_from_data(&plot_data)
-	};
+/// the limits of an axis, fitted to the lines and thresholds on it
+fn ylimits(figure: &Figure, secondary: bool) -> (f32, f32) {
+	let on_axis = |line: &&Line| line.secondary == secondary;
+	let (mut min, mut max) = ylimits_from_data(figure.lines.iter().filter(on_axis));
+	for line in figure.lines.iter().filter(on_axis) {
+		for (_, low, high) in &line.band {
+			min = min.min(*low);
+			max = max.max(*high);
+		}
+	}
+	for threshold in figure.thresholds.iter().filter(|t| t.secondary == secondary) {
+		min = min.min(threshold.value);
+		max = max.max(threshold.value);
+	}
+	(min, max)
+}
 
-	//Init plot
-	let mut subpixelbuffer: Vec<u8> = vec![0u8; (DIMENSIONS.0 * DIMENSIONS.1 * 3) as usize];
-	let root = BitMapBackend::with_buffer(&mut subpixelbuffer, DIMENSIONS).into_drawing_area();
+fn draw<DB: DrawingBackend>(
+	root: DrawingArea<DB, Shift>,
+	figure: Figure,
+	tz: Tz,
+) -> Result<(), Error> {
 	root.fill(&WHITE).map_err(|_| Error::PlotLib)?;
+	let has_secondary = figure.lines.iter().any(|line| line.secondary);
+
+	let (from_date, to_date) = xlimits_from_data(&figure.lines, tz);
+	let x_label_formatstr = format_str_from_limits(&from_date, &to_date);
+	let (y_min, y_max) = match figure.scaling {
+		Some(manual) => manual,
+		None => ylimits(&figure, false),
+	};
+	let (y2_min, y2_max) = ylimits(&figure, true);
 
 	let mut chart = ChartBuilder::on(&root)
 		.x_label_area_size(40)
 		.y_label_area_size(40)
+		.right_y_label_area_size(if has_secondary { 40 } else { 0 })
 		.build_cartesian_2d(from_date..to_date, y_min..y_max)
-		.map_err(|_| Error::PlotLib)?;
+		.map_err(|_| Error::PlotLib)?
+		.set_secondary_coord(from_date..to_date, y2_min..y2_max);
 	chart
 		.configure_mesh()
 		// .line_style_2(&WHITE)
 		.x_label_formatter(&|v| v.format(x_label_formatstr).to_string())
 		.draw()
 		.map_err(|_| Error::PlotLib)?;
+	if has_secondary {
+		chart
+			.configure_secondary_axes()
+			.draw()
+			.map_err(|_| Error::PlotLib)?;
+	}
 
-	//add lines
-	for (shared_x, ys, mut labels) in plot_data {
-		let n_lines = labels.len();
-		for (i, label) in labels.drain(..).enumerate() {
-			chart
-				.draw_series(LineSeries::new(
-					shared_x
-						.iter()
-						.map(|x| {
-                            let naive = NaiveDateTime::from_timestamp(*x, 0);
-							Local.from_utc_datetime(&naive)
-						})
-						.zip(ys.iter().skip(i).step_by(n_lines).copied()),
-					&RED,
-				))
-				.map_err(|_| Error::PlotLib)?
-				.label(label.1);
+	//add min/max bands below the lines
+	for (i, line) in figure.lines.iter().enumerate() {
+		if line.band.is_empty() {
+			continue;
 		}
+		let color = Palette99::pick(i).to_rgba();
+		let upper = line.band.iter().map(|(x, _, high)| (to_local(*x, tz), *high));
+		let lower = line.band.iter().rev().map(|(x, low, _)| (to_local(*x, tz), *low));
+		let band = Polygon::new(upper.chain(lower).collect::<Vec<_>>(), color.mix(0.2).filled());
+		let res = if line.secondary {
+			chart.draw_secondary_series(std::iter::once(band))
+		} else {
+			chart.draw_series(std::iter::once(band))
+		};
+		res.map_err(|_| Error::PlotLib)?;
+	}
+
+	//add lines, a moving average gets the color of the line it averages
+	let lines = figure.lines.into_iter().enumerate();
+	let averages = figure.averages.into_iter().enumerate();
+	let styled = lines
+		.map(|(i, line)| (Palette99::pick(i).to_rgba().stroke_width(1), line))
+		.chain(averages.map(|(i, line)| (Palette99::pick(i).to_rgba().stroke_width(3), line)));
+	for (style, line) in styled {
+		let points = line.points.into_iter().map(|(x, y)| (to_local(x, tz), y));
+		let series = LineSeries::new(points, style);
+		let annotation = if line.secondary {
+			chart.draw_secondary_series(series)
+		} else {
+			chart.draw_series(series)
+		};
+		annotation
+			.map_err(|_| Error::PlotLib)?
+			.label(line.label)
+			.legend(move |(x, y)| PathElement::new(vec![(x, y), (x + 20, y)], style));
 	}
+
+	for threshold in figure.thresholds {
+		let style = BLACK.mix(0.6).stroke_width(1);
+		let points = vec![(from_date, threshold.value), (to_date, threshold.value)];
+		let series = LineSeries::new(points, style);
+		let annotation = if threshold.secondary {
+			chart.draw_secondary_series(series)
+		} else {
+			chart.draw_series(series)
+		};
+		annotation
+			.map_err(|_| Error::PlotLib)?
+			.label(threshold.label)
+			.legend(move |(x, y)| PathElement::new(vec![(x, y), (x + 20, y)], style));
+	}
+
 	//finish plot
 	chart
 		.configure_series_labels()
@@ -243,44 +395,179 @@ fn plot(args: Vec<String>, state: DataRouterState, user: User) -> Result<Vec<u8>
 		.border_style(&BLACK)
 		.draw()
 		.map_err(|_| Error::PlotLib)?;
+	Ok(())
+}
 
-	drop(chart);
-	drop(root);
+/// the plotted points as: time,field,value with the time in the users timezone
+fn to_csv(lines: &[Line], tz: Tz) -> String {
+	let mut csv = String::from("time,field,value\n");
+	for line in lines {
+		for (x, y) in &line.points {
+			csv.push_str(&format!("{},{},{}\n", to_local(*x, tz).to_rfc3339(), line.label, y));
+		}
+	}
+	csv
+}
 
-	//plot to png image
-	let mut image = Vec::new();
-	PngEncoder::new(&mut image)
-		.encode(&subpixelbuffer, DIMENSIONS.0, DIMENSIONS.1, ColorType::Rgb8)
-		.map_err(Error::Encoding)?;
+fn plot(args: Vec<String>, state: DataRouterState, user: User) -> Result<Rendered, Error> {
+	let PlotArgs {
+		timerange,
+		plotables,
+		scaling,
+		options,
+	} = parse_plot_arguments(args, user.timezone)?;
+	let selected_datasets = select_data(&plotables, &user)?;
 
-	Ok(image)
+	//collect data for plotting
+	let max_points = if options.band {
+		BAND_POINTS
+	} else {
+		PLOT_POINTS
+	};
+	let mut lines = Vec::with_capacity(plotables.len());
+	{
+		let mut read = HashMap::new();
+		for (set_id, field_ids) in selected_datasets {
+			let data = read_data((set_id, field_ids.clone()), &state.data, timerange, max_points)?;
+			read.insert(set_id, (field_ids, data));
+		}
+		let data = state.data.read().unwrap();
+		for plotable in &plotables {
+			let (field_ids, (x_shared, ys)) = &read[&plotable.set_id];
+			let n_fields = field_ids.len();
+			let column = field_ids.iter().position(|id| *id == plotable.field_id).unwrap();
+			let label = data
+				.name_of_field(plotable.set_id, plotable.field_id)
+				.unwrap_or_else(|| format!("{}_{}", plotable.set_id, plotable.field_id));
+			let points = x_shared
+				.iter()
+				.copied()
+				.zip(ys.iter().skip(column).step_by(n_fields).copied())
+				.collect();
+			lines.push((
+				*plotable,
+				Line {
+					label,
+					points,
+					secondary: plotable.secondary,
+					band: Vec::new(),
+				},
+			));
+		}
+	}
+	lines.retain(|(_, line)| !line.points.is_empty());
+	if lines.is_empty() {
+		return Err(Error::NoData);
+	}
+	if options.band {
+		for (_, line) in &mut lines {
+			overlay::bucket(line);
+		}
+	}
+	let (plotables, mut lines): (Vec<Plotable>, Vec<Line>) = lines.into_iter().unzip();
+	assign_axes(&mut lines);
+
+	let averages = match options.moving_average {
+		Some(seconds) => lines
+			.iter()
+			.map(|line| overlay::moving_average(line, seconds))
+			.collect(),
+		None => Vec::new(),
+	};
+	let thresholds = if options.thresholds {
+		let alarms = state.alarm_db.list_users_alarms(user.id);
+		plotables
+			.iter()
+			.zip(&lines)
+			.flat_map(|(p, line)| overlay::thresholds(&alarms, p.set_id, p.field_id, line))
+			.collect()
+	} else {
+		Vec::new()
+	};
+
+	let csv = if options.csv {
+		Some(to_csv(&lines, user.timezone))
+	} else {
+		None
+	};
+	let figure = Figure {
+		lines,
+		averages,
+		thresholds,
+		scaling,
+	};
+
+	let (width, height) = options.dimensions;
+	let image = match options.format {
+		Format::Png => {
+			let mut subpixelbuffer: Vec<u8> = vec![0u8; (width * height * 3) as usize];
+			let root = BitMapBackend::with_buffer(&mut subpixelbuffer, (width, height))
+				.into_drawing_area();
+			draw(root, figure, user.timezone)?;
+
+			//plot to png image
+			let mut image = Vec::new();
+			PngEncoder::new(&mut image)
+				.encode(&subpixelbuffer, width, height, ColorType::Rgb8)
+				.map_err(Error::Encoding)?;
+			image
+		}
+		Format::Svg => {
+			let mut svg = String::new();
+			let root = SVGBackend::with_string(&mut svg, (width, height)).into_drawing_area();
+			draw(root, figure, user.timezone)?;
+			svg.into_bytes()
+		}
+	};
+
+	Ok(Rendered {
+		image,
+		format: options.format,
+		csv,
+	})
 }
 
-type PlotArgs = ((DateTime<Utc>, DateTime<Utc>), DatasetId, FieldId, Option<(f32, f32)>);
-fn parse_plot_arguments(
-	args: Vec<String>,
-) -> Result<PlotArgs, Error> {
-	if args.len() < 2 {
-		return Err(Error::NotEnoughArguments);
-	}
+struct PlotArgs {
+	timerange: (DateTime<Utc>, DateTime<Utc>),
+	plotables: Vec<Plotable>,
+	scaling: Option<(f32, f32)>,
+	options: Options,
+}
 
-	let mut plotable = args[0].split('_');
+fn parse_plotable(arg: &str) -> Result<Plotable, Error> {
+	let (ids, secondary) = match arg.strip_suffix("@r") {
+		Some(ids) => (ids, true),
+		None => (arg, false),
+	};
+	let mut plotable = ids.split('_');
 	let set_id = plotable
 		.next()
-		.ok_or_else(|| Error::IncorrectArgument(args[0].clone()))?
+		.ok_or_else(|| Error::IncorrectArgument(arg.to_owned()))?
 		.parse::<DatasetId>()?;
-
 	let field_id = plotable
 		.next()
-		.ok_or_else(|| Error::IncorrectArgument(args[0].clone()))?
+		.ok_or_else(|| Error::IncorrectArgument(arg.to_owned()))?
 		.parse::<FieldId>()?;
+	Ok(Plotable {
+		set_id,
+		field_id,
+		secondary,
+	})
+}
+
+/// a duration such as 24h, the plot runs from that long ago till now
+fn parse_ago(arg: &str) -> Result<(DateTime<Utc>, DateTime<Utc>), Error> {
+	let duration = parse_length(arg)?;
+	Ok((Utc::now() - duration, Utc::now()))
+}
 
-	let end = args[1]
-		.find(|c: char| c.is_alphabetic() || c == '.')
-		.ok_or_else(|| Error::IncorrectArgument(args[1].clone()))?;
+fn parse_length(arg: &str) -> Result<Duration, Error> {
+	let end = arg
+		.find(|c: char| c.is_alphabetic())
+		.ok_or_else(|| Error::IncorrectArgument(arg.to_owned()))?;
 
-	let numb = args[1][..end].parse::<f32>()?;
-	let unit = &args[1][end..];
+	let numb = arg[..end].parse::<f32>()?;
+	let unit = &arg[end..];
 	let duration = match unit {
 		"s" => numb * 1_f32,
 		"m" => numb * 60_f32,
@@ -289,64 +576,146 @@ fn parse_plot_arguments(
 		"w" => numb * (7 * 24 * 3600) as f32,
 		"months" => numb * (4 * 7 * 24 * 3600) as f32,
 		"years" => numb * (365 * 24 * 3600) as f32,
-		_ => return Err(Error::IncorrectArgument(args[1].clone())),
+		_ => return Err(Error::IncorrectArgument(arg.to_owned())),
 	};
-	let duration = Duration::seconds(duration as i64);
-	let timerange = (Utc::now() - duration, Utc::now());
-
-	//optional argument
-	let scaling = if args.len() > 2 {
-		let mut params = args[2].split(':');
-		let y_min = params
-			.next()
-			.ok_or_else(|| Error::IncorrectArgument(args[2].clone()))?
-			.parse::<f32>()?;
-		let y_max = params
-			.next()
-			.ok_or_else(|| Error::IncorrectArgument(args[2].clone()))?
-			.parse::<f32>()?;
-        #[allow(clippy::float_cmp)]
-		if y_min == y_max { //direct float cmp allowed as both come from a parse
-			return Err(Error::IncorrectArgument(args[2].clone()));
-		}
+	Ok(Duration::seconds(duration as i64))
+}
 
-		Some((y_min, y_max))
+fn parse_dimensions(arg: Option<&String>) -> Result<(u32, u32), Error> {
+	let arg = arg.ok_or(Error::NotEnoughArguments)?;
+	let mut sizes = arg.split('x');
+	let width = sizes
+		.next()
+		.ok_or_else(|| Error::IncorrectArgument(arg.clone()))?
+		.parse::<u32>()?;
+	let height = sizes
+		.next()
+		.ok_or_else(|| Error::IncorrectArgument(arg.clone()))?
+		.parse::<u32>()?;
+	let valid = MIN_DIMENSION..=MAX_DIMENSION;
+	if !valid.contains(&width) || !valid.contains(&height) {
+		return Err(Error::IncorrectArgument(arg.clone()));
+	}
+	Ok((width, height))
+}
+
+/// a moment in the users timezone as: yyyy-mm-dd, yyyy-mm-ddThh:mm or hh:mm
+/// for today
+fn parse_moment(arg: &str, tz: Tz) -> Result<DateTime<Utc>, Error> {
+	let invalid = || Error::IncorrectArgument(arg.to_owned());
+	let naive = if let Ok(datetime) = NaiveDateTime::parse_from_str(arg, "%Y-%m-%dT%H:%M") {
+		datetime
+	} else if let Ok(date) = NaiveDate::parse_from_str(arg, "%Y-%m-%d") {
+		date.and_hms(0, 0, 0)
+	} else if let Ok(time) = NaiveTime::parse_from_str(arg, "%H:%M") {
+		Utc::now().with_timezone(&tz).date().naive_local().and_time(time)
 	} else {
-		None
+		return Err(invalid());
 	};
-	Ok((timerange, set_id, field_id, scaling))
+	let local = tz.from_local_datetime(&naive).earliest().ok_or_else(invalid)?;
+	Ok(local.with_timezone(&Utc))
 }
 
-pub fn select_data(
-	set_id: DatasetId,
-	field_ids: Vec<FieldId>,
+/// an explicit range as from..to, an empty to means now
+fn parse_range(arg: &str, tz: Tz) -> Result<(DateTime<Utc>, DateTime<Utc>), Error> {
+	let mut parts = arg.splitn(2, "..");
+	let from = parse_moment(parts.next().unwrap_or_default(), tz)?;
+	let to = match parts.next() {
+		Some("") | None => Utc::now(),
+		Some(to) => parse_moment(to, tz)?,
+	};
+	if from >= to {
+		return Err(Error::IncorrectArgument(arg.to_owned()));
+	}
+	Ok((from, to))
+}
+
+fn parse_scaling(arg: &str) -> Result<(f32, f32), Error> {
+	let mut params = arg.split(':');
+	let y_min = params
+		.next()
+		.ok_or_else(|| Error::IncorrectArgument(arg.to_owned()))?
+		.parse::<f32>()?;
+	let y_max = params
+		.next()
+		.ok_or_else(|| Error::IncorrectArgument(arg.to_owned()))?
+		.parse::<f32>()?;
+	#[allow(clippy::float_cmp)]
+	if y_min == y_max { //direct float cmp allowed as both come from a parse
+		return Err(Error::IncorrectArgument(arg.to_owned()));
+	}
+	Ok((y_min, y_max))
+}
+
+fn parse_plot_arguments(args: Vec<String>, tz: Tz) -> Result<PlotArgs, Error> {
+	let plotable_re = Regex::new(r#"^\d+_\d+(@r)?$"#).unwrap();
+	let scaling_re = Regex::new(r#"^-?[\d.]+:-?[\d.]+$"#).unwrap();
+
+	let mut plotables = Vec::new();
+	let mut timerange = None;
+	let mut scaling = None;
+	let mut options = Options::default();
+	let mut args = args.iter();
+	while let Some(arg) = args.next() {
+		match arg.as_str() {
+			"-svg" => options.format = Format::Svg,
+			"-csv" => options.csv = true,
+			"-band" => options.band = true,
+			"-alarms" => options.thresholds = true,
+			"-size" => options.dimensions = parse_dimensions(args.next())?,
+			"-avg" => {
+				let window = args.next().ok_or(Error::NotEnoughArguments)?;
+				options.moving_average = Some(parse_length(window)?.num_seconds());
+			}
+			arg if plotable_re.is_match(arg) => plotables.push(parse_plotable(arg)?),
+			arg if arg.contains("..") => timerange = Some(parse_range(arg, tz)?),
+			arg if scaling_re.is_match(arg) => scaling = Some(parse_scaling(arg)?),
+			arg if arg.starts_with('-') => return Err(Error::IncorrectArgument(arg.to_owned())),
+			arg => timerange = Some(parse_ago(arg)?),
+		}
+	}
+
+	if plotables.is_empty() {
+		return Err(Error::NotEnoughArguments);
+	}
+	let timerange = timerange.ok_or(Error::NotEnoughArguments)?;
+	Ok(PlotArgs {
+		timerange,
+		plotables,
+		scaling,
+		options,
+	})
+}
+
+fn select_data(
+	plotables: &[Plotable],
 	user: &User,
 ) -> Result<HashMap<DatasetId, Vec<FieldId>>, Error> {
 	//get timeseries_with_access for this user
 	let timeseries_with_access = &user.timeseries_with_access;
 
-	let mut selected_data = HashMap::new();
-	//check if user has access to the requested dataset
-	if let Some(fields_with_access) = timeseries_with_access.get(&set_id) {
-		let mut subbed_fields = Vec::with_capacity(field_ids.len());
-		for field_id in field_ids {
-			//prevent users requesting a field twice (this leads to an overflow later)
-			if subbed_fields.contains(&field_id) {
-				warn!("field was requested twice, ignoring duplicate");
-			} else if fields_with_access
-				.binary_search_by(|auth| auth.as_ref().cmp(&field_id))
-				.is_ok()
-			{
-				subbed_fields.push(field_id);
-			} else {
-				warn!("unauthorised field requested");
-				return Err(Error::NoAccessToField(field_id));
-			}
+	let mut selected_data: HashMap<DatasetId, Vec<FieldId>> = HashMap::new();
+	for plotable in plotables {
+		//check if user has access to the requested dataset
+		let fields_with_access = timeseries_with_access.get(&plotable.set_id).ok_or_else(|| {
+			warn!("no access to dataset");
+			Error::NoAccessToDataSet(plotable.set_id)
+		})?;
+		if fields_with_access
+			.binary_search_by(|auth| auth.as_ref().cmp(&plotable.field_id))
+			.is_err()
+		{
+			warn!("unauthorised field requested");
+			return Err(Error::NoAccessToField(plotable.field_id));
+		}
+
+		let subbed_fields = selected_data.entry(plotable.set_id).or_default();
+		//prevent reading a field twice (this leads to an overflow later)
+		if subbed_fields.contains(&plotable.field_id) {
+			warn!("field was requested twice, ignoring duplicate");
+		} else {
+			subbed_fields.push(plotable.field_id);
 		}
-		selected_data.insert(set_id, subbed_fields);
-	} else {
-		warn!("no access to dataset");
-		return Err(Error::NoAccessToDataSet(set_id));
 	}
 	Ok(selected_data)
 }
@@ -355,8 +724,8 @@ fn read_data(
 	selected_data: (DatasetId, Vec<FieldId>),
 	data: &Arc<RwLock<Data>>,
 	timerange: (DateTime<Utc>, DateTime<Utc>),
-) -> Result<PlotData, Error> {
-	let max_plot_points = 1000;
+	max_plot_points: usize,
+) -> Result<(Vec<i64>, Vec<f32>), Error> {
 	let (dataset_id, field_ids) = selected_data;
 
 	let data_handle = data;
@@ -373,14 +742,66 @@ fn read_data(
 		.build()?;
 
 	sampler.sample_all()?; //TODO some sampling over a mean probably wise
-	let (x_shared, ys) = sampler.into_data();
+	Ok(sampler.into_data())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
 
-	//prepare metadata
-	let mut metadata = Vec::new();
-	for field_id in field_ids.iter().map(|id| *id) {
-		let field = &dataset.metadata.fields[field_id as usize];
-		metadata.push((field_id, field.name.to_owned()));
+	fn args(line: &str) -> Vec<String> {
+		line.split_whitespace().map(str::to_owned).collect()
 	}
 
-	Ok((x_shared, ys, metadata))
+	#[test]
+	fn several_plotables() {
+		let parsed = parse_plot_arguments(args("3_0 3_1 5_2@r 24h 0:30"), Tz::UTC).unwrap();
+		assert_eq!(parsed.plotables.len(), 3);
+		assert!(parsed.plotables[2].secondary);
+		assert_eq!(parsed.plotables[2].set_id, 5);
+		assert_eq!(parsed.scaling, Some((0.0, 30.0)));
+		let (from, to) = parsed.timerange;
+		assert_eq!((to - from).num_hours(), 24);
+	}
+
+	#[test]
+	fn explicit_range() {
+		let parsed =
+			parse_plot_arguments(args("3_0 2021-05-01..2021-05-02T12:00"), Tz::Europe__Amsterdam)
+				.unwrap();
+		let (from, to) = parsed.timerange;
+		assert_eq!(from.to_rfc3339(), "2021-04-30T22:00:00+00:00");
+		assert_eq!(to.to_rfc3339(), "2021-05-02T10:00:00+00:00");
+
+		assert!(parse_plot_arguments(args("3_0 2021-05-02..2021-05-01"), Tz::UTC).is_err());
+		assert!(parse_plot_arguments(args("3_0"), Tz::UTC).is_err());
+	}
+
+	#[test]
+	fn export_options() {
+		let parsed =
+			parse_plot_arguments(args("3_0 24h -svg -size 1200x600 -avg 1h -5:10"), Tz::UTC)
+				.unwrap();
+		assert_eq!(parsed.options.format, Format::Svg);
+		assert_eq!(parsed.options.dimensions, (1200, 600));
+		assert_eq!(parsed.options.moving_average, Some(3600));
+		assert_eq!(parsed.scaling, Some((-5.0, 10.0)));
+
+		assert!(parse_plot_arguments(args("3_0 24h -size 10x10"), Tz::UTC).is_err());
+		assert!(parse_plot_arguments(args("3_0 24h -bogus"), Tz::UTC).is_err());
+	}
+
+	#[test]
+	fn axes_by_magnitude() {
+		let line = |values: &[f32]| Line {
+			label: String::new(),
+			points: values.iter().map(|v| (0, *v)).collect(),
+			secondary: false,
+			band: Vec::new(),
+		};
+		let mut lines = vec![line(&[20.0, 25.0]), line(&[18.0]), line(&[1013.0])];
+		assign_axes(&mut lines);
+		let secondary: Vec<_> = lines.iter().map(|l| l.secondary).collect();
+		assert_eq!(secondary, vec![false, false, true]);
+	}
 }
@@ -0,0 +1,181 @@
+use regex::Regex;
+use std::collections::VecDeque;
+
+use super::Line;
+use crate::data_store::data_router::Alarm;
+use crate::data_store::DatasetId;
+use crate::database::AlarmId;
+use bitspec::FieldId;
+
+/// number of buckets the points are aggregated into for a min/max band
+pub const BUCKETS: usize = 200;
+
+/// replaces the points of the line by the mean of each bucket and fills
+/// the band with the minimum and maximum of each bucket
+pub fn bucket(line: &mut Line) {
+	let (first, last) = match (line.points.first(), line.points.last()) {
+		(Some(first), Some(last)) => (first.0, last.0),
+		_ => return,
+	};
+	let width = (last - first) / BUCKETS as i64 + 1;
+
+	let mut points = Vec::with_capacity(BUCKETS);
+	let mut band = Vec::with_capacity(BUCKETS);
+	let mut chunk_start = 0;
+	while chunk_start < line.points.len() {
+		let bucket = (line.points[chunk_start].0 - first) / width;
+		let chunk_len = line.points[chunk_start..]
+			.iter()
+			.take_while(|p| (p.0 - first) / width == bucket)
+			.count();
+		let chunk = &line.points[chunk_start..chunk_start + chunk_len];
+
+		let time = chunk[chunk.len() / 2].0;
+		let sum: f32 = chunk.iter().map(|p| p.1).sum();
+		let min = chunk.iter().map(|p| p.1).fold(f32::MAX, f32::min);
+		let max = chunk.iter().map(|p| p.1).fold(f32::MIN, f32::max);
+		points.push((time, sum / chunk.len() as f32));
+		band.push((time, min, max));
+		chunk_start += chunk_len;
+	}
+	line.points = points;
+	line.band = band;
+}
+
+/// average over the points within `seconds` before each point
+pub fn moving_average(line: &Line, seconds: i64) -> Line {
+	let mut window = VecDeque::new();
+	let mut sum = 0f32;
+	let mut points = Vec::with_capacity(line.points.len());
+	for &(time, value) in &line.points {
+		window.push_back((time, value));
+		sum += value;
+		while let Some(&(start, old)) = window.front() {
+			if start > time - seconds {
+				break;
+			}
+			window.pop_front();
+			sum -= old;
+		}
+		points.push((time, sum / window.len() as f32));
+	}
+
+	Line {
+		label: format!("{} (avg)", line.label),
+		points,
+		secondary: line.secondary,
+		band: Vec::new(),
+	}
+}
+
+/// a horizontal line at a value an alarm compares the field with
+pub struct Threshold {
+	pub value: f32,
+	pub secondary: bool,
+	pub label: String,
+}
+
+/// comparisons of a field with a constant in the alarms, such as 3_0 > 25
+pub fn thresholds(
+	alarms: &[(AlarmId, Alarm)],
+	set_id: DatasetId,
+	field_id: FieldId,
+	line: &Line,
+) -> Vec<Threshold> {
+	let field = format!("{}_{}", set_id, field_id);
+	let field_first =
+		Regex::new(r#"\b(\d+_\d+)\s*(<=|>=|<|>)\s*(-?\d+(?:\.\d+)?)\b"#).unwrap();
+	let value_first =
+		Regex::new(r#"(?:^|[\s(])(-?\d+(?:\.\d+)?)\s*(<=|>=|<|>)\s*(\d+_\d+)\b"#).unwrap();
+
+	let mut found = Vec::new();
+	for (_, alarm) in alarms {
+		for caps in field_first.captures_iter(&alarm.expression) {
+			if caps[1] == field {
+				found.push((caps[3].to_owned(), caps[2].to_owned()));
+			}
+		}
+		for caps in value_first.captures_iter(&alarm.expression) {
+			if caps[3] == field {
+				let flipped = match &caps[2] {
+					"<" => ">",
+					">" => "<",
+					"<=" => ">=",
+					_ => "<=",
+				};
+				found.push((caps[1].to_owned(), flipped.to_owned()));
+			}
+		}
+	}
+	found.sort();
+	found.dedup();
+
+	found
+		.into_iter()
+		.filter_map(|(value, op)| {
+			Some(Threshold {
+				value: value.parse().ok()?,
+				secondary: line.secondary,
+				label: format!("alarm: {} {} {}", line.label, op, value),
+			})
+		})
+		.collect()
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::data_store::data_router::NotifyVia;
+
+	fn line(points: Vec<(i64, f32)>) -> Line {
+		Line {
+			label: String::from("temp"),
+			points,
+			secondary: false,
+			band: Vec::new(),
+		}
+	}
+
+	#[test]
+	fn moving_average_over_window() {
+		let avg = moving_average(&line(vec![(0, 1.0), (10, 3.0), (20, 5.0), (30, 7.0)]), 15);
+		let values: Vec<f32> = avg.points.iter().map(|p| p.1).collect();
+		assert_eq!(values, vec![1.0, 2.0, 4.0, 6.0]);
+	}
+
+	#[test]
+	fn bucket_min_max() {
+		let points = (0..(BUCKETS as i64 * 4)).map(|t| (t, (t % 4) as f32)).collect();
+		let mut line = line(points);
+		bucket(&mut line);
+		assert_eq!(line.points.len(), BUCKETS);
+		assert_eq!(line.band[0], (2, 0.0, 3.0));
+		assert_eq!(line.points[0].1, 1.5);
+	}
+
+	#[test]
+	fn thresholds_from_alarms() {
+		let alarm = |expression: &str| Alarm {
+			expression: expression.to_owned(),
+			inv_expr: None,
+			weekday: None,
+			period: None,
+			message: None,
+			command: None,
+			timezone: chrono_tz::Tz::UTC,
+			notify: NotifyVia {
+				email: None,
+				telegram: None,
+				webhook: None,
+			},
+		};
+		let alarms = vec![
+			(1, alarm("3_0 > 25.5 && 3_1 < 3_0")),
+			(2, alarm("10 > 3_0")),
+			(3, alarm("4_0 > 2")),
+		];
+		let found = thresholds(&alarms, 3, 0, &line(Vec::new()));
+		let labels: Vec<&str> = found.iter().map(|t| t.label.as_str()).collect();
+		assert_eq!(labels, vec!["alarm: temp < 10", "alarm: temp > 25.5"]);
+	}
+}
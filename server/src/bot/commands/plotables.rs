@@ -1,6 +1,6 @@
 use crate::bot::{send_text_reply, Error};
 use crate::database::User;
-use telegram_bot::types::refs::ChatId;
+use crate::bot::backend::{Address, Backends};
 
 use crate::data_store::data_router::DataRouterState;
 use bitspec::FieldId;
@@ -8,10 +8,10 @@ use bitspec::FieldId;
 pub const USAGE: &str = "/plotables";
 pub const DESCRIPTION: &str = "shows all possible data input for the plot function";
 pub async fn send(
-	chat_id: ChatId,
+	chat: &Address,
 	user: &User,
 	state: &DataRouterState,
-	token: &str,
+	backends: &Backends,
 ) -> Result<(), Error> {
 	let mut text = String::default();
 	const HEADER: &str = "\n<plotable id> <plotable name>\n";
@@ -34,5 +34,5 @@ pub async fn send(
 	if text.is_empty() {
 		text.push_str("you have no plotables")
 	}
-	send_text_reply(chat_id, token, text).await
+	send_text_reply(chat, backends, text).await
 }
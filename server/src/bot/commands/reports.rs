@@ -0,0 +1,144 @@
+pub const USAGE: &str = "/report [add daily|weekly <day> <hh:mm> <command>|list|remove <id>]";
+pub const DESCRIPTION: &str = "runs a command such as /plot 3_0 24h on a schedule, \
+ times are in your timezone (see /timezone). Without arguments lists your reports";
+
+use chrono::{NaiveTime, Utc, Weekday};
+use error_level::ErrorLevel;
+
+use crate::bot::backend::{Address, Backends};
+use crate::data_store::data_router::DataRouterState;
+use crate::database::{Report, ReportDbError, ReportId, Schedule, User};
+
+use super::super::send_text_reply;
+use super::super::Error as botError;
+
+#[derive(ErrorLevel, thiserror::Error, Debug)]
+pub enum Error {
+	#[report(debug)]
+	#[error("Not enough arguments\nuse: {}", USAGE)]
+	NotEnoughArguments,
+	#[report(debug)]
+	#[error("\"{0}\" is not a schedule, use daily or weekly")]
+	UnknownSchedule(String),
+	#[report(debug)]
+	#[error("\"{0}\" is not a time, write it as hh:mm")]
+	InvalidTime(String),
+	#[report(debug)]
+	#[error("\"{0}\" is not a day of the week")]
+	InvalidWeekday(String),
+	#[report(debug)]
+	#[error("\"{0}\" is not a report id, see /report list")]
+	InvalidId(String),
+	#[report(debug)]
+	#[error("a report can not manage reports")]
+	RecursiveReport,
+	#[report(debug)]
+	#[error("unknown subcommand: {0}\nuse: {}", USAGE)]
+	UnknownSubcommand(String),
+	#[error("{0}")]
+	Db(#[from] ReportDbError),
+}
+
+fn parse_time(arg: Option<&str>) -> Result<NaiveTime, Error> {
+	let arg = arg.ok_or(Error::NotEnoughArguments)?;
+	NaiveTime::parse_from_str(arg, "%H:%M").map_err(|_| Error::InvalidTime(arg.to_owned()))
+}
+
+/// parses: daily 08:00 <command> or weekly mon 08:00 <command>
+fn parse_report(args: &str) -> Result<(Schedule, String), Error> {
+	let mut args = args.split_whitespace();
+	let schedule = match args.next().ok_or(Error::NotEnoughArguments)? {
+		"daily" => Schedule::Daily(parse_time(args.next())?),
+		"weekly" => {
+			let day = args.next().ok_or(Error::NotEnoughArguments)?;
+			let day: Weekday = day
+				.parse()
+				.map_err(|_| Error::InvalidWeekday(day.to_owned()))?;
+			Schedule::Weekly(day, parse_time(args.next())?)
+		}
+		other => return Err(Error::UnknownSchedule(other.to_owned())),
+	};
+
+	let command = args.collect::<Vec<_>>().join(" ");
+	if command.is_empty() {
+		return Err(Error::NotEnoughArguments);
+	}
+	if command.starts_with("/report") {
+		return Err(Error::RecursiveReport);
+	}
+	Ok((schedule, command))
+}
+
+fn format_list(reports: &[(ReportId, Report)]) -> String {
+	if reports.is_empty() {
+		return String::from("you have no reports");
+	}
+	let mut list = String::from("id: schedule, command\n");
+	for (id, report) in reports {
+		list.push_str(&format!("{}: {}, {}\n", id, report.schedule, report.command));
+	}
+	list
+}
+
+pub async fn handle(
+	chat: &Address,
+	backends: &Backends,
+	args: String,
+	user: &User,
+	state: &DataRouterState,
+) -> Result<(), botError> {
+	let args = args.trim_start();
+	let split = args.find(char::is_whitespace).unwrap_or_else(|| args.len());
+	let (subcommand, rest) = args.split_at(split);
+
+	let text = match subcommand {
+		"add" => {
+			let (schedule, command) = parse_report(rest)?;
+			let report = Report {
+				schedule,
+				command,
+				last_run: Utc::now(),
+			};
+			let id = state.report_db.add(user.id, &report).map_err(Error::from)?;
+			let next = schedule
+				.next_after(report.last_run, user.timezone)
+				.with_timezone(&user.timezone);
+			format!(
+				"added report {}, it first runs at {}",
+				id,
+				next.format("%Y-%m-%d %H:%M")
+			)
+		}
+		"remove" => {
+			let id = rest.trim();
+			let id = id.parse().map_err(|_| Error::InvalidId(id.to_owned()))?;
+			let report = state.report_db.remove(user.id, id).map_err(Error::from)?;
+			format!("removed report: {}, {}", report.schedule, report.command)
+		}
+		"list" | "" => format_list(&state.report_db.list_users_reports(user.id)),
+		other => return Err(Error::UnknownSubcommand(other.to_owned()).into()),
+	};
+	send_text_reply(chat, backends, text).await
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn parse_schedules() {
+		let (schedule, command) = parse_report("daily 08:00 /plot 3_0 24h").unwrap();
+		assert_eq!(schedule, Schedule::Daily(NaiveTime::from_hms(8, 0, 0)));
+		assert_eq!(command, "/plot 3_0 24h");
+
+		let (schedule, _) = parse_report("weekly mon 18:30 /show 3_0").unwrap();
+		assert_eq!(
+			schedule,
+			Schedule::Weekly(Weekday::Mon, NaiveTime::from_hms(18, 30, 0))
+		);
+
+		assert!(parse_report("daily 25:00 /show").is_err());
+		assert!(parse_report("daily 08:00").is_err());
+		assert!(parse_report("daily 08:00 /report list").is_err());
+	}
+}
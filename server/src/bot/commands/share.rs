@@ -0,0 +1,396 @@
+pub const USAGE_SHARE: &str = "/share <set_id> [<username> [fields] [read|owner]]";
+pub const DESCRIPTION_SHARE: &str = "gives another user access to fields of a dataset \
+ you own, fields are given as ids separated by commas: 0,2,3. Without fields all \
+ fields you own are shared, without read or owner the user can only read. Owners can \
+ share the fields further. Without a username lists who has access";
+pub const USAGE_UNSHARE: &str = "/unshare <set_id> <username> [fields]";
+pub const DESCRIPTION_UNSHARE: &str = "removes the access of a user to fields you own, \
+ without fields to all of them. Their alarms and error notifications on those fields \
+ are removed too. Access of other owners can not be changed";
+
+use error_level::ErrorLevel;
+
+use crate::bot::backend::{Address, Backends};
+use crate::data_store::data_router::{Alarm, DataRouterState, RemoveAlarm};
+use crate::data_store::error_router;
+use crate::data_store::{Authorisation, DatasetId};
+use crate::database::{AlarmDbError, User, UserDbError};
+use crate::httpserver;
+use bitspec::FieldId;
+
+use super::super::send_text_reply;
+use super::super::Error as botError;
+
+#[derive(ErrorLevel, thiserror::Error, Debug)]
+pub enum Error {
+	#[report(debug)]
+	#[error("Not enough arguments\nuse: {}", USAGE_SHARE)]
+	NotEnoughArguments,
+	#[report(debug)]
+	#[error("\"{0}\" is not a dataset id")]
+	InvalidSetId(String),
+	#[report(debug)]
+	#[error("\"{0}\" is not a list of field ids such as 0,2,3")]
+	InvalidFields(String),
+	#[report(debug)]
+	#[error("unexpected argument: {0}")]
+	UnknownArgument(String),
+	#[report(debug)]
+	#[error("you do not own any fields of dataset {0}")]
+	NotOwner(DatasetId),
+	#[report(debug)]
+	#[error("you do not own field {0}, only owners can share a field")]
+	NotOwnerOfField(FieldId),
+	#[report(debug)]
+	#[error("you can not change your own access")]
+	OwnAccess,
+	#[report(debug)]
+	#[error("{0} owns field {1}, you can not change their access to it")]
+	TargetOwns(String, FieldId),
+	#[error("{0}")]
+	Db(#[from] UserDbError),
+	#[error("{0}")]
+	AlarmDb(#[from] AlarmDbError),
+	#[report(error)]
+	#[error("Could not update notification settings")]
+	Unsubscribe(crate::error::DataserverError),
+}
+
+#[derive(Debug, PartialEq)]
+struct Args {
+	set_id: DatasetId,
+	username: Option<String>,
+	fields: Option<Vec<FieldId>>,
+	/// Some(true) for owner, Some(false) for read
+	owner: Option<bool>,
+}
+
+fn parse_args(args: &str) -> Result<Args, Error> {
+	let mut args = args.split_whitespace();
+	let set_id = args.next().ok_or(Error::NotEnoughArguments)?;
+	let set_id = set_id
+		.parse()
+		.map_err(|_| Error::InvalidSetId(set_id.to_owned()))?;
+	let mut parsed = Args {
+		set_id,
+		username: args.next().map(str::to_owned),
+		fields: None,
+		owner: None,
+	};
+
+	for arg in args {
+		match arg {
+			"read" | "owner" if parsed.owner.is_some() => {
+				return Err(Error::UnknownArgument(arg.to_owned()))
+			}
+			"read" => parsed.owner = Some(false),
+			"owner" => parsed.owner = Some(true),
+			_ if parsed.fields.is_some() => return Err(Error::UnknownArgument(arg.to_owned())),
+			_ => {
+				let fields = arg
+					.split(',')
+					.map(str::parse)
+					.collect::<Result<Vec<FieldId>, _>>()
+					.map_err(|_| Error::InvalidFields(arg.to_owned()))?;
+				parsed.fields = Some(fields);
+			}
+		}
+	}
+	Ok(parsed)
+}
+
+fn owned_fields(user: &User, set_id: DatasetId) -> Vec<FieldId> {
+	user.timeseries_with_access
+		.get(&set_id)
+		.into_iter()
+		.flatten()
+		.filter_map(|auth| match auth {
+			Authorisation::Owner(id) => Some(*id),
+			Authorisation::Reader(_) => None,
+		})
+		.collect()
+}
+
+/// the fields to change, all of them must be owned by the user
+fn checked_fields(args: &Args, user: &User) -> Result<Vec<FieldId>, Error> {
+	let owned = owned_fields(user, args.set_id);
+	if owned.is_empty() {
+		return Err(Error::NotOwner(args.set_id));
+	}
+	let fields = args.fields.clone().unwrap_or_else(|| owned.clone());
+	if let Some(field) = fields.iter().find(|field| !owned.contains(field)) {
+		return Err(Error::NotOwnerOfField(*field));
+	}
+	Ok(fields)
+}
+
+fn format_access(access: &[Authorisation]) -> String {
+	access
+		.iter()
+		.map(|auth| match auth {
+			Authorisation::Owner(id) => format!("{} (owner)", id),
+			Authorisation::Reader(id) => format!("{} (read)", id),
+		})
+		.collect::<Vec<_>>()
+		.join(", ")
+}
+
+fn list_access(set_id: DatasetId, user: &User, state: &DataRouterState) -> Result<String, Error> {
+	if owned_fields(user, set_id).is_empty() {
+		return Err(Error::NotOwner(set_id));
+	}
+	let mut text = format!("access to dataset {}:\n", set_id);
+	for other in state.user_db.iter() {
+		if let Some(access) = other.timeseries_with_access.get(&set_id) {
+			text.push_str(&format!("- {}: {}\n", other.name, format_access(access)));
+		}
+	}
+	Ok(text)
+}
+
+/// applies change to the access of the named user, stores it and updates
+/// their web sessions. Owners of the fields can not be changed by others
+async fn change_access(
+	username: &str,
+	user: &User,
+	set_id: DatasetId,
+	fields: &[FieldId],
+	state: &DataRouterState,
+	change: impl FnOnce(&mut User),
+) -> Result<User, Error> {
+	let target_id = state.db_lookup.by_name(username)?;
+	if target_id == user.id {
+		return Err(Error::OwnAccess);
+	}
+	let mut target = state.user_db.get_user(target_id)?;
+	if let Some(field) = owned_fields(&target, set_id)
+		.into_iter()
+		.find(|field| fields.contains(field))
+	{
+		return Err(Error::TargetOwns(target.name, field));
+	}
+	change(&mut target);
+	state.user_db.set_user(target.clone()).await?;
+	httpserver::refresh_sessions(state, &target);
+	Ok(target)
+}
+
+pub async fn share(
+	chat: &Address,
+	backends: &Backends,
+	args: String,
+	user: &User,
+	state: &DataRouterState,
+) -> Result<(), botError> {
+	let args = parse_args(&args)?;
+	let username = match &args.username {
+		Some(username) => username,
+		None => {
+			let text = list_access(args.set_id, user, state)?;
+			return send_text_reply(chat, backends, text).await;
+		}
+	};
+	let fields = checked_fields(&args, user)?;
+	let owner = args.owner.unwrap_or(false);
+
+	let target = change_access(username, user, args.set_id, &fields, state, |target| {
+		let access = target
+			.timeseries_with_access
+			.entry(args.set_id)
+			.or_default();
+		// authorisations compare by field only
+		access.retain(|auth| !fields.contains(auth.as_ref()));
+		access.extend(fields.iter().map(|id| {
+			if owner {
+				Authorisation::Owner(*id)
+			} else {
+				Authorisation::Reader(*id)
+			}
+		}));
+		access.sort_unstable();
+	})
+	.await?;
+
+	let access = &target.timeseries_with_access[&args.set_id];
+	let text = format!(
+		"{} now has access to dataset {}: {}",
+		target.name,
+		args.set_id,
+		format_access(access)
+	);
+	send_text_reply(chat, backends, text).await
+}
+
+pub async fn unshare(
+	chat: &Address,
+	backends: &Backends,
+	args: String,
+	user: &User,
+	state: &DataRouterState,
+) -> Result<(), botError> {
+	let args = parse_args(&args)?;
+	if args.owner.is_some() {
+		return Err(Error::UnknownArgument(String::from("read/owner")).into());
+	}
+	let username = args.username.as_ref().ok_or(Error::NotEnoughArguments)?;
+	let fields = checked_fields(&args, user)?;
+
+	let target = change_access(username, user, args.set_id, &fields, state, |target| {
+		if let Some(access) = target.timeseries_with_access.get_mut(&args.set_id) {
+			access.retain(|auth| !fields.contains(auth.as_ref()));
+			if access.is_empty() {
+				target.timeseries_with_access.remove(&args.set_id);
+			}
+		}
+	})
+	.await?;
+	let kept_set = target.timeseries_with_access.contains_key(&args.set_id);
+	let removed_alarms = remove_alarms(&target, args.set_id, &fields, kept_set, state).await?;
+	unsubscribe(&target, args.set_id, &fields, kept_set, state).await?;
+
+	let mut text = match target.timeseries_with_access.get(&args.set_id) {
+		Some(access) => format!(
+			"{} still has access to dataset {}: {}",
+			target.name,
+			args.set_id,
+			format_access(access)
+		),
+		None => format!("{} no longer has access to dataset {}", target.name, args.set_id),
+	};
+	if removed_alarms > 0 {
+		text.push_str(&format!(
+			"\nremoved {} of their alarms watching these fields",
+			removed_alarms
+		));
+	}
+	send_text_reply(chat, backends, text).await
+}
+
+/// true if the alarm reads any of the fields or, without access to the set
+/// left, anything from the set
+fn watches(alarm: &Alarm, set_id: DatasetId, fields: &[FieldId], kept_set: bool) -> bool {
+	if !kept_set && alarm.watched_sets().contains(&set_id) {
+		return true;
+	}
+	alarm
+		.watched_fields(set_id)
+		.iter()
+		.any(|field| fields.contains(field))
+}
+
+/// removes the alarms of target on fields they lost access to, returns how
+/// many were removed
+async fn remove_alarms(
+	target: &User,
+	set_id: DatasetId,
+	fields: &[FieldId],
+	kept_set: bool,
+	state: &DataRouterState,
+) -> Result<usize, Error> {
+	let alarms = state.alarm_db.list_users_alarms(target.id);
+	let mut removed = 0;
+	for (alarm_id, alarm) in alarms {
+		if !watches(&alarm, set_id, fields, kept_set) {
+			continue;
+		}
+		state.alarm_db.remove(target.id, alarm_id)?;
+		state
+			.data_router_addr
+			.send(RemoveAlarm {
+				sets: alarm.watched_sets(),
+				user_id: target.id,
+				alarm_id,
+			})
+			.await
+			.unwrap();
+		removed += 1;
+	}
+	Ok(removed)
+}
+
+/// stops error notifications for target on fields they lost access to
+async fn unsubscribe(
+	target: &User,
+	set_id: DatasetId,
+	fields: &[FieldId],
+	kept_set: bool,
+	state: &DataRouterState,
+) -> Result<(), Error> {
+	let whole_set = if kept_set {
+		None
+	} else {
+		Some(u8::max_value())
+	};
+	let keys = fields
+		.iter()
+		.copied()
+		.chain(whole_set)
+		.map(|field_id| error_router::to_field_specific_key(set_id, field_id))
+		.collect();
+	state
+		.error_router_addr
+		.send(error_router::Unsubscribe {
+			keys,
+			user_id: target.id,
+		})
+		.await
+		.unwrap()
+		.map_err(Error::Unsubscribe)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::data_store::data_router::NotifyVia;
+
+	#[test]
+	fn share_arguments() {
+		assert_eq!(
+			parse_args("3 alice 0,2 owner").unwrap(),
+			Args {
+				set_id: 3,
+				username: Some(String::from("alice")),
+				fields: Some(vec![0, 2]),
+				owner: Some(true),
+			}
+		);
+		assert_eq!(
+			parse_args("3 alice read").unwrap(),
+			Args {
+				set_id: 3,
+				username: Some(String::from("alice")),
+				fields: None,
+				owner: Some(false),
+			}
+		);
+		assert_eq!(parse_args("3").unwrap().username, None);
+		assert!(matches!(parse_args("x"), Err(Error::InvalidSetId(_))));
+		assert!(matches!(parse_args("3 alice 0,a"), Err(Error::InvalidFields(_))));
+		assert!(matches!(
+			parse_args("3 alice read owner"),
+			Err(Error::UnknownArgument(_))
+		));
+	}
+
+	#[test]
+	fn alarms_on_removed_fields() {
+		let alarm = |expression: &str| Alarm {
+			expression: expression.to_owned(),
+			inv_expr: None,
+			weekday: None,
+			period: None,
+			message: None,
+			command: None,
+			timezone: chrono_tz::Tz::UTC,
+			notify: NotifyVia {
+				email: None,
+				telegram: None,
+				webhook: None,
+			},
+		};
+		assert!(watches(&alarm("3_1 > 5 && 4_0 < 2"), 3, &[1], true));
+		assert!(!watches(&alarm("13_1 > 5 && 3_2 < 2"), 3, &[1], true));
+		assert!(!watches(&alarm("age_3 > 60"), 3, &[1], true));
+		assert!(watches(&alarm("age_3 > 60"), 3, &[1], false));
+	}
+}
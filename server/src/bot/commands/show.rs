@@ -1,14 +1,16 @@
-pub const USAGE: &str = "/show <plotable_id 1> ... <plotable_id n>";
-pub const DESCRIPTION: &str = "sends the current value(s) of the requested plotable(s)";
+pub const USAGE: &str = "/show <plotable_id 1> ... <plotable_id n>, plotables can also be \
+ given as dataset_name.field_name";
+pub const DESCRIPTION: &str = "sends the current value(s) of the requested plotable(s), \
+ without arguments a menu lets you pick what to show";
 
 use chrono::{DateTime, Utc};
 use std::collections::HashMap;
 
-use telegram_bot::types::refs::ChatId;
 use error_level::ErrorLevel;
 
+use crate::bot::backend::{Address, Backends};
 use crate::data_store::data_router::DataRouterState;
-use crate::data_store::DatasetId;
+use crate::data_store::{names, DatasetId};
 use crate::database::{User, UserDbError};
 use bitspec::FieldId;
 
@@ -37,6 +39,8 @@ pub enum Error {
     #[report(error)]
 	#[error("Error accessing dataset: {0}")]
 	DataSet(#[from] byteseries::Error),
+	#[error("{0}")]
+	Names(#[from] names::Error),
 }
 
 fn parse_args(args: String, user: &User) -> Result<Vec<(DatasetId, Vec<FieldId>)>, Error> {
@@ -90,13 +94,15 @@ fn parse_args(args: String, user: &User) -> Result<Vec<(DatasetId, Vec<FieldId>)
 }
 
 pub async fn send(
-	chat_id: ChatId,
+	chat: &Address,
 	state: &DataRouterState,
-	token: &str,
+	backends: &Backends,
 	args: String,
 	user: &User,
 ) -> Result<(), botError> {
 	let mut text = String::default();
+	let access = &user.timeseries_with_access;
+	let args = names::resolve(&args, &state.data.read().unwrap(), access).map_err(Error::from)?;
 	let dataset_fields = parse_args(args, user)?;
 	let datasets = &state.data.read().unwrap().sets;
 	for (dataset_id, field_ids) in dataset_fields.iter() {
@@ -107,9 +113,10 @@ pub async fn send(
 
 		let set_name = &set.metadata.name;
 		let time_since = format_to_duration(time);
+		let local_time = time.with_timezone(&user.timezone).format("%H:%M %Z");
 		text.push_str(&format!(
-			"dataset: {}\nlast data: {} ago\n",
-			set_name, time_since
+			"dataset: {}\nlast data: {} ago ({})\n",
+			set_name, time_since, local_time
 		));
 
 		for field in field_ids.iter().map(|id| &fields[*id as usize]) {
@@ -120,7 +127,7 @@ pub async fn send(
 		}
 	}
 
-	send_text_reply(chat_id, token, text).await?;
+	send_text_reply(chat, backends, text).await?;
 	Ok(())
 }
 
@@ -0,0 +1,292 @@
+pub const USAGE: &str = "/status";
+pub const DESCRIPTION: &str = "overview of every dataset you can access: the time \
+ since the last data, the latest values, open errors and the number of armed alarms. \
+ Datasets that stopped sending data are marked stale";
+
+use chrono::{DateTime, Utc};
+use std::collections::{HashMap, HashSet};
+
+use crate::bot::backend::{Address, Backends};
+use crate::data_store::data_router::{staleness_condition, DataRouterState};
+use crate::data_store::error_router::{IncidentInfo, ListIncidents};
+use crate::data_store::DatasetId;
+use crate::database::{AlarmId, User};
+use bitspec::FieldId;
+
+use super::super::send_text_reply;
+use super::super::Error as botError;
+use super::show::format_to_duration;
+
+/// seconds without data after which a dataset is stale, unless the user
+/// has a staleness alarm (age_<set> > <seconds>) for it
+const STALE_AFTER: i64 = 3600;
+
+/// number of alarms watching each dataset that can still go off, alarms
+/// that fired and wait to be re-enabled, are snoozed or watch a dataset in
+/// maintenance are not armed
+fn armed_alarms(
+	user: &User,
+	state: &DataRouterState,
+	now: DateTime<Utc>,
+) -> HashMap<DatasetId, usize> {
+	let mut armed = HashMap::new();
+	if state.alarm_db.mute().filter(|until| *until > now).is_some() {
+		return armed;
+	}
+	let snoozed: HashMap<Option<AlarmId>, _> = state
+		.alarm_db
+		.snoozes()
+		.filter(|(user_id, _, until)| *user_id == user.id && *until > now)
+		.map(|(_, alarm_id, until)| (alarm_id, until))
+		.collect();
+	if snoozed.contains_key(&None) {
+		return armed;
+	}
+	let in_maintenance: HashSet<DatasetId> = state
+		.alarm_db
+		.maintenance()
+		.filter(|(_, window)| window.from <= now && now < window.until)
+		.map(|(set_id, _)| set_id)
+		.collect();
+
+	for (alarm_id, alarm) in state.alarm_db.list_users_alarms(user.id) {
+		if snoozed.contains_key(&Some(alarm_id)) {
+			continue;
+		}
+		let fired = state
+			.alarm_db
+			.get_state(user.id, alarm_id)
+			.map(|state| state.inverted)
+			.unwrap_or(false);
+		if fired {
+			continue;
+		}
+		let sets = alarm.watched_sets();
+		if sets.iter().any(|set_id| in_maintenance.contains(set_id)) {
+			continue;
+		}
+		for set_id in sets {
+			*armed.entry(set_id).or_insert(0) += 1;
+		}
+	}
+	armed
+}
+
+fn stale_after(user: &User, state: &DataRouterState) -> HashMap<DatasetId, i64> {
+	state
+		.alarm_db
+		.list_users_alarms(user.id)
+		.into_iter()
+		.filter_map(|(_, alarm)| staleness_condition(&alarm.expression))
+		.map(|(set_id, seconds)| (set_id, seconds as i64))
+		.collect()
+}
+
+/// what is known of a dataset at the moment
+struct SetStatus<'a> {
+	name: &'a str,
+	/// time of the last data and the values the user may see
+	last: Option<(DateTime<Utc>, Vec<(String, f32)>)>,
+	stale_after: i64,
+	errors: &'a [IncidentInfo],
+	armed: usize,
+}
+
+fn format_set(status: SetStatus, now: DateTime<Utc>) -> String {
+	let mut text = String::default();
+	match status.last {
+		Some((time, values)) => {
+			if now.signed_duration_since(time).num_seconds() > status.stale_after {
+				text.push_str(&format!("⚠️ {} (stale)\n", status.name));
+			} else {
+				text.push_str(&format!("{}\n", status.name));
+			}
+			text.push_str(&format!("last data: {} ago\n", format_to_duration(time)));
+			for (field, value) in values {
+				text.push_str(&format!("\t-{}:\t{:.2}\n", field, value));
+			}
+		}
+		None => text.push_str(&format!("{}\nno data yet\n", status.name)),
+	}
+
+	if !status.errors.is_empty() {
+		text.push_str(&format!("open errors: {}\n", status.errors.len()));
+		for info in status.errors {
+			text.push_str(&format!("\t{}: {}\n", info.id, info.description));
+		}
+	}
+	text.push_str(&format!("armed alarms: {}\n\n", status.armed));
+	text
+}
+
+fn format(
+	user: &User,
+	state: &DataRouterState,
+	incidents: Vec<IncidentInfo>,
+	now: DateTime<Utc>,
+) -> String {
+	let armed = armed_alarms(user, state, now);
+	let stale_after = stale_after(user, state);
+	let mut errors: HashMap<DatasetId, Vec<IncidentInfo>> = HashMap::new();
+	for info in incidents {
+		errors.entry(info.dataset_id).or_default().push(info);
+	}
+
+	let mut set_ids: Vec<_> = user.timeseries_with_access.keys().copied().collect();
+	set_ids.sort_unstable();
+
+	let mut text = String::default();
+	let datasets = &state.data.read().unwrap().sets;
+	for set_id in set_ids {
+		let set = match datasets.get(&set_id) {
+			Some(set) => set,
+			None => continue,
+		};
+		let fields = &set.metadata.fields;
+		let last = set.timeseries.last_line_raw().ok().map(|(time, line)| {
+			let values = user.timeseries_with_access[&set_id]
+				.iter()
+				.filter_map(|f| fields.get(FieldId::from(f) as usize))
+				.map(|field| {
+					let value: f32 = field.decode(&line).into();
+					(field.name.to_string(), value)
+				})
+				.collect();
+			(time, values)
+		});
+		let status = SetStatus {
+			name: &set.metadata.name,
+			last,
+			stale_after: stale_after.get(&set_id).copied().unwrap_or(STALE_AFTER),
+			errors: errors.get(&set_id).map(Vec::as_slice).unwrap_or(&[]),
+			armed: armed.get(&set_id).copied().unwrap_or(0),
+		};
+		text.push_str(&format_set(status, now));
+	}
+
+	if text.is_empty() {
+		text.push_str("you have no datasets");
+	}
+	text
+}
+
+pub async fn send(
+	chat: &Address,
+	backends: &Backends,
+	user: &User,
+	state: &DataRouterState,
+) -> Result<(), botError> {
+	let incidents = state
+		.error_router_addr
+		.send(ListIncidents {
+			access: user.timeseries_with_access.clone(),
+			admin: false,
+			include_resolved: false,
+		})
+		.await
+		.unwrap();
+
+	let text = format(user, state, incidents, Utc::now());
+	send_text_reply(chat, backends, text).await
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::bot::backend::fake::Fake;
+	use crate::bot::tests::state;
+	use crate::data_store::data_router::{Alarm, Maintenance, NotifyVia};
+	use crate::data_store::error_router::incidents::{Incident, IncidentState};
+
+	fn alarm(expression: &str) -> Alarm {
+		Alarm {
+			expression: expression.to_owned(),
+			inv_expr: None,
+			weekday: None,
+			period: None,
+			message: None,
+			command: None,
+			timezone: chrono_tz::Tz::UTC,
+			notify: NotifyVia {
+				email: None,
+				telegram: None,
+				webhook: None,
+			},
+		}
+	}
+
+	#[test]
+	fn alarms_in_maintenance_are_not_armed() {
+		let state = state(Fake::default());
+		let alice = state.db_lookup.by_name("alice").unwrap();
+		let user = state.user_db.get_user(alice).unwrap();
+		state.alarm_db.add(&alarm("3_0 > 5"), alice).unwrap();
+		state
+			.alarm_db
+			.add(&alarm("3_1 > 5 || 4_0 > 5"), alice)
+			.unwrap();
+		state.alarm_db.add(&alarm("4_1 > 5"), alice).unwrap();
+
+		let now = Utc::now();
+		let armed = armed_alarms(&user, &state, now);
+		assert_eq!(armed[&3], 2);
+		assert_eq!(armed[&4], 2);
+
+		let window = Maintenance {
+			from: now - chrono::Duration::hours(1),
+			until: now + chrono::Duration::hours(1),
+		};
+		state.alarm_db.add_maintenance(4, &window).unwrap();
+		let armed = armed_alarms(&user, &state, now);
+		assert_eq!(armed[&3], 1);
+		assert_eq!(armed.get(&4), None);
+	}
+
+	#[test]
+	fn set_status() {
+		let now = Utc::now();
+		let status = |last, armed| SetStatus {
+			name: "kitchen",
+			last,
+			stale_after: STALE_AFTER,
+			errors: &[],
+			armed,
+		};
+
+		let recent = now - chrono::Duration::seconds(30);
+		let text = format_set(
+			status(Some((recent, vec![(String::from("temp"), 21.5)])), 2),
+			now,
+		);
+		assert!(text.starts_with("kitchen\n"));
+		assert!(text.contains("\t-temp:\t21.50\n"));
+		assert!(text.ends_with("armed alarms: 2\n\n"));
+
+		let old = now - chrono::Duration::seconds(STALE_AFTER + 1);
+		let text = format_set(status(Some((old, Vec::new())), 0), now);
+		assert!(text.starts_with("⚠️ kitchen (stale)\n"));
+
+		let text = format_set(status(None, 1), now);
+		assert_eq!(text, "kitchen\nno data yet\narmed alarms: 1\n\n");
+
+		let errors = [IncidentInfo {
+			id: String::from("3_0_20"),
+			dataset_id: 3,
+			description: String::from("sensor unplugged"),
+			incident: Incident {
+				state: IncidentState::Open,
+				first_seen: now,
+				last_seen: now,
+				occurrences: 1,
+			},
+		}];
+		let text = format_set(
+			SetStatus {
+				errors: &errors,
+				..status(None, 0)
+			},
+			now,
+		);
+		assert!(text.contains("open errors: 1\n\t3_0_20: sensor unplugged\n"));
+	}
+}
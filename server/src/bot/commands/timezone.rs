@@ -0,0 +1,54 @@
+pub const USAGE: &str = "/timezone [name]";
+pub const DESCRIPTION: &str = "shows or sets your timezone, used for alarms, plots \
+ and times in messages. Use the IANA name for example: Europe/Amsterdam";
+
+use chrono_tz::Tz;
+use error_level::ErrorLevel;
+
+use crate::bot::backend::{Address, Backends};
+use crate::data_store::data_router::DataRouterState;
+use crate::database::User;
+
+use super::super::send_text_reply;
+use super::super::Error as botError;
+
+#[derive(ErrorLevel, thiserror::Error, Debug)]
+pub enum Error {
+	#[report(debug)]
+	#[error("I do not know the timezone \"{0}\", use a name such as Europe/Amsterdam")]
+	UnknownTimezone(String),
+	#[error("could not update database during setting of timezone")]
+	DbError(crate::database::UserDbError),
+}
+
+pub async fn send(
+	chat: &Address,
+	state: &DataRouterState,
+	backends: &Backends,
+	args: String,
+	mut user: User,
+) -> Result<(), botError> {
+	let name = match args.split_whitespace().next() {
+		None => {
+			let text = format!("your timezone is: {}", user.timezone.name());
+			return send_text_reply(chat, backends, text).await;
+		}
+		Some(name) => name,
+	};
+
+	let timezone: Tz = name
+		.parse()
+		.map_err(|_| Error::UnknownTimezone(name.to_owned()))?;
+	user.timezone = timezone;
+	state
+		.user_db
+		.set_user(user)
+		.await
+		.map_err(Error::DbError)?;
+
+	let text = format!(
+		"timezone set to {}, alarms set from now on use this timezone",
+		timezone.name()
+	);
+	send_text_reply(chat, backends, text).await
+}
@@ -1,55 +1,68 @@
 use actix_web::http::StatusCode;
-use actix_web::web::{Bytes, Data, HttpResponse};
+use actix_web::web::{Bytes, Data, HttpResponse, Query};
 use actix_web::Responder;
 
-use log::{error, info, warn};
+use log::{error, warn};
+use serde::Deserialize;
 
-use telegram_bot::types::message::MessageKind;
-use telegram_bot::types::refs::{ChatId, UserId};
+use telegram_bot::types::callback_query::CallbackQuery;
+use telegram_bot::types::refs::{ChatId, UserId as TelegramUserId};
 use telegram_bot::types::update::Update;
 use telegram_bot::types::update::UpdateKind;
 
 use crate::data_store::data_router::DataRouterState;
-use crate::database::{User, UserDbError};
+use crate::data_store::error_router::{NewError, SystemError};
+use crate::database::{AlarmDbError, LinkDbError, ReportDbError, User, UserDbError, UserId};
 
+pub mod backend;
 pub mod commands;
 pub use commands::alarms;
+mod poll;
+mod scheduler;
+pub use poll::Poller;
+pub use scheduler::ReportScheduler;
 
+use backend::{Address, Backend, Backends, Incoming};
 use commands::plot;
-use commands::{alias, help, keyboard, plotables, show};
+use commands::{
+	alias, help, incidents, keyboard, link, menu, plotables, reports, share, show, status,
+	timezone,
+};
 use error_level::ErrorLevel;
 
-async fn handle_error(error: Error, chat_id: ChatId, token: &str) {
-    error.log_error();
+async fn handle_error(error: Error, chat: &Address, state: &DataRouterState) {
+	error.log_error();
+	if error.is_database_error() {
+		state
+			.error_router_addr
+			.do_send(NewError::system(SystemError::Database));
+	}
 	let error_message = error.to_string();
-	if let Err(error) = send_text_reply(chat_id, token, error_message).await {
+	if let Err(error) = send_text_reply(chat, &state.backends, error_message).await {
 		error!("Could not send text reply to user: {:?}", error);
 	}
 }
 
-const INT_ERR_TEXT: &str = "apologies, an internal error happend this has been reported and will be fixed as soon as possible";
 #[derive(ErrorLevel, thiserror::Error, Debug)]
 pub enum Error {
-	#[report(error)]
-    #[error("{}", INT_ERR_TEXT)]
-	HttpClient(#[from] reqwest::Error),
-	#[report(error)]
-	#[error("could not set up webhook for telegram bot")]
-	CouldNotSetWebhook,
-	#[report(warn)]
-	#[error("{}", INT_ERR_TEXT)]
-	InvalidServerResponse(reqwest::Response),
-	#[report(warn)]
-	#[error("{}", INT_ERR_TEXT)]
-	InvalidServerResponseBlocking(reqwest::blocking::Response),
-	#[report(no)]
-	#[error("sorry I can not understand your input")]
-	UnhandledUpdateKind,
-	#[report(no)]
-	#[error("sorry I can not understand your input")]
-	UnhandledMessageKind,
 	#[error("sorry I can not understand your input")]
 	BotDatabase(#[from] UserDbError),
+	#[error("{0}")]
+	LinkDatabase(#[from] LinkDbError),
+	#[report(no)]
+	#[error(
+		"your {0} account is not linked, generate a code on the settings page \
+		and send: /link <code>"
+	)]
+	NotLinked(&'static str),
+	#[report(no)]
+	#[error(
+		"this telegram account is not linked to an account, generate a code \
+		on the settings page and send: /link <code>"
+	)]
+	UnknownTelegram,
+	#[error("{0}")]
+	Backend(#[from] backend::Error),
 	#[report(no)]
 	#[error(
 		"your input: \"{0}\", is not a possible command or \
@@ -67,180 +80,488 @@ pub enum Error {
 	Alarm(#[from] alarms::Error),
 	#[error("{0}")]
 	Plot(#[from] plot::Error),
+	#[error("{0}")]
+	Incidents(#[from] incidents::Error),
+	#[error("{0}")]
+	Timezone(#[from] timezone::Error),
+	#[error("{0}")]
+	Report(#[from] reports::Error),
+	#[error("{0}")]
+	Menu(#[from] menu::Error),
+	#[error("{0}")]
+	Link(#[from] link::Error),
+	#[error("{0}")]
+	Share(#[from] share::Error),
 }
 
-fn to_string_and_ids(update: Update) -> Result<(String, ChatId, UserId), Error> {
-	if let UpdateKind::Message(message) = update.kind {
-		let chat_id = message.chat.id();
-		let user_id = message.from.id;
-		if let MessageKind::Text { data, entities: _ } = message.kind {
-			Ok((data, chat_id, user_id))
-		} else {
-			warn!("unhandled message kind");
-			Err(Error::UnhandledUpdateKind)
-		}
-	} else {
-		warn!("unhandled update from telegram: {:?}", update);
-		Err(Error::UnhandledMessageKind)
+impl Error {
+	fn is_database_error(&self) -> bool {
+		use UserDbError::DatabaseError as UserDb;
+		matches!(
+			self,
+			Error::BotDatabase(UserDb(_))
+				| Error::LinkDatabase(LinkDbError::DatabaseError(_))
+				| Error::Show(show::Error::BotDatabase(UserDb(_)))
+				| Error::Alias(alias::Error::DbError(UserDb(_)))
+				| Error::KeyBoard(keyboard::Error::Db(UserDb(_)))
+				| Error::Plot(plot::Error::BotDatabase(UserDb(_)))
+				| Error::Alarm(alarms::Error::Db(AlarmDbError::DatabaseError(_)))
+				| Error::Report(reports::Error::Db(ReportDbError::DatabaseError(_)))
+				| Error::Link(link::Error::UserDb(UserDb(_)))
+				| Error::Share(share::Error::Db(UserDb(_)))
+		)
 	}
 }
 
-fn resolve_alias(possible_alias: &str, user: &User) -> Result<Option<String>, Error> {
-	if let Some(alias) = user.aliases.get(possible_alias) {
-		Ok(Some(alias.to_string()))
-	} else {
-		Ok(None)
-	}
+fn telegram_sender(chat: &Address, sender: &str) -> Result<TelegramUserId, Error> {
+	sender
+		.parse()
+		.map(TelegramUserId::new)
+		.map_err(|_| Error::NotLinked(chat.backend()))
+}
+
+/// finds the user behind a message by its sender, in a shared room or
+/// topic only senders that linked their account can run commands
+fn user_for(chat: &Address, sender: &str, state: &DataRouterState) -> Result<User, Error> {
+	let db_id = match chat {
+		Address::Telegram(_) => {
+			let user_id = telegram_sender(chat, sender)?;
+			match state.db_lookup.by_telegram_id(&user_id) {
+				Err(UserDbError::TelegramUserNotInDb(_)) => return Err(Error::UnknownTelegram),
+				res => res?,
+			}
+		}
+		_ => state
+			.link_db
+			.sender(chat, sender)?
+			.ok_or_else(|| Error::NotLinked(chat.backend()))?,
+	};
+	Ok(state.user_db.get_user(db_id)?)
 }
 
 async fn handle_command(
-	mut text: String,
-	chat_id: ChatId,
-	user_id: UserId,
+	text: String,
+	chat: &Address,
+	sender: &str,
 	state: &DataRouterState,
 ) -> Result<(), Error> {
-	let token = &state.bot_token;
-	let db_id = state.db_lookup.by_telegram_id(&user_id)?;
-	let user = state.user_db.get_user(db_id)?;
+	// linking an account happens before the sender is known to us
+	if let Some(code) = link::code(&text) {
+		return link::redeem(code, chat, sender, state).await;
+	}
+	let user = user_for(chat, sender, state)?;
+	run_command(text, chat, user, state).await
+}
 
-	loop {
-		let split = text.find(char::is_whitespace);
-		let mut command = text;
-		let args = command.split_off(split.unwrap_or_else(|| command.len()));
-		match command.as_str() {
-			"/test" => {
-				send_text_reply(chat_id, token, "hi").await?;
-				break;
-			}
-			//TODO needs to use threadpool
-			"/plot" => {
-				plot::send(chat_id, state, token, args, &user).await?;
-				break;
-			}
-			"/help" => {
-				help::send(chat_id, &user, token).await?;
-				break;
-			}
-			"/plotables" => {
-				plotables::send(chat_id, &user, state, token).await?;
-				break;
-			}
-			"/show" => {
-				show::send(chat_id, state, token, args, &user).await?;
-				break;
-			}
-			"/keyboard" => {
-				keyboard::show(chat_id, token, user).await?;
-				break;
-			}
-			"/keyboard_add" => {
-				keyboard::add_button(chat_id, state, token, args, user).await?;
-				break;
-			}
-			"/keyboard_remove" => {
-				keyboard::remove_button(chat_id, state, token, args, user).await?;
-				break;
-			}
-			"/alarm" => {
-				alarms::handle(chat_id, token, args, user, state).await?;
-				break;
-			}
-			"/alias" => {
-				alias::send(chat_id, state, token, args, user).await?;
-				break;
-			}
-			&_ => {}
+/// runs the command of an alarm that went off or a scheduled report on
+/// behalf of its owner, the owners current access rights apply
+pub async fn run_command_as(text: String, chat: Address, owner: UserId, state: &DataRouterState) {
+	let res = match state.user_db.get_user(owner) {
+		Ok(user) => run_command(text, &chat, user, state).await,
+		Err(e) => Err(e.into()),
+	};
+	if let Err(error) = res {
+		handle_error(error, &chat, state).await;
+	}
+}
+
+/// the commands run_single handles, anything else is looked up as alias
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Command {
+	Test,
+	Plot,
+	Help,
+	Plotables,
+	Show,
+	Status,
+	Keyboard,
+	KeyboardAdd,
+	KeyboardRemove,
+	Alarm,
+	Alias,
+	Ack,
+	Errors,
+	Timezone,
+	Report,
+	Link,
+	Unlink,
+	Share,
+	Unshare,
+}
+
+const COMMANDS: &[(&str, Command)] = &[
+	("/test", Command::Test),
+	("/plot", Command::Plot),
+	("/help", Command::Help),
+	("/plotables", Command::Plotables),
+	("/show", Command::Show),
+	("/status", Command::Status),
+	("/keyboard", Command::Keyboard),
+	("/keyboard_add", Command::KeyboardAdd),
+	("/keyboard_remove", Command::KeyboardRemove),
+	("/alarm", Command::Alarm),
+	("/alias", Command::Alias),
+	("/ack", Command::Ack),
+	("/errors", Command::Errors),
+	("/timezone", Command::Timezone),
+	("/report", Command::Report),
+	("/link", Command::Link),
+	("/unlink", Command::Unlink),
+	("/share", Command::Share),
+	("/unshare", Command::Unshare),
+];
+
+impl Command {
+	fn parse(name: &str) -> Option<Self> {
+		COMMANDS
+			.iter()
+			.find(|(command, _)| *command == name)
+			.map(|(_, command)| *command)
+	}
+}
+
+/// true if name is a command, commands can not be replaced by aliases
+pub fn is_command(name: &str) -> bool {
+	Command::parse(name).is_some()
+}
+
+/// expands aliases then runs the resulting commands in order
+async fn run_command(
+	text: String,
+	chat: &Address,
+	mut user: User,
+	state: &DataRouterState,
+) -> Result<(), Error> {
+	let commands = alias::expand(&text, &user.aliases)?;
+	for (i, command) in commands.into_iter().enumerate() {
+		// an earlier command in the chain may have changed the user
+		if i > 0 {
+			user = state.user_db.get_user(user.id)?;
 		}
-		if let Some(alias_text) = resolve_alias(&command, &user)? {
-			text = alias_text; //FIXME //TODO allows loops in aliasses, thats fun right? (fix after fun)
-		} else {
+		run_single(command, chat, user.clone(), state).await?;
+	}
+	Ok(())
+}
+
+async fn run_single(
+	mut command: String,
+	chat: &Address,
+	user: User,
+	state: &DataRouterState,
+) -> Result<(), Error> {
+	let backends = &state.backends;
+
+	let split = command.find(char::is_whitespace);
+	let args = command.split_off(split.unwrap_or_else(|| command.len()));
+	let parsed = match Command::parse(&command) {
+		Some(parsed) => parsed,
+		None => {
 			warn!("no known command or alias: {:?}", &command);
 			return Err(Error::UnknownAlias(command));
 		}
+	};
+	match parsed {
+		Command::Test => {
+			send_text_reply(chat, backends, "hi").await?;
+		}
+		//TODO needs to use threadpool
+		Command::Plot if args.trim().is_empty() => {
+			menu::start(chat, backends, menu::Action::Plot, &user, state).await?;
+		}
+		Command::Plot => {
+			plot::send(chat, state, backends, args, &user).await?;
+		}
+		Command::Help => {
+			help::send(chat, &user, backends).await?;
+		}
+		Command::Plotables => {
+			plotables::send(chat, &user, state, backends).await?;
+		}
+		Command::Show if args.trim().is_empty() => {
+			menu::start(chat, backends, menu::Action::Show, &user, state).await?;
+		}
+		Command::Show => {
+			show::send(chat, state, backends, args, &user).await?;
+		}
+		Command::Status => {
+			status::send(chat, backends, &user, state).await?;
+		}
+		Command::Keyboard => {
+			keyboard::show(chat, backends, user).await?;
+		}
+		Command::KeyboardAdd => {
+			keyboard::add_button(chat, state, backends, args, user).await?;
+		}
+		Command::KeyboardRemove => {
+			keyboard::remove_button(chat, state, backends, args, user).await?;
+		}
+		Command::Alarm => {
+			alarms::handle(chat, backends, args, user, state).await?;
+		}
+		Command::Alias => {
+			alias::send(chat, state, backends, args, user).await?;
+		}
+		Command::Ack => {
+			incidents::ack(chat, backends, args, &user, state).await?;
+		}
+		Command::Errors => {
+			incidents::handle(chat, backends, args, &user, state).await?;
+		}
+		Command::Timezone => {
+			timezone::send(chat, state, backends, args, user).await?;
+		}
+		Command::Report => {
+			reports::handle(chat, backends, args, &user, state).await?;
+		}
+		Command::Link => {
+			link::link(chat, backends, args, &user, state).await?;
+		}
+		Command::Unlink => {
+			link::unlink(chat, backends, args, &user, state).await?;
+		}
+		Command::Share => {
+			share::share(chat, backends, args, &user, state).await?;
+		}
+		Command::Unshare => {
+			share::unshare(chat, backends, args, &user, state).await?;
+		}
+	}
+	Ok(())
+}
+
+/// a button of an inline keyboard was pressed, once the menu is done the
+/// command it build is run
+async fn handle_callback_query(
+	query: CallbackQuery,
+	chat: &Address,
+	user_id: TelegramUserId,
+	state: &DataRouterState,
+) -> Result<(), Error> {
+	let db_id = state.db_lookup.by_telegram_id(&user_id)?;
+	let user = state.user_db.get_user(db_id)?;
+	if let Some(command) = menu::callback(query, &user, state).await? {
+		run_command(command, chat, user, state).await?;
 	}
 	Ok(())
 }
 
+async fn handle_incoming(incoming: Incoming, state: &DataRouterState) {
+	let Incoming { chat, sender, text } = incoming;
+	if let Err(error) = handle_command(text, &chat, &sender, state).await {
+		handle_error(error, &chat, state).await;
+	}
+}
+
 async fn handle(update: Update, state: &DataRouterState) {
-	let token = &state.bot_token;
-	if let Ok((text, chat_id, user_id)) = to_string_and_ids(update) {
-		if let Err(error) = handle_command(text, chat_id, user_id, state).await {
-			handle_error(error, chat_id, token).await;
+	if let UpdateKind::CallbackQuery(query) = update.kind {
+		let (chat_id, _, user_id) = match menu::ids(&query) {
+			Ok(ids) => ids,
+			Err(e) => {
+				warn!("callback query without message: {:?}", e);
+				return;
+			}
+		};
+		let chat = Address::Telegram(chat_id);
+		if let Err(error) = handle_callback_query(query, &chat, user_id, state).await {
+			handle_error(error, &chat, state).await;
+		}
+	} else {
+		for incoming in state.backends.telegram.receive_update(update) {
+			handle_incoming(incoming, state).await;
 		}
 	}
 }
 
 pub async fn handle_webhook(state: Data<DataRouterState>, raw_update: Bytes) -> impl Responder {
-	let update: Update = serde_json::from_slice(&raw_update.to_vec()).unwrap();
+	let update: Update = match serde_json::from_slice(&raw_update) {
+		Ok(update) => update,
+		Err(e) => {
+			warn!("could not parse update from telegram: {:?}", e);
+			return HttpResponse::build(StatusCode::OK).body("{}");
+		}
+	};
 	handle(update, state.get_ref()).await;
 
 	HttpResponse::build(StatusCode::OK).body("{}")
 }
 
-pub async fn send_text_reply<T: Into<String>>(
-	chat_id: ChatId,
-	token: &str,
-	text: T,
-) -> Result<(), Error> {
-	//add as arg generic ToChatRef (should get from Update)
-	//TODO create a SendMessage, serialise it (use member function serialize)
-	//then use the HttpRequest fields, (url, method, and body) to send to telegram
-	let url = format!("https://api.telegram.org/bot{}/sendMessage", token);
-	let form = reqwest::multipart::Form::new()
-		.text("chat_id", chat_id.to_string())
-		.text("text", text.into());
-
-	let client = reqwest::Client::new();
-	let resp = client.post(&url).multipart(form).send().await?;
-	//https://stackoverflow.com/questions/57540455/error-blockingclientinfuturecontext-when-trying-to-make-a-request-from-within
-
-	if resp.status() != reqwest::StatusCode::OK {
-		Err(Error::InvalidServerResponse(resp))
-	} else {
-		info!("send message");
-		Ok(())
+#[derive(Deserialize)]
+pub struct MatrixAuth {
+	access_token: String,
+}
+
+/// transactions pushed by the matrix homeserver, always acknowledged so
+/// the homeserver does not retry them
+pub async fn handle_matrix(
+	state: Data<DataRouterState>,
+	auth: Query<MatrixAuth>,
+	body: Bytes,
+) -> impl Responder {
+	let matrix = match &state.backends.matrix {
+		Some(matrix) => matrix,
+		None => return HttpResponse::build(StatusCode::NOT_FOUND).body("{}"),
+	};
+	if !matrix.authorized(&auth.access_token) {
+		warn!("matrix transaction with invalid token");
+		return HttpResponse::build(StatusCode::FORBIDDEN)
+			.body(r#"{"errcode":"M_FORBIDDEN"}"#);
+	}
+	for incoming in matrix.receive(&body) {
+		handle_incoming(incoming, state.get_ref()).await;
 	}
+	HttpResponse::build(StatusCode::OK).body("{}")
+}
+
+/// where to reach a user, their telegram chat first followed by the
+/// chats they linked
+pub fn addresses(user: &User, state: &DataRouterState) -> Vec<Address> {
+	let telegram = user
+		.telegram_id
+		.map(|id| Address::Telegram(ChatId::new(id.into())));
+	telegram
+		.into_iter()
+		.chain(state.link_db.links(user.id))
+		.collect()
 }
 
-pub fn send_text_reply_blocking<T: Into<String>>(
-	chat_id: ChatId,
-	token: &str,
+pub async fn send_text_reply<T: Into<String>>(
+	chat: &Address,
+	backends: &Backends,
 	text: T,
 ) -> Result<(), Error> {
-	//add as arg generic ToChatRef (should get from Update)
-	//TODO create a SendMessage, serialise it (use member function serialize)
-	//then use the HttpRequest fields, (url, method, and body) to send to telegram
-	let url = format!("https://api.telegram.org/bot{}/sendMessage", token);
-	let form = reqwest::blocking::multipart::Form::new()
-		.text("chat_id", chat_id.to_string())
-		.text("text", text.into());
-
-	let client = reqwest::blocking::Client::new();
-	let resp = client.post(&url).multipart(form).send()?;
-	//https://stackoverflow.com/questions/57540455/error-blockingclientinfuturecontext-when-trying-to-make-a-request-from-within
-
-	if resp.status() != reqwest::StatusCode::OK {
-		Err(Error::InvalidServerResponseBlocking(resp))
-	} else {
-		info!("send message");
-		Ok(())
-	}
+	backends.send_text(chat, text).await?;
+	Ok(())
 }
 
-pub async fn set_webhook(domain: &str, token: &str, port: u16) -> Result<(), Error> {
-	let url = format!("https://api.telegram.org/bot{}/setWebhook", token);
-	let webhook_url = format!("{}:{}/{}", domain, port, token);
-    dbg!(&url,&webhook_url);
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::data_store::{self, data_router::DataRouter, error_router::ErrorRouter};
+	use crate::database::{
+		AlarmDatabase, LinkDatabase, PasswordDatabase, ReportDatabase, UserDatabase, UserLookup,
+	};
+	use crate::notify::webhook::{Poster, SecretKey};
+	use actix::Context;
+	use backend::{fake::Fake, telegram, Telegram};
+	use chrono::Utc;
+	use futures::executor::block_on;
+	use std::collections::HashMap;
+	use std::sync::atomic::AtomicUsize;
+	use std::sync::{Arc, RwLock};
 
-	let params = [("url", &webhook_url)];
-	let client = reqwest::Client::new();
-	let res = client.post(url.as_str()).form(&params).send().await?;
+	/// state with users alice and mallory, nothing that needs a running
+	/// actor system works
+	pub(crate) fn state(fake: Fake) -> DataRouterState {
+		let db = sled::Config::new().temporary(true).open().unwrap();
+		let user_db = UserDatabase::from_db(&db).unwrap();
+		block_on(user_db.new_user(String::from("alice"))).unwrap();
+		block_on(user_db.new_user(String::from("mallory"))).unwrap();
+		let dir = std::env::temp_dir().join(format!("dataserver_bot_{}", rand::random::<u64>()));
 
-	if res.status() != reqwest::StatusCode::OK {
-        dbg!(&res);
-		Err(Error::CouldNotSetWebhook)
-	} else {
-		info!("set webhook to: {}", webhook_url);
-		Ok(())
+		DataRouterState {
+			passw_db: PasswordDatabase::from_db(&db).unwrap(),
+			db_lookup: UserLookup::from_user_db(&user_db).unwrap(),
+			user_db,
+			alarm_db: AlarmDatabase::from_db(&db).unwrap(),
+			report_db: ReportDatabase::from_db(&db).unwrap(),
+			link_db: LinkDatabase::from_db(&db).unwrap(),
+			link_codes: link::LinkCodes::default(),
+			backends: Backends {
+				telegram: Telegram::new(String::from("token"), telegram::DEFAULT_API.to_owned()),
+				matrix: None,
+				ntfy: None,
+				fake: Some(fake),
+			},
+			admins: Vec::new(),
+			email: None,
+			webhooks: Poster::start(SecretKey::generate()),
+			data_router_addr: Context::<DataRouter>::new().address(),
+			error_router_addr: Context::<ErrorRouter>::new().address(),
+			data: Arc::new(RwLock::new(data_store::init(dir).unwrap())),
+			sessions: Arc::new(RwLock::new(HashMap::new())),
+			free_session_ids: Arc::new(AtomicUsize::new(0)),
+			free_ws_session_ids: Arc::new(AtomicUsize::new(0)),
+		}
+	}
+
+	/// handles a message formatted as: <fake chat id> <sender> <text>, returns
+	/// the last reply
+	fn send(line: &str, fake: &Fake, state: &DataRouterState) -> (Address, String) {
+		for incoming in fake.receive(line.as_bytes()) {
+			block_on(handle_incoming(incoming, state));
+		}
+		fake.texts().pop().unwrap()
+	}
+
+	fn code_in(text: &str) -> String {
+		let mut words = text.split_whitespace().skip_while(|word| *word != "/link");
+		words.nth(1).unwrap().to_owned()
+	}
+
+	#[test]
+	fn only_linked_senders_run_commands() {
+		let fake = Fake::default();
+		let state = state(fake.clone());
+		let alice = state.db_lookup.by_name("alice").unwrap();
+		let not_linked = Error::NotLinked("fake").to_string();
+
+		assert_eq!(send("1 @alice /test", &fake, &state).1, not_linked);
+		let code = state.link_codes.generate(alice, Utc::now());
+		send(&format!("1 @alice /link {}", code), &fake, &state);
+		assert_eq!(
+			state.link_db.sender(&Address::Fake(1), "@alice").unwrap(),
+			Some(alice)
+		);
+		assert_eq!(
+			send("1 @alice /test", &fake, &state),
+			(Address::Fake(1), String::from("hi"))
+		);
+
+		// a linked room does not make everyone in it alice
+		state.link_db.link(alice, Address::Fake(1)).unwrap();
+		assert_eq!(send("1 @mallory /test", &fake, &state).1, not_linked);
+
+		send("1 @alice /unlink", &fake, &state);
+		assert_eq!(send("1 @alice /test", &fake, &state).1, not_linked);
+	}
+
+	#[test]
+	fn links_chat_after_code_comes_back() {
+		let fake = Fake::default();
+		let state = state(fake.clone());
+		let alice = state.db_lookup.by_name("alice").unwrap();
+		let mallory = state.db_lookup.by_name("mallory").unwrap();
+		state
+			.link_db
+			.link_sender(alice, &Address::Fake(1), "@alice")
+			.unwrap();
+		state
+			.link_db
+			.link_sender(mallory, &Address::Fake(3), "@mallory")
+			.unwrap();
+
+		send("1 @alice /link fake 2", &fake, &state);
+		let (to, text) = fake.texts()[0].clone();
+		assert_eq!(to, Address::Fake(2));
+		assert_eq!(state.link_db.owner(&Address::Fake(2)).unwrap(), None);
+
+		// someone else reading the code can not use it
+		send(
+			&format!("3 @mallory /link {}", code_in(&text)),
+			&fake,
+			&state,
+		);
+		assert_eq!(state.link_db.owner(&Address::Fake(2)).unwrap(), None);
+
+		send("1 @alice /link fake 2", &fake, &state);
+		let (_, text) = fake
+			.texts()
+			.into_iter()
+			.rev()
+			.find(|(to, _)| *to == Address::Fake(2))
+			.unwrap();
+		let reply = send(&format!("1 @alice /link {}", code_in(&text)), &fake, &state);
+		assert_eq!(reply, (Address::Fake(1), String::from("linked fake 2")));
+		assert_eq!(state.link_db.owner(&Address::Fake(2)).unwrap(), Some(alice));
 	}
 }
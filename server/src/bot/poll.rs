@@ -0,0 +1,81 @@
+use std::time::Duration;
+
+use byteorder::{BigEndian, ByteOrder};
+use log::{error, info, warn};
+
+use crate::data_store::data_router::DataRouterState;
+
+use super::handle;
+
+/// seconds telegram keeps a getUpdates request open if there are no updates
+const POLL_TIMEOUT: u64 = 30;
+const RETRY_DELAY: Duration = Duration::from_secs(5);
+const OFFSET_KEY: &[u8] = b"offset";
+
+/// receives telegram updates by long polling, for servers telegram can not
+/// reach such as those behind NAT or without a public TLS endpoint
+pub struct Poller {
+	state: DataRouterState,
+	/// the next update to ask for, persisted so updates are not handled
+	/// twice after a restart
+	offset: sled::Tree,
+}
+
+impl Poller {
+	pub fn new(db: &sled::Db, state: DataRouterState) -> Result<Self, sled::Error> {
+		Ok(Self {
+			state,
+			offset: db.open_tree("telegram_poll")?,
+		})
+	}
+
+	fn offset(&self) -> i64 {
+		self.offset
+			.get(OFFSET_KEY)
+			.ok()
+			.flatten()
+			.map(|offset| BigEndian::read_i64(&offset))
+			.unwrap_or(0)
+	}
+
+	async fn set_offset(&self, offset: i64) -> Result<(), sled::Error> {
+		self.offset.insert(OFFSET_KEY, &offset.to_be_bytes())?;
+		// the database is not flushed periodically
+		self.offset.flush_async().await?;
+		Ok(())
+	}
+
+	/// handles updates until the program exits
+	pub async fn run(self) {
+		let telegram = &self.state.backends.telegram;
+		while let Err(e) = telegram.delete_webhook().await {
+			warn!("could not remove telegram webhook, retrying: {:?}", e);
+			actix::clock::sleep(RETRY_DELAY).await;
+		}
+		info!("long polling telegram for updates");
+
+		loop {
+			let updates = match telegram.get_updates(self.offset(), POLL_TIMEOUT).await {
+				Ok(updates) => updates,
+				Err(e) => {
+					warn!("could not get updates from telegram: {:?}", e);
+					actix::clock::sleep(RETRY_DELAY).await;
+					continue;
+				}
+			};
+
+			for (id, update) in updates {
+				// move past the update before handling it, a crash while
+				// handling means it is skipped instead of handled twice
+				if let Err(e) = self.set_offset(id + 1).await {
+					error!("could not store telegram update offset: {:?}", e);
+				}
+				// a slow command such as a plot should not hold up the rest
+				if let Some(update) = update {
+					let state = self.state.clone();
+					actix::spawn(async move { handle(update, &state).await });
+				}
+			}
+		}
+	}
+}
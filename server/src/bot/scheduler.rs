@@ -0,0 +1,71 @@
+use std::time::Duration;
+
+use actix::prelude::*;
+use chrono::Utc;
+use log::{error, warn};
+
+use crate::data_store::data_router::DataRouterState;
+use crate::database::UserDbError;
+
+use super::{addresses, run_command_as};
+
+const CHECK_INTERVAL: Duration = Duration::from_secs(30);
+
+/// runs the scheduled reports of all users once they are due
+pub struct ReportScheduler {
+	state: DataRouterState,
+}
+
+impl ReportScheduler {
+	pub fn new(state: DataRouterState) -> Self {
+		Self { state }
+	}
+
+	fn run_due(&self) {
+		let now = Utc::now();
+		for (owner, report_id, report) in self.state.report_db.iter() {
+			let user = match self.state.user_db.get_user(owner) {
+				Ok(user) => user,
+				Err(UserDbError::UserNotInDb(_)) => {
+					warn!("removing reports of removed user: {}", owner);
+					if let Err(e) = self.state.report_db.remove_user(owner) {
+						error!("could not remove reports: {:?}", e);
+					}
+					continue;
+				}
+				Err(e) => {
+					error!("could not look up owner of report: {:?}", e);
+					continue;
+				}
+			};
+			if !report.is_due(now, user.timezone) {
+				continue;
+			}
+			let address = match addresses(&user, &self.state).into_iter().next() {
+				Some(address) => address,
+				None => {
+					warn!("can not send report to user {} as they have no chat", owner);
+					continue;
+				}
+			};
+
+			// a report that was missed while the server was down runs once
+			if let Err(e) = self.state.report_db.set_last_run(owner, report_id, now) {
+				error!("could not update report, not running it: {:?}", e);
+				continue;
+			}
+			let state = self.state.clone();
+			actix::spawn(async move {
+				run_command_as(report.command, address, owner, &state).await;
+			});
+		}
+	}
+}
+
+impl Actor for ReportScheduler {
+	type Context = Context<Self>;
+
+	fn started(&mut self, ctx: &mut Context<Self>) {
+		ctx.run_interval(CHECK_INTERVAL, |act, _| act.run_due());
+	}
+}
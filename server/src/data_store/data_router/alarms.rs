@@ -1,5 +1,6 @@
 use actix::prelude::*;
-use chrono::offset::{FixedOffset, TimeZone};
+use chrono::offset::TimeZone;
+use chrono_tz::Tz;
 use chrono::{DateTime, Datelike, Timelike, Utc, Weekday};
 use evalexpr::{
 	self, build_operator_tree, error::EvalexprError::VariableIdentifierNotFound,
@@ -10,26 +11,33 @@ use error_level::ErrorLevel;
 use regex::Regex;
 use serde::{Deserialize, Serialize};
 use std::collections::{HashMap, HashSet};
-use std::time::{Duration, Instant};
+use std::time::Duration;
 use telegram_bot::types::refs::ChatId;
-use threadpool::ThreadPool;
 
-use super::{AlarmId, DataRouter, UserId};
-use crate::bot;
+use super::windows::{self, Window};
+use super::{AlarmId, DataRouter, DataRouterState, UserId};
+use crate::bot::{self, backend::{self, Address}};
+use crate::data_store::error_router::{NewError, SystemError};
 use crate::data_store::DatasetId;
+use crate::database::timezone;
+use crate::notify::webhook::{self, Webhook};
+use bitspec::FieldId;
+
+/// number of consecutive failed evaluations after which an alarm is
+/// reported as broken to the admins
+const MAX_FAILED_EVALUATIONS: u32 = 100;
 
 #[derive(ErrorLevel, Debug)]
 pub enum AlarmError {
-    #[report(warn)] //could just be telegram is down
-	CouldNotNotify(reqwest::Response),
+	#[report(warn)] //could just be telegram is down
+	CouldNotNotify(backend::Error),
+	#[report(error)]
+	RepeatedEvalFailure(String),
 }
 
-impl From<bot::Error> for AlarmError {
-	fn from(err: bot::Error) -> Self {
-		match err {
-			bot::Error::InvalidServerResponse(resp) => AlarmError::CouldNotNotify(resp),
-			_ => unreachable!(),
-		}
+impl From<backend::Error> for AlarmError {
+	fn from(err: backend::Error) -> Self {
+		AlarmError::CouldNotNotify(err)
 	}
 }
 
@@ -37,6 +45,7 @@ impl From<bot::Error> for AlarmError {
 pub struct NotifyVia {
 	pub email: Option<String>,
 	pub telegram: Option<ChatId>,
+	pub webhook: Option<Webhook>,
 }
 
 #[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
@@ -47,38 +56,158 @@ pub struct Alarm {
 	pub period: Option<Duration>,
 	pub message: Option<String>,
 	pub command: Option<String>,
-	pub tz_offset: i32, //in hours to the east
+	#[serde(with = "crate::database::timezone::serde_tz")]
+	pub timezone: Tz,
 	pub notify: NotifyVia,
 }
 
+/// layout of Alarm before timezones were stored by name
+#[derive(Deserialize)]
+pub struct LegacyAlarm {
+	expression: String,
+	inv_expr: Option<String>,
+	weekday: Option<HashSet<Weekday>>,
+	period: Option<Duration>,
+	message: Option<String>,
+	command: Option<String>,
+	tz_offset: i32, //in hours to the east
+	notify: (Option<String>, Option<ChatId>), //email and telegram
+}
+
+impl From<LegacyAlarm> for Alarm {
+	fn from(legacy: LegacyAlarm) -> Self {
+		Alarm {
+			expression: legacy.expression,
+			inv_expr: legacy.inv_expr,
+			weekday: legacy.weekday,
+			period: legacy.period,
+			message: legacy.message,
+			command: legacy.command,
+			timezone: timezone::from_legacy_offset(legacy.tz_offset),
+			notify: NotifyVia {
+				email: legacy.notify.0,
+				telegram: legacy.notify.1,
+				webhook: None,
+			},
+		}
+	}
+}
+
 impl Alarm {
 	///expression needs to be valid or this will panic
 	pub fn watched_sets(&self) -> Vec<DatasetId> {
-		let re: regex::Regex = Regex::new(r#"(\d+)_\d+"#).unwrap();
-		let sets = re
+		let re: regex::Regex = Regex::new(r#"(?:age_(\d+))|(?:(\d+)_\d+)"#).unwrap();
+		let mut sets: Vec<DatasetId> = re
 			.captures_iter(&self.expression)
-			.map(|caps| caps[1].parse().unwrap())
+			.map(|caps| caps.get(1).or_else(|| caps.get(2)).unwrap())
+			.map(|set| set.as_str().parse().unwrap())
 			.collect();
+		sets.sort_unstable();
+		sets.dedup();
 		sets
 	}
+
+	///expression needs to be valid or this will panic
+	pub fn watched_fields(&self, set_id: DatasetId) -> Vec<FieldId> {
+		let re: regex::Regex = Regex::new(r#"(\d+)_(\d+)"#).unwrap();
+		let mut fields: Vec<FieldId> = re
+			.captures_iter(&self.expression)
+			.filter(|caps| caps[1].parse::<DatasetId>().ok() == Some(set_id))
+			.map(|caps| caps[2].parse().unwrap())
+			.collect();
+		fields.sort_unstable();
+		fields.dedup();
+		fields
+	}
+}
+
+/// staleness alarms watch the age of a sets data in seconds, they are
+/// written as: age_<dataset id> > <seconds>
+pub fn staleness_condition(expression: &str) -> Option<(DatasetId, u64)> {
+	let re = Regex::new(r#"^age_(\d+) > (\d+)$"#).unwrap();
+	let caps = re.captures(expression)?;
+	Some((caps[1].parse().ok()?, caps[2].parse().ok()?))
+}
+
+/// describes an alarm going off (or being re-enabled when inverted)
+fn describe(expression: &str, inverted: bool) -> String {
+	if let Some((set_id, seconds)) = staleness_condition(expression) {
+		if inverted {
+			format!("data from dataset {} is coming in again", set_id)
+		} else {
+			format!(
+				"no data received from dataset {} for over {:?}",
+				set_id,
+				Duration::from_secs(seconds)
+			)
+		}
+	} else {
+		let expression = bot::alarms::format_time_human_readable(expression.to_owned());
+		if inverted {
+			format!("alarm re-enabled: {}", expression)
+		} else {
+			format!("alarm fired: {}", expression)
+		}
+	}
+}
+
+/// runtime state of an alarm, persisted so a restart does not re-arm alarms
+/// or reset their cooldown. An alarm with an inverse expression is disarmed
+/// (inverted) after it fires until the inverse expression holds.
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AlarmState {
+	pub last_fired: Option<DateTime<Utc>>,
+	pub inverted: bool,
+}
+
+/// an alarm going off (or being re-enabled if inverted) as stored in the
+/// alarm history, values holds the variables in the condition at that moment
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FiringRecord {
+	pub at: DateTime<Utc>,
+	pub inverted: bool,
+	pub expression: String,
+	pub values: Vec<(String, f64)>,
+}
+
+/// the variables in a (rewritten) expression, excluding the time
+fn variables(expression: &str) -> Vec<String> {
+	let re = Regex::new(r#"\b(?:[a-z]+__\d+_\d+__\d+|age_\d+|\d+_\d+)\b"#).unwrap();
+	let mut variables: Vec<String> = re
+		.find_iter(expression)
+		.map(|mat| mat.as_str().to_owned())
+		.collect();
+	variables.sort_unstable();
+	variables.dedup();
+	variables
 }
 
 pub struct CompiledAlarm {
+	owner: UserId,
 	expression: evalexpr::Node,
 	inv_expr: Option<evalexpr::Node>,
 	inverted: bool,
 
 	expr_string: String,
 	weekday: Option<HashSet<Weekday>>,
-	period: Option<(Duration, Instant)>,
+	period: Option<chrono::Duration>,
+	last_fired: Option<DateTime<Utc>>,
 	message: Option<String>,
 	command: Option<String>,
-	timezone: FixedOffset, //in hours to the east
+	timezone: Tz,
 	notify: NotifyVia,
+	failed_evaluations: u32,
+	watches_age: bool,
+	staleness: bool,
+	windows: Vec<Window>,
+	sets: Vec<DatasetId>,
+	variables: Vec<String>,
 }
 
-impl From<Alarm> for CompiledAlarm {
-	fn from(alarm: Alarm) -> Self {
+impl CompiledAlarm {
+	/// alarm needs to be valid (its expressions must parse) or this will panic
+	pub fn compile(alarm: Alarm, owner: UserId, state: AlarmState) -> Self {
+		let sets = alarm.watched_sets();
 		let Alarm {
 			expression,
 			inv_expr,
@@ -86,41 +215,95 @@ impl From<Alarm> for CompiledAlarm {
 			period,
 			message,
 			command,
-			tz_offset,
+			timezone,
 			notify,
 		} = alarm;
-		let timezone = FixedOffset::east(tz_offset * 3600);
 		let expr_string = expression;
-		let expression = build_operator_tree(&expr_string).unwrap();
-		let inv_expr = inv_expr.map(|expr| build_operator_tree(&expr).unwrap());
-		let period = period.map(|delay| (delay, Instant::now()));
+		let (rewritten, windows) = windows::rewrite(&expr_string).unwrap();
+		let expression = build_operator_tree(&rewritten).unwrap();
+		let mut variables = variables(&rewritten);
+		let inv_expr = inv_expr.map(|expr| {
+			let (rewritten, _) = windows::rewrite(&expr).unwrap();
+			variables.extend(self::variables(&rewritten));
+			build_operator_tree(&rewritten).unwrap()
+		});
+		variables.sort_unstable();
+		variables.dedup();
+		let period = period.map(|delay| chrono::Duration::from_std(delay).unwrap());
 
+		let watches_age = expr_string.contains("age_");
+		let staleness = staleness_condition(&expr_string).is_some();
 		CompiledAlarm {
+			owner,
 			expression,
+			inverted: state.inverted && inv_expr.is_some(),
 			inv_expr,
-			inverted: false,
 			expr_string,
 			weekday,
 			period,
+			last_fired: state.last_fired,
 			message,
 			command,
 			timezone,
 			notify,
+			failed_evaluations: 0,
+			watches_age,
+			staleness,
+			windows,
+			sets,
+			variables,
 		}
 	}
 }
 
+/// what needs to happen now an alarm went off
+pub struct Fired {
+	pub owner: UserId,
+	pub notify: NotifyVia,
+	pub message: Option<String>,
+	pub expression: String,
+	pub command: Option<String>,
+	pub inverted: bool,
+	pub record: FiringRecord,
+}
+
 impl CompiledAlarm {
+	/// true if the alarm needs to be evaluated periodically as it uses the age
+	/// of a sets data
+	pub fn watches_age(&self) -> bool {
+		self.watches_age
+	}
+
+	/// windowed functions used by the alarm, these need to be kept up to
+	/// date in the context
+	pub fn windows(&self) -> &[Window] {
+		&self.windows
+	}
+
+	pub fn sets(&self) -> &[DatasetId] {
+		&self.sets
+	}
+
+	pub fn state(&self) -> AlarmState {
+		AlarmState {
+			last_fired: self.last_fired,
+			inverted: self.inverted,
+		}
+	}
+
+	/// returns what to notify if the alarm went off, the alarms state
+	/// changed if it did
 	pub fn evalute(
 		&mut self,
 		context: &mut evalexpr::HashMapContext,
 		now: &DateTime<Utc>,
-		pool: &ThreadPool,
-		token: String,
-	) -> Result<(), AlarmError> {
-		if let Some((period, last)) = self.period {
-			if last.elapsed() < period {
-				return Ok(());
+	) -> Result<Option<Fired>, AlarmError> {
+		//the period is the minimal time between activations, a staleness
+		//alarm noticing data coming in again is not delayed by it
+		let recovering = self.staleness && self.inverted;
+		if let (Some(period), Some(last), false) = (self.period, self.last_fired, recovering) {
+			if *now - last < period {
+				return Ok(None);
 			}
 		}
 
@@ -128,7 +311,7 @@ impl CompiledAlarm {
 		let today_user_tz = now_user_tz.weekday();
 		if let Some(active_weekdays) = &self.weekday {
 			if !active_weekdays.contains(&today_user_tz) {
-				return Ok(());
+				return Ok(None);
 			}
 		}
 
@@ -145,73 +328,151 @@ impl CompiledAlarm {
 
 		match to_evaluate.eval_boolean_with_context(context) {
 			Ok(alarm_condition) => {
-				if alarm_condition {
-					let notify = self.notify.clone();
-					let message = self.message.clone();
-					let command = self.command.clone();
-					let expr_string = self.expr_string.clone();
-					let inverted = self.inverted;
-					pool.execute(move || {
-						sound_alarm(notify, message, expr_string, command, inverted, token);
-					});
-					if self.inv_expr.is_some() {
-						self.inverted = !self.inverted;
-					}
+				self.failed_evaluations = 0;
+				if !alarm_condition {
+					return Ok(None);
 				}
+				let values = self
+					.variables
+					.iter()
+					.filter_map(|var| {
+						let value = context.get_value(var)?.as_number().ok()?;
+						Some((windows::readable(var), value))
+					})
+					.collect();
+				let fired = Fired {
+					owner: self.owner,
+					notify: self.notify.clone(),
+					message: self.message.clone(),
+					expression: self.expr_string.clone(),
+					command: self.command.clone(),
+					inverted: self.inverted,
+					record: FiringRecord {
+						at: *now,
+						inverted: self.inverted,
+						expression: self.expr_string.clone(),
+						values,
+					},
+				};
+				if !recovering {
+					self.last_fired = Some(*now);
+				}
+				if self.inv_expr.is_some() {
+					self.inverted = !self.inverted;
+				}
+				Ok(Some(fired))
 			}
-			Err(error) => match error {
-				VariableIdentifierNotFound(_) => {
-					warn!("variable not found, normal shortly after startup");
-					//TODO if happens for long time warn user
+			Err(error) => {
+				match error {
+					VariableIdentifierNotFound(_) => {
+						warn!("variable not found, normal shortly after startup");
+					}
+					_ => {
+						error!("{:?}", error);
+					}
 				}
-				_ => {
-					error!("{:?}", error);
-					dbg!();
+				self.failed_evaluations += 1;
+				if self.failed_evaluations == MAX_FAILED_EVALUATIONS {
+					return Err(AlarmError::RepeatedEvalFailure(self.expr_string.clone()));
 				}
-			},
+				Ok(None)
+			}
 		}
-		Ok(())
 	}
 }
 
-//TODO //FIXME has to handle error without returning
-fn sound_alarm(
-	notify: NotifyVia,
-	message: Option<String>,
-	expression: String,
-	command: Option<String>,
-	inverted: bool,
-	token: String,
-) {
-	let to_send = if let Some(message) = &message {
-		message.to_owned()
-	} else if inverted {
-        format!(
-            "alarm re-enabled: {}",
-            bot::alarms::format_time_human_readable(expression)
-        )
-	} else {
-        format!(
-            "alarm fired: {}",
-            bot::alarms::format_time_human_readable(expression)
-        )
-	};
+fn text(message: &Option<String>, expression: &str, inverted: bool) -> String {
+	let recovering = inverted && staleness_condition(expression).is_some();
+	match message {
+		Some(message) if !recovering => message.to_owned(),
+		_ => describe(expression, inverted),
+	}
+}
+
+/// the text the owner is notified with
+pub fn notification_text(fired: &Fired) -> String {
+	text(&fired.message, &fired.expression, fired.inverted)
+}
+
+/// sends out the notifications for an alarm that went off, must be called
+/// from within the actix runtime as the alarms command is run on it
+pub fn sound_alarm(fired: Fired, state: &DataRouterState) {
+	let Fired {
+		owner,
+		notify,
+		message,
+		expression,
+		command,
+		inverted,
+		record,
+	} = fired;
+
+	let to_send = text(&message, &expression, inverted);
+	let expression = bot::alarms::format_time_human_readable(expression);
+
+	if let Some(hook) = notify.webhook {
+		let payload = webhook::Payload {
+			text: to_send.clone(),
+			time: record.at,
+			event: webhook::Event::Alarm {
+				expression: expression.clone(),
+				inverted,
+				values: record.values.into_iter().collect(),
+			},
+		};
+		state.webhooks.send(&hook, &payload);
+	}
 
-	if let Some(_email) = &notify.email {
-		todo!();
+	if let Some(address) = notify.email {
+		if let Some(mailer) = &state.email {
+			let time = Utc::now().to_rfc2822();
+			let vars = [
+				("message", to_send.as_str()),
+				("expression", expression.as_str()),
+				("time", time.as_str()),
+			];
+			mailer.send(address, &vars);
+		} else {
+			warn!("alarm should notify via email however no smtp server is configured");
+		}
 	}
-	if let Some(chat_id) = &notify.telegram {
-		if let Err(err) = bot::send_text_reply_blocking(*chat_id, &token, to_send) {
-			error!("could not notify client via telegram: {:?}", err);
+
+	// the chat the alarm was set from followed by the chats the owner linked
+	let addresses: Vec<Address> = notify
+		.telegram
+		.map(Address::Telegram)
+		.into_iter()
+		.chain(state.link_db.links(owner))
+		.collect();
+	let first = match addresses.first() {
+		Some(address) => address.clone(),
+		None => {
+			if command.is_some() {
+				warn!("alarm has a command but no chat to send the result to");
+			}
+			return;
 		}
+	};
 
-		if let Some(_command) = &command {
-			todo!();
-			//let user_id = ;
-			//let state = ;
-			//bot::handle_command(command, chat_id, user_id, state);
+	let state = state.clone();
+	actix::spawn(async move {
+		for address in &addresses {
+			if let Err(err) = state.backends.send_text(address, to_send.clone()).await {
+				error!("could not notify client via {}: {:?}", address, err);
+				if address.telegram().is_some() {
+					state
+						.error_router_addr
+						.do_send(NewError::system(SystemError::TelegramNotify));
+				}
+			}
 		}
-	}
+
+		//only run the command once the message has been send so it shows
+		//up below the alarm message
+		if let Some(command) = command {
+			bot::run_command_as(command, first, owner, &state).await;
+		}
+	});
 }
 
 #[derive(Message)]
@@ -223,20 +484,42 @@ pub struct AddAlarm {
 	pub sets: Vec<DatasetId>,
 }
 
+impl DataRouter {
+	fn insert_alarm(
+		&mut self,
+		alarm: &Alarm,
+		user_id: UserId,
+		alarm_id: AlarmId,
+		sets: &[DatasetId],
+	) {
+		for set_id in sets {
+			let list = self.alarms_by_set.entry(*set_id).or_insert_with(HashMap::new);
+			let alarm = CompiledAlarm::compile(alarm.clone(), user_id, AlarmState::default());
+			for window in alarm.windows() {
+				self.windows.register(window);
+			}
+			list.insert((user_id, alarm_id), alarm);
+		}
+	}
+
+	fn remove_alarm(&mut self, user_id: UserId, alarm_id: AlarmId, sets: &[DatasetId]) {
+		for set in sets {
+			if let Some(alarms) = self.alarms_by_set.get_mut(set) {
+				if let Some(alarm) = alarms.remove(&(user_id, alarm_id)) {
+					for window in alarm.windows() {
+						self.windows.unregister(window);
+					}
+				}
+			}
+		}
+	}
+}
+
 impl Handler<AddAlarm> for DataRouter {
 	type Result = Result<(), AlarmError>;
 
 	fn handle(&mut self, msg: AddAlarm, _: &mut Context<Self>) -> Self::Result {
-		for set_id in msg.sets {
-			let list = if let Some(list) = self.alarms_by_set.get_mut(&set_id) {
-				list
-			} else {
-				self.alarms_by_set.insert(set_id, HashMap::new());
-				self.alarms_by_set.get_mut(&set_id).unwrap()
-			};
-			let alarm: CompiledAlarm = msg.alarm.clone().into();
-			list.insert((msg.user_id, msg.alarm_id), alarm);
-		}
+		self.insert_alarm(&msg.alarm, msg.user_id, msg.alarm_id, &msg.sets);
 		Ok(())
 	}
 }
@@ -253,10 +536,44 @@ impl Handler<RemoveAlarm> for DataRouter {
 	type Result = ();
 
 	fn handle(&mut self, msg: RemoveAlarm, _: &mut Context<Self>) -> Self::Result {
-		for set in msg.sets {
-			if let Some(alarms) = self.alarms_by_set.get_mut(&set) {
-				alarms.remove(&(msg.user_id, msg.alarm_id));
-			}
-		}
+		self.remove_alarm(msg.user_id, msg.alarm_id, &msg.sets);
+	}
+}
+
+/// swaps an alarm for a new version, as this is handled in one go no data
+/// can come in while neither or both versions are active
+#[derive(Message)]
+#[rtype(result = "")]
+pub struct ReplaceAlarm {
+	pub alarm: Alarm,
+	pub user_id: UserId,
+	pub alarm_id: AlarmId,
+	pub old_sets: Vec<DatasetId>,
+	pub sets: Vec<DatasetId>,
+}
+
+impl Handler<ReplaceAlarm> for DataRouter {
+	type Result = ();
+
+	fn handle(&mut self, msg: ReplaceAlarm, _: &mut Context<Self>) -> Self::Result {
+		self.remove_alarm(msg.user_id, msg.alarm_id, &msg.old_sets);
+		self.insert_alarm(&msg.alarm, msg.user_id, msg.alarm_id, &msg.sets);
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn message_is_send_unless_data_comes_in_again() {
+		let message = Some(String::from("check the fridge"));
+		assert_eq!(text(&message, "3_0 > 5", false), "check the fridge");
+		assert_eq!(text(&message, "3_0 > 5", true), "check the fridge");
+		assert_eq!(text(&message, "age_3 > 60", false), "check the fridge");
+		assert_eq!(
+			text(&message, "age_3 > 60", true),
+			"data from dataset 3 is coming in again"
+		);
 	}
 }
@@ -0,0 +1,143 @@
+use bitspec::FieldId;
+use chrono::{DateTime, TimeZone, Utc};
+use error_level::ErrorLevel;
+use evalexpr::{Context as evalContext, HashMapContext};
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+
+use super::alarms::{Alarm, AlarmState, CompiledAlarm};
+use super::windows::Windows;
+use super::UserId;
+use crate::data_store::{Data, DatasetId, FieldDecoder};
+
+/// upper limit on the number of lines read per dataset
+const MAX_LINES: usize = 100_000;
+
+#[derive(ErrorLevel, thiserror::Error, Debug)]
+pub enum Error {
+	#[report(debug)]
+	#[error("There is no dataset: {0}")]
+	NoSuchSet(DatasetId),
+	#[report(error)]
+	#[error("Error getting data: {0}")]
+	DataSet(#[from] byteseries::Error),
+}
+
+/// a moment the alarm would have gone off, or been re-enabled if inverted
+#[derive(Debug, Clone)]
+pub struct Firing {
+	pub at: DateTime<Utc>,
+	pub inverted: bool,
+}
+
+struct Line {
+	timestamp: i64,
+	set_id: DatasetId,
+	values: Vec<(FieldId, f32)>,
+}
+
+fn read_lines(
+	sets: &[DatasetId],
+	data: &Arc<RwLock<Data>>,
+	from: DateTime<Utc>,
+	to: DateTime<Utc>,
+) -> Result<Vec<Line>, Error> {
+	let data = data.read().unwrap();
+	let mut lines = Vec::new();
+	for set_id in sets {
+		let dataset = data.sets.get(set_id).ok_or(Error::NoSuchSet(*set_id))?;
+		let fields = &dataset.metadata.fields;
+		let field_ids: Vec<FieldId> = (0..fields.len() as FieldId).collect();
+		let decoder = FieldDecoder::from_fields_and_id(fields, &field_ids);
+		let mut sampler = byteseries::new_sampler(&dataset.timeseries, decoder)
+			.start(from)
+			.stop(to)
+			.points(MAX_LINES)
+			.build()?;
+		sampler.sample_all()?;
+		let (timestamps, values) = sampler.into_data();
+
+		for (timestamp, line) in timestamps
+			.into_iter()
+			.zip(values.chunks(field_ids.len().max(1)))
+		{
+			lines.push(Line {
+				timestamp,
+				set_id: *set_id,
+				values: field_ids.iter().copied().zip(line.iter().copied()).collect(),
+			});
+		}
+	}
+	//stable sort keeps the order of lines within a set
+	lines.sort_by_key(|line| line.timestamp);
+	Ok(lines)
+}
+
+fn evaluate(
+	alarm: &mut CompiledAlarm,
+	context: &mut HashMapContext,
+	now: &DateTime<Utc>,
+	firings: &mut Vec<Firing>,
+) {
+	if let Ok(Some(fired)) = alarm.evalute(context, now) {
+		firings.push(Firing {
+			at: *now,
+			inverted: fired.inverted,
+		});
+	}
+}
+
+/// replays the stored data of the sets the alarm watches through the alarm
+/// logic. Staleness is checked whenever a line arrives, not periodically.
+pub fn backtest(
+	alarm: Alarm,
+	owner: UserId,
+	data: &Arc<RwLock<Data>>,
+	from: DateTime<Utc>,
+	to: DateTime<Utc>,
+) -> Result<Vec<Firing>, Error> {
+	let sets = alarm.watched_sets();
+	let lines = read_lines(&sets, data, from, to)?;
+
+	let mut alarm = CompiledAlarm::compile(alarm, owner, AlarmState::default());
+	let mut windows = Windows::default();
+	for window in alarm.windows() {
+		windows.register(window);
+	}
+
+	let mut context = HashMapContext::new();
+	let mut last_data: HashMap<DatasetId, DateTime<Utc>> = HashMap::new();
+	let mut firings = Vec::new();
+	for line in lines {
+		let now = Utc.timestamp(line.timestamp, 0);
+		//only evaluate once every watched set has data
+		let complete = last_data.len() == sets.len();
+
+		if alarm.watches_age() && complete {
+			for (set_id, last) in &last_data {
+				let age = (now - *last).num_seconds() as f64;
+				context
+					.set_value(format!("age_{}", set_id), age.into())
+					.unwrap();
+			}
+			evaluate(&mut alarm, &mut context, &now, &mut firings);
+		}
+
+		last_data.insert(line.set_id, now);
+		for (field_id, value) in line.values {
+			let value = value as f64;
+			context
+				.set_value(format!("{}_{}", line.set_id, field_id), value.into())
+				.unwrap();
+			windows.update(line.set_id, field_id, value, &now, &mut context);
+		}
+		context
+			.set_value(format!("age_{}", line.set_id), 0f64.into())
+			.unwrap();
+
+		if last_data.len() == sets.len() {
+			evaluate(&mut alarm, &mut context, &now, &mut firings);
+		}
+	}
+	Ok(firings)
+}
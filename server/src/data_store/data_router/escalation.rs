@@ -0,0 +1,185 @@
+use chrono::{DateTime, Utc};
+use log::{error, warn};
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+use super::{AlarmId, DataRouterState, UserId};
+use crate::bot;
+use crate::database::AlarmDbError;
+
+/// who to notify if nobody acknowledged the alarm `after` it went off
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct EscalationStep {
+	pub after: Duration,
+	pub users: Vec<UserId>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Acknowledged {
+	pub by: UserId,
+	pub name: String,
+	pub at: DateTime<Utc>,
+}
+
+/// an alarm with an escalation chain that went off, it escalates further
+/// along the chain until someone acknowledges it
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Escalation {
+	pub fired_at: DateTime<Utc>,
+	/// the notification the owner received
+	pub text: String,
+	/// number of steps of the chain that have been notified
+	pub notified: usize,
+	pub acknowledged: Option<Acknowledged>,
+}
+
+impl Escalation {
+	/// the owner and everyone notified so far
+	pub fn involved(&self, owner: UserId, chain: &[EscalationStep]) -> Vec<UserId> {
+		let mut users: Vec<UserId> = chain
+			.iter()
+			.take(self.notified)
+			.flat_map(|step| step.users.iter().copied())
+			.collect();
+		users.push(owner);
+		users.sort_unstable();
+		users.dedup();
+		users
+	}
+}
+
+/// id used to acknowledge an alarm of someone else: <owner id>_<alarm id>
+pub fn format_id(owner: UserId, alarm_id: AlarmId) -> String {
+	format!("{}_{}", owner, alarm_id)
+}
+
+pub fn parse_id(id: &str) -> Option<(UserId, AlarmId)> {
+	let mut parts = id.splitn(2, '_');
+	let owner = parts.next()?.parse().ok()?;
+	let alarm_id = parts.next()?.parse().ok()?;
+	Some((owner, alarm_id))
+}
+
+/// starts escalating an alarm that went off, once the condition clears
+/// (the inverse expression fires) the escalation stops
+pub fn on_fired(
+	state: &DataRouterState,
+	owner: UserId,
+	alarm_id: AlarmId,
+	inverted: bool,
+	text: String,
+	now: DateTime<Utc>,
+) -> Result<(), AlarmDbError> {
+	let db = &state.alarm_db;
+	if inverted {
+		return db.stop_escalation(owner, alarm_id);
+	}
+	if db.escalation_chain(owner, alarm_id)?.is_empty() {
+		return Ok(());
+	}
+	let escalation = Escalation {
+		fired_at: now,
+		text,
+		notified: 0,
+		acknowledged: None,
+	};
+	db.start_escalation(owner, alarm_id, &escalation)
+}
+
+/// number of steps after those already notified that are due, a step
+/// too far in the future to represent is never due
+fn due_steps(chain: &[EscalationStep], escalation: &Escalation, now: DateTime<Utc>) -> usize {
+	chain
+		.iter()
+		.skip(escalation.notified)
+		.take_while(|step| {
+			chrono::Duration::from_std(step.after)
+				.ok()
+				.and_then(|after| escalation.fired_at.checked_add_signed(after))
+				.map(|due| due <= now)
+				.unwrap_or(false)
+		})
+		.count()
+}
+
+/// notifies the next steps of every unacknowledged escalation that are due
+pub fn escalate(state: &DataRouterState) -> Result<(), AlarmDbError> {
+	let now = Utc::now();
+	let db = &state.alarm_db;
+	for (owner, alarm_id, escalation) in db.escalations() {
+		if escalation.acknowledged.is_some() {
+			continue;
+		}
+		let chain = db.escalation_chain(owner, alarm_id)?;
+		let due = &chain[escalation.notified..][..due_steps(&chain, &escalation, now)];
+		if due.is_empty() {
+			continue;
+		}
+
+		db.set_notified(owner, alarm_id, escalation.notified + due.len())?;
+		let minutes = (now - escalation.fired_at).num_minutes();
+		let text = format!(
+			"{}\n[nobody acknowledged this alarm for {} minutes, use /alarm ack {} to claim it]",
+			escalation.text,
+			minutes,
+			format_id(owner, alarm_id)
+		);
+		let users = due.iter().flat_map(|step| step.users.iter().copied()).collect();
+		notify_users(users, text, state);
+	}
+	Ok(())
+}
+
+/// sends the text to the telegram and linked chats of each user
+pub fn notify_users(users: Vec<UserId>, text: String, state: &DataRouterState) {
+	for user_id in users {
+		let addresses = match state.user_db.get_user(user_id) {
+			Ok(user) => bot::addresses(&user, state),
+			Err(e) => {
+				error!("could not look up user to escalate to: {:?}", e);
+				continue;
+			}
+		};
+		if addresses.is_empty() {
+			warn!("can not escalate to user {} as they have no chat", user_id);
+		}
+		for address in addresses {
+			let state = state.clone();
+			let text = text.clone();
+			actix::spawn(async move {
+				if let Err(err) = state.backends.send_text(&address, text).await {
+					error!("could not notify user of escalation via {}: {:?}", address, err);
+				}
+			});
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn steps_become_due_in_order() {
+		let step = |minutes, user| EscalationStep {
+			after: Duration::from_secs(minutes * 60),
+			users: vec![user],
+		};
+		let chain = vec![step(15, 2), step(60, 3), step(u64::MAX / 60, 4)];
+		let fired_at = Utc::now();
+		let mut escalation = Escalation {
+			fired_at,
+			text: String::from("fridge too warm"),
+			notified: 0,
+			acknowledged: None,
+		};
+
+		let at = |minutes| fired_at + chrono::Duration::minutes(minutes);
+		assert_eq!(due_steps(&chain, &escalation, at(10)), 0);
+		assert_eq!(due_steps(&chain, &escalation, at(15)), 1);
+		assert_eq!(due_steps(&chain, &escalation, at(90)), 2);
+		escalation.notified = 2;
+		assert_eq!(due_steps(&chain, &escalation, at(60 * 24 * 365)), 0);
+		assert_eq!(escalation.involved(1, &chain), vec![1, 2, 3]);
+	}
+}
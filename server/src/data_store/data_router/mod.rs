@@ -1,12 +1,12 @@
 use std::sync::atomic::AtomicUsize;
 use std::sync::{Arc, Mutex, RwLock};
+use std::time::Duration;
 
 use actix::prelude::*;
-use chrono::Utc;
+use chrono::{DateTime, Utc};
 use evalexpr::{Context as evalContext, HashMapContext};
-use log::{debug, trace};
+use log::{debug, error, trace};
 use error_level::ErrorLevel;
-use threadpool::ThreadPool;
 use bitspec::FixedLine;
 
 use std::collections::{HashMap, HashSet};
@@ -15,21 +15,45 @@ use super::error_router;
 use super::DatasetId;
 use super::Data;
 
+use crate::bot::backend::Backends;
+use crate::bot::commands::link::LinkCodes;
 use crate::database::{
-	AlarmDatabase, AlarmId, PasswordDatabase, UserDatabase, UserId, UserLookup,
+	AlarmDatabase, AlarmId, LinkDatabase, PasswordDatabase, ReportDatabase, UserDatabase, UserId,
+	UserLookup,
 };
 use crate::httpserver::Session;
+use crate::notify::{email, webhook};
 
 mod alarms;
-pub use alarms::{AddAlarm, Alarm, CompiledAlarm, NotifyVia, RemoveAlarm};
+mod backtest;
+mod escalation;
+mod silence;
+pub mod windows;
+pub use alarms::{
+	AddAlarm, Alarm, AlarmError, AlarmState, CompiledAlarm, FiringRecord, LegacyAlarm,
+	NotifyVia, RemoveAlarm, ReplaceAlarm, staleness_condition,
+};
+use alarms::sound_alarm;
+pub use backtest::{backtest, Error as BacktestError, Firing};
+pub use escalation::{
+	format_id as format_escalation_id, notify_users, parse_id as parse_escalation_id,
+	Acknowledged, Escalation, EscalationStep,
+};
+pub use silence::{Maintenance, SilencesChanged};
 
 #[derive(Clone)]
 pub struct DataRouterState {
 	pub passw_db: PasswordDatabase,
 	pub user_db: UserDatabase,
 	pub alarm_db: AlarmDatabase,
+	pub report_db: ReportDatabase,
+	pub link_db: LinkDatabase,
+	pub link_codes: LinkCodes,
 	pub db_lookup: UserLookup,
-	pub bot_token: String,
+	pub backends: Backends,
+	pub admins: Vec<UserId>,
+	pub email: Option<email::Mailer>,
+	pub webhooks: webhook::Poster,
 
 	pub data_router_addr: Addr<DataRouter>,
 	pub error_router_addr: Addr<error_router::ErrorRouter>,
@@ -46,14 +70,21 @@ pub struct DataRouter {
 	sessions: HashMap<ClientSessionId, Clientinfo>,
 	subs: HashMap<DatasetId, HashSet<ClientSessionId>>,
 	meta: HashMap<DatasetId, FixedLine>,
+	last_data: HashMap<DatasetId, DateTime<Utc>>,
 	alarms_by_set: HashMap<DatasetId, HashMap<(UserId, AlarmId), CompiledAlarm>>,
 	alarm_context: HashMapContext,
-	async_pool: ThreadPool,
-	bot_token: String,
+	windows: windows::Windows,
+	silences: silence::Silences,
+	state: DataRouterState,
 }
 
+/// how often alarms on the age of the data (staleness alarms) are checked
+const STALENESS_CHECK_INTERVAL: Duration = Duration::from_secs(10);
+/// how often escalations are checked for steps that are due
+const ESCALATION_CHECK_INTERVAL: Duration = Duration::from_secs(30);
+
 impl DataRouter {
-	fn update_context(&mut self, line: &[u8], set_id: &DatasetId) {
+	fn update_context(&mut self, line: &[u8], set_id: &DatasetId, now: &DateTime<Utc>) {
 		let meta = self.meta.get(set_id).unwrap();
 		for field in &meta.fields {
 			let value: f64 = field.decode(line).into();
@@ -61,16 +92,91 @@ impl DataRouter {
 			self.alarm_context
 				.set_value(name, value.into())
 				.unwrap();
+			self.windows
+				.update(*set_id, field.id, value, now, &mut self.alarm_context);
+		}
+		self.alarm_context
+			.set_value(format!("age_{}", set_id), 0f64.into())
+			.unwrap();
+	}
+
+	/// evaluates the alarms watching a set, if only_staleness is set only the
+	/// alarms on the age of the data are evaluated
+	fn check_alarms(&mut self, set_id: DatasetId, now: &DateTime<Utc>, only_staleness: bool) {
+		let alarms = match self.alarms_by_set.get_mut(&set_id) {
+			Some(alarms) => alarms,
+			None => return,
+		};
+
+		for ((user_id, alarm_id), alarm) in alarms.iter_mut() {
+			if only_staleness && !alarm.watches_age() {
+				continue;
+			}
+			if self.silences.silenced(*user_id, *alarm_id, alarm.sets(), now) {
+				continue;
+			}
+			match alarm.evalute(&mut self.alarm_context, now) {
+				Ok(Some(fired)) => {
+					let state = &self.state;
+					let db = &state.alarm_db;
+					let text = alarms::notification_text(&fired);
+					let res = db
+						.set_state(*user_id, *alarm_id, &alarm.state())
+						.and_then(|_| db.record_firing(*user_id, *alarm_id, &fired.record))
+						.and_then(|_| {
+							let (owner, inverted) = (*user_id, fired.inverted);
+							escalation::on_fired(state, owner, *alarm_id, inverted, text, *now)
+						});
+					if let Err(e) = res {
+						error!("could not persist alarm state, history or escalation: {:?}", e);
+						self.state.error_router_addr.do_send(
+							error_router::NewError::system(
+								error_router::SystemError::Database,
+							),
+						);
+					}
+					sound_alarm(fired, &self.state)
+				}
+				Ok(None) => (),
+				Err(e) => {
+					e.log_error();
+					if let AlarmError::RepeatedEvalFailure(_) = e {
+						self.state.error_router_addr.do_send(
+							error_router::NewError::system(
+								error_router::SystemError::AlarmEvaluation,
+							),
+						);
+					}
+				}
+			}
+		}
+	}
+
+	/// updates the age of the data of every set then evaluates the alarms
+	/// that watch those ages
+	fn check_staleness(&mut self) {
+		let now = Utc::now();
+		for (set_id, last) in &self.last_data {
+			let age = (now - *last).num_seconds() as f64;
+			self.alarm_context
+				.set_value(format!("age_{}", set_id), age.into())
+				.unwrap();
+		}
+
+		let sets: Vec<DatasetId> = self.alarms_by_set.keys().copied().collect();
+		for set_id in sets {
+			self.check_alarms(set_id, &now, true);
 		}
 	}
 
 	//TODO get full alarm Id from iter method
 	//finish insertion
-	pub fn new(data: &Arc<RwLock<Data>>, alarm_db: AlarmDatabase, bot_token: String) -> DataRouter {
+	pub fn new(state: DataRouterState) -> DataRouter {
 		type AlarmList = HashMap<(UserId, AlarmId), CompiledAlarm>;
 
 		//collect metadata on all datasets
-		let meta = data
+		let meta: HashMap<DatasetId, FixedLine> = state
+			.data
 			.read()
 			.unwrap()
 			.sets
@@ -80,9 +186,23 @@ impl DataRouter {
 
 		//read alarms from the database into lookup hashmap
 		let mut alarms_by_set: HashMap<DatasetId, AlarmList> = HashMap::new();
-		for (owner_id, alarm_id, alarm) in alarm_db.iter() {
+		let mut windows = windows::Windows::default();
+		for (owner_id, alarm_id, alarm) in state.alarm_db.iter() {
+			let alarm_state = state
+				.alarm_db
+				.get_state(owner_id, alarm_id)
+				.unwrap_or_default();
+			// stored before windows were bounded
+			if let Err(e) = windows::rewrite(&alarm.expression) {
+				error!("skipping alarm {} of user {}: {}", alarm_id, owner_id, e);
+				continue;
+			}
 			for set in alarm.watched_sets() {
-				let compiled_alarm = CompiledAlarm::from(alarm.clone());
+				let compiled_alarm =
+					CompiledAlarm::compile(alarm.clone(), owner_id, alarm_state.clone());
+				for window in compiled_alarm.windows() {
+					windows.register(window);
+				}
 				if let Some(list) = alarms_by_set.get_mut(&set) {
 					list.insert((owner_id, alarm_id), compiled_alarm);
 				} else {
@@ -93,14 +213,21 @@ impl DataRouter {
 			}
 		}
 
+		//the age of the data is counted from startup, we do not know when the
+		//last line was received
+		let now = Utc::now();
+		let last_data = meta.keys().map(|set_id| (*set_id, now)).collect();
+
 		DataRouter {
 			sessions: HashMap::new(),
 			subs: HashMap::new(),
-			bot_token,
 			meta,
+			last_data,
 			alarms_by_set,
 			alarm_context: HashMapContext::new(),
-			async_pool: ThreadPool::new(2),
+			windows,
+			silences: silence::Silences::load(&state.alarm_db),
+			state,
 		}
 	}
 }
@@ -120,22 +247,11 @@ impl Handler<NewData> for DataRouter {
 		let updated_dataset_id = msg.from_id;
 
 		//check all alarms that could go off
+		let now = Utc::now();
+		self.last_data.insert(updated_dataset_id, now);
 		if self.alarms_by_set.contains_key(&updated_dataset_id) {
-			let now = Utc::now();
-			self.update_context(&msg.line, &updated_dataset_id); //Opt:
-			if let Some(alarms) = self.alarms_by_set.get_mut(&updated_dataset_id) {
-				for alarm in alarms.values_mut() {
-					let res = alarm.evalute(
-						&mut self.alarm_context,
-						&now,
-						&self.async_pool,
-						self.bot_token.clone(),
-					);
-                    if let Err(e) = res {
-                        e.log_error();
-                    }
-				}
-			}
+			self.update_context(&msg.line, &updated_dataset_id, &now); //Opt:
+			self.check_alarms(updated_dataset_id, &now, false);
 		}
 
 		//get a list of clients connected to the datasource with new data
@@ -246,9 +362,18 @@ impl Actor for DataRouter {
 	/// with other actors.
 	type Context = Context<Self>;
 
-	fn started(&mut self, _ctx: &mut Context<Self>) {
+	fn started(&mut self, ctx: &mut Context<Self>) {
 		// start heartbeats otherwise server will disconnect after 10 seconds
 		dbg!("started datarouter");
+		ctx.run_interval(STALENESS_CHECK_INTERVAL, |act, _| act.check_staleness());
+		ctx.run_interval(ESCALATION_CHECK_INTERVAL, |act, _| {
+			if let Err(e) = escalation::escalate(&act.state) {
+				error!("could not escalate alarms: {:?}", e);
+				act.state
+					.error_router_addr
+					.do_send(error_router::NewError::system(error_router::SystemError::Database));
+			}
+		});
 	}
 }
 
@@ -0,0 +1,84 @@
+use actix::prelude::*;
+use chrono::{DateTime, Utc};
+use log::error;
+use std::collections::HashMap;
+
+use super::{AlarmId, DataRouter, UserId};
+use crate::data_store::DatasetId;
+use crate::database::AlarmDatabase;
+
+/// a scheduled period during which alarms on a dataset are not evaluated
+#[derive(Debug, Clone, PartialEq)]
+pub struct Maintenance {
+	pub from: DateTime<Utc>,
+	pub until: DateTime<Utc>,
+}
+
+/// in memory copy of the snoozes, mute and maintenance windows stored in
+/// the AlarmDatabase, expired entries are skipped when loading and expired
+/// maintenance windows are removed from the database
+#[derive(Default)]
+pub struct Silences {
+	alarms: HashMap<(UserId, AlarmId), DateTime<Utc>>,
+	users: HashMap<UserId, DateTime<Utc>>,
+	mute: Option<DateTime<Utc>>,
+	maintenance: HashMap<DatasetId, Vec<Maintenance>>,
+}
+
+impl Silences {
+	pub fn load(db: &AlarmDatabase) -> Self {
+		let now = Utc::now();
+		let mut silences = Silences::default();
+		for (user_id, alarm_id, until) in db.snoozes().filter(|s| s.2 > now) {
+			if let Some(alarm_id) = alarm_id {
+				silences.alarms.insert((user_id, alarm_id), until);
+			} else {
+				silences.users.insert(user_id, until);
+			}
+		}
+		silences.mute = db.mute().filter(|until| *until > now);
+		if let Err(e) = db.purge_maintenance(&now) {
+			error!("could not remove expired maintenance windows: {:?}", e);
+		}
+		for (set_id, window) in db.maintenance().filter(|m| m.1.until > now) {
+			silences.maintenance.entry(set_id).or_default().push(window);
+		}
+		silences
+	}
+
+	/// alarms that are silenced are not evaluated at all, once the silence
+	/// ends they pick up where they left off
+	pub fn silenced(
+		&self,
+		user_id: UserId,
+		alarm_id: AlarmId,
+		sets: &[DatasetId],
+		now: &DateTime<Utc>,
+	) -> bool {
+		let active = |until: Option<&DateTime<Utc>>| until.map(|u| u > now).unwrap_or(false);
+		if active(self.mute.as_ref())
+			|| active(self.users.get(&user_id))
+			|| active(self.alarms.get(&(user_id, alarm_id)))
+		{
+			return true;
+		}
+
+		sets.iter()
+			.filter_map(|set_id| self.maintenance.get(set_id))
+			.flatten()
+			.any(|window| window.from <= *now && *now < window.until)
+	}
+}
+
+/// the snoozes, mute or maintenance windows in the database changed
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct SilencesChanged;
+
+impl Handler<SilencesChanged> for DataRouter {
+	type Result = ();
+
+	fn handle(&mut self, _: SilencesChanged, _: &mut Context<Self>) -> Self::Result {
+		self.silences = Silences::load(&self.state.alarm_db);
+	}
+}
@@ -0,0 +1,311 @@
+use bitspec::FieldId;
+use chrono::{DateTime, Utc};
+use evalexpr::{Context as evalContext, HashMapContext};
+use regex::{Captures, Regex};
+use std::collections::{HashMap, VecDeque};
+
+use crate::data_store::DatasetId;
+
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+	#[error("This \"{0}\" is not a valid window, use a number followed by s, m, h, d or w")]
+	InvalidDuration(String),
+	#[error("A window must be longer then zero seconds")]
+	EmptyWindow,
+	#[error("This \"{0}\" window is too long, windows can be at most 4 weeks")]
+	WindowTooLong(String),
+}
+
+/// longest window, we keep every value received during it in memory
+const MAX_WINDOW: u64 = 60 * 60 * 24 * 7 * 4;
+/// most values buffered per field, older values are dropped beyond this
+/// even if a window still covers them
+const MAX_BUFFERED: usize = 100_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Function {
+	Avg,
+	Delta,
+	Max,
+	Min,
+	/// change per second
+	Rate,
+}
+
+impl Function {
+	fn parse(name: &str) -> Self {
+		match name {
+			"avg" => Function::Avg,
+			"delta" => Function::Delta,
+			"max" => Function::Max,
+			"min" => Function::Min,
+			"rate" => Function::Rate,
+			_ => unreachable!("regex only matches known functions"),
+		}
+	}
+
+	fn name(&self) -> &'static str {
+		match self {
+			Function::Avg => "avg",
+			Function::Delta => "delta",
+			Function::Max => "max",
+			Function::Min => "min",
+			Function::Rate => "rate",
+		}
+	}
+}
+
+/// a function over the values a field had during the last seconds
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Window {
+	pub function: Function,
+	pub set_id: DatasetId,
+	pub field_id: FieldId,
+	pub seconds: u64,
+}
+
+impl Window {
+	/// name of the variable in the alarm context holding the windows value
+	pub fn variable(&self) -> String {
+		format!(
+			"{}__{}_{}__{}",
+			self.function.name(),
+			self.set_id,
+			self.field_id,
+			self.seconds
+		)
+	}
+
+	fn apply(&self, values: &VecDeque<(DateTime<Utc>, f64)>, now: &DateTime<Utc>) -> f64 {
+		let start = *now - chrono::Duration::seconds(self.seconds as i64);
+		let mut in_window = values.iter().filter(|(time, _)| *time >= start);
+
+		let first = match in_window.next() {
+			Some(first) => *first,
+			None => return 0.0,
+		};
+		let (last, count, sum, max, min) = in_window.fold(
+			(first, 1usize, first.1, first.1, first.1),
+			|(_, count, sum, max, min), &(time, value)| {
+				(
+					(time, value),
+					count + 1,
+					sum + value,
+					max.max(value),
+					min.min(value),
+				)
+			},
+		);
+
+		match self.function {
+			Function::Avg => sum / count as f64,
+			Function::Max => max,
+			Function::Min => min,
+			Function::Delta => last.1 - first.1,
+			Function::Rate => {
+				let elapsed = (last.0 - first.0).num_milliseconds() as f64 / 1000.0;
+				if elapsed > 0.0 {
+					(last.1 - first.1) / elapsed
+				} else {
+					0.0
+				}
+			}
+		}
+	}
+}
+
+/// turns the name of a windows variable back into the function call it
+/// replaced, other variables are returned as is
+pub fn readable(variable: &str) -> String {
+	let re = Regex::new(r#"^([a-z]+)__(\d+_\d+)__(\d+)$"#).unwrap();
+	match re.captures(variable) {
+		Some(caps) => format!("{}({}, {}s)", &caps[1], &caps[2], &caps[3]),
+		None => variable.to_owned(),
+	}
+}
+
+fn parse_duration(numb: &str, unit: &str) -> Result<u64, Error> {
+	let invalid = || Error::InvalidDuration(format!("{}{}", numb, unit));
+	let numb: u64 = numb.parse().map_err(|_| invalid())?;
+	let unit_seconds = match unit {
+		"s" => 1,
+		"m" => 60,
+		"h" => 60 * 60,
+		"d" => 60 * 60 * 24,
+		"w" => 60 * 60 * 24 * 7,
+		_ => return Err(invalid()),
+	};
+	let seconds = numb
+		.checked_mul(unit_seconds)
+		.filter(|seconds| *seconds <= MAX_WINDOW)
+		.ok_or_else(|| Error::WindowTooLong(format!("{}{}", numb, unit)))?;
+	if seconds == 0 {
+		return Err(Error::EmptyWindow);
+	}
+	Ok(seconds)
+}
+
+/// replaces functions such as avg(3_0, 1h) in an alarm expression by a
+/// variable holding the functions value, returns the rewritten expression
+/// and the windows that need to be kept up to date for it
+pub fn rewrite(expression: &str) -> Result<(String, Vec<Window>), Error> {
+	let re = Regex::new(
+		r#"\b(avg|delta|max|min|rate)\(\s*(\d+)_(\d+)\s*,\s*(\d+)\s*([a-z]+)\s*\)"#,
+	)
+	.unwrap();
+
+	let mut windows = Vec::new();
+	let mut error = None;
+	let rewritten = re.replace_all(expression, |caps: &Captures| {
+		let seconds = match parse_duration(&caps[4], &caps[5]) {
+			Ok(seconds) => seconds,
+			Err(e) => {
+				error = Some(e);
+				return String::new();
+			}
+		};
+		let window = Window {
+			function: Function::parse(&caps[1]),
+			set_id: caps[2].parse().unwrap(),
+			field_id: caps[3].parse().unwrap(),
+			seconds,
+		};
+		windows.push(window);
+		window.variable()
+	});
+
+	if let Some(e) = error {
+		return Err(e);
+	}
+	windows.dedup();
+	Ok((rewritten.to_string(), windows))
+}
+
+#[derive(Default)]
+struct Buffer {
+	values: VecDeque<(DateTime<Utc>, f64)>,
+	/// the windows on this field and the number of alarms using them
+	windows: HashMap<Window, usize>,
+}
+
+/// rolling buffers with the recent values of fields used in windowed
+/// functions, the buffers start empty, right after startup functions are
+/// evaluated over the data received since
+#[derive(Default)]
+pub struct Windows {
+	buffers: HashMap<(DatasetId, FieldId), Buffer>,
+}
+
+impl Windows {
+	pub fn register(&mut self, window: &Window) {
+		let buffer = self
+			.buffers
+			.entry((window.set_id, window.field_id))
+			.or_default();
+		*buffer.windows.entry(*window).or_default() += 1;
+	}
+
+	pub fn unregister(&mut self, window: &Window) {
+		let key = (window.set_id, window.field_id);
+		if let Some(buffer) = self.buffers.get_mut(&key) {
+			if let Some(users) = buffer.windows.get_mut(window) {
+				*users -= 1;
+				if *users == 0 {
+					buffer.windows.remove(window);
+				}
+			}
+			if buffer.windows.is_empty() {
+				self.buffers.remove(&key);
+			}
+		}
+	}
+
+	/// stores the new value and updates the variables of the windows on the
+	/// field in the context
+	pub fn update(
+		&mut self,
+		set_id: DatasetId,
+		field_id: FieldId,
+		value: f64,
+		now: &DateTime<Utc>,
+		context: &mut HashMapContext,
+	) {
+		let buffer = match self.buffers.get_mut(&(set_id, field_id)) {
+			Some(buffer) => buffer,
+			None => return,
+		};
+
+		let longest = buffer.windows.keys().map(|w| w.seconds).max().unwrap_or(0);
+		let keep_from = *now - chrono::Duration::seconds(longest as i64);
+		while let Some((time, _)) = buffer.values.front() {
+			if *time >= keep_from {
+				break;
+			}
+			buffer.values.pop_front();
+		}
+		if buffer.values.len() >= MAX_BUFFERED {
+			buffer.values.pop_front();
+		}
+		buffer.values.push_back((*now, value));
+
+		for window in buffer.windows.keys() {
+			let value = window.apply(&buffer.values, now);
+			context
+				.set_value(window.variable(), value.into())
+				.unwrap();
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn rewrite_functions() {
+		let (expr, windows) = rewrite("avg(3_0, 1h) > 70 && delta(3_1,10m) > 5").unwrap();
+		assert_eq!(expr, "avg__3_0__3600 > 70 && delta__3_1__600 > 5");
+		assert_eq!(windows.len(), 2);
+		assert_eq!(windows[1].function, Function::Delta);
+
+		assert!(rewrite("max(3_0, 1y) > 2").is_err());
+	}
+
+	#[test]
+	fn windows_are_bounded() {
+		assert!(rewrite("avg(3_0, 4w) > 1").is_ok());
+		assert!(matches!(
+			rewrite("avg(3_0, 5w) > 1"),
+			Err(Error::WindowTooLong(_))
+		));
+		assert!(matches!(
+			rewrite("avg(3_0, 99999999999999999w) > 1"),
+			Err(Error::WindowTooLong(_))
+		));
+		assert!(matches!(
+			rewrite("avg(3_0, 0s) > 1"),
+			Err(Error::EmptyWindow)
+		));
+	}
+
+	#[test]
+	fn window_functions() {
+		let now = Utc::now();
+		let values: VecDeque<_> = vec![(30, 1.0), (20, 4.0), (10, 2.0), (0, 5.0)]
+			.into_iter()
+			.map(|(ago, v)| (now - chrono::Duration::seconds(ago), v))
+			.collect();
+
+		let window = |function| Window {
+			function,
+			set_id: 1,
+			field_id: 0,
+			seconds: 20,
+		};
+		assert_eq!(window(Function::Avg).apply(&values, &now), 11.0 / 3.0);
+		assert_eq!(window(Function::Max).apply(&values, &now), 5.0);
+		assert_eq!(window(Function::Min).apply(&values, &now), 2.0);
+		assert_eq!(window(Function::Delta).apply(&values, &now), 1.0);
+		assert_eq!(window(Function::Rate).apply(&values, &now), 0.05);
+	}
+}
@@ -0,0 +1,238 @@
+use chrono::{offset::Utc, DateTime};
+use error_level::ErrorLevel;
+use serde::{Deserialize, Serialize};
+
+use super::{ErrorSpecificKey, NewError};
+use crate::data_store::{DatasetId, FieldId};
+use crate::database::{Access, UserId};
+
+#[derive(ErrorLevel, thiserror::Error, Debug)]
+pub enum Error {
+	#[report(debug)]
+	#[error("There is no incident with id: {0}")]
+	NotFound(String),
+	#[report(debug)]
+	#[error("Incident {0} is already resolved")]
+	AlreadyResolved(String),
+	#[report(error)]
+	#[error("An internal error occured")]
+	Database(#[from] sled::Error),
+	#[report(error)]
+	#[error("An internal error occured")]
+	Serialization(#[from] bincode::Error),
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum IncidentState {
+	Open,
+	Acknowledged {
+		user_id: UserId,
+		user_name: String,
+		at: DateTime<Utc>,
+	},
+	Resolved(DateTime<Utc>),
+	Reopened,
+}
+
+impl std::fmt::Display for IncidentState {
+	fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+		match self {
+			IncidentState::Open => write!(f, "open"),
+			IncidentState::Acknowledged { user_name, .. } => {
+				write!(f, "acknowledged by {}", user_name)
+			}
+			IncidentState::Resolved(_) => write!(f, "resolved"),
+			IncidentState::Reopened => write!(f, "reopened"),
+		}
+	}
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Incident {
+	pub state: IncidentState,
+	pub first_seen: DateTime<Utc>,
+	pub last_seen: DateTime<Utc>,
+	pub occurrences: u32,
+}
+
+/// every distinct error (dataset, field, error code) is tracked as an
+/// incident, stored under its ErrorSpecificKey
+pub struct Incidents {
+	tree: sled::Tree,
+}
+
+/// incidents are refered to by users as: dataset_id_field_id_error_code
+pub fn format_id(key: ErrorSpecificKey) -> String {
+	format!("{}_{}_{}", key >> 16, (key >> 8) & 0xFF, key & 0xFF)
+}
+
+pub fn parse_id(id: &str) -> Option<ErrorSpecificKey> {
+	let mut parts = id.trim().split('_');
+	let dataset_id: DatasetId = parts.next()?.parse().ok()?;
+	let field_id: u8 = parts.next()?.parse().ok()?;
+	let error_code: u8 = parts.next()?.parse().ok()?;
+	if parts.next().is_some() {
+		return None;
+	}
+
+	let mut key: ErrorSpecificKey = 0;
+	key |= (dataset_id as u32) << 16;
+	key |= (field_id as u32) << 8;
+	key |= error_code as u32;
+	Some(key)
+}
+
+pub fn dataset_of(key: ErrorSpecificKey) -> DatasetId {
+	(key >> 16) as DatasetId
+}
+
+pub fn field_of(key: ErrorSpecificKey) -> FieldId {
+	((key >> 8) & 0xFF) as FieldId
+}
+
+/// admins may see the system errors (dataset zero), others the errors of
+/// the fields they can access and those about a dataset they can access
+/// as a whole
+pub fn may_access(key: ErrorSpecificKey, access: &Access, admin: bool) -> bool {
+	let set_id = dataset_of(key);
+	if set_id == 0 {
+		return admin;
+	}
+	let field_id = field_of(key);
+	match access.get(&set_id) {
+		Some(fields) => {
+			field_id == u8::max_value() || fields.iter().any(|auth| *auth.as_ref() == field_id)
+		}
+		None => false,
+	}
+}
+
+impl Incidents {
+	pub fn load(db: &sled::Db) -> Result<Self, sled::Error> {
+		Ok(Self {
+			tree: db.open_tree("incidents")?,
+		})
+	}
+
+	fn get(&self, key: ErrorSpecificKey) -> Result<Option<Incident>, Error> {
+		if let Some(incident) = self.tree.get(key.to_be_bytes())? {
+			Ok(Some(bincode::deserialize(&incident)?))
+		} else {
+			Ok(None)
+		}
+	}
+
+	fn set(&self, key: ErrorSpecificKey, incident: &Incident) -> Result<(), Error> {
+		self.tree
+			.insert(key.to_be_bytes(), bincode::serialize(incident)?)?;
+		Ok(())
+	}
+
+	/// registers an occurrence of the error, returns the updated incident and
+	/// true if the incident was just opened or reopened
+	pub fn record(&mut self, msg: &NewError) -> Result<(Incident, bool), Error> {
+		let key = msg.to_error_specific_key();
+		let (incident, changed) = match self.get(key)? {
+			None => (
+				Incident {
+					state: IncidentState::Open,
+					first_seen: msg.timestamp,
+					last_seen: msg.timestamp,
+					occurrences: 1,
+				},
+				true,
+			),
+			Some(mut incident) => {
+				incident.last_seen = msg.timestamp;
+				incident.occurrences += 1;
+				let reopened = matches!(incident.state, IncidentState::Resolved(_));
+				if reopened {
+					incident.state = IncidentState::Reopened;
+				}
+				(incident, reopened)
+			}
+		};
+		self.set(key, &incident)?;
+		Ok((incident, changed))
+	}
+
+	pub fn acknowledge(
+		&mut self,
+		key: ErrorSpecificKey,
+		user_id: UserId,
+		user_name: String,
+	) -> Result<Incident, Error> {
+		let mut incident = self.get(key)?.ok_or_else(|| Error::NotFound(format_id(key)))?;
+		if let IncidentState::Resolved(_) = incident.state {
+			return Err(Error::AlreadyResolved(format_id(key)));
+		}
+		incident.state = IncidentState::Acknowledged {
+			user_id,
+			user_name,
+			at: Utc::now(),
+		};
+		self.set(key, &incident)?;
+		Ok(incident)
+	}
+
+	pub fn resolve(&mut self, key: ErrorSpecificKey) -> Result<Incident, Error> {
+		let mut incident = self.get(key)?.ok_or_else(|| Error::NotFound(format_id(key)))?;
+		if let IncidentState::Resolved(_) = incident.state {
+			return Err(Error::AlreadyResolved(format_id(key)));
+		}
+		incident.state = IncidentState::Resolved(Utc::now());
+		self.set(key, &incident)?;
+		Ok(incident)
+	}
+
+	/// all incidents for a dataset, using the dataset_id as most significant
+	/// part of the key allows for a range query
+	pub fn for_dataset(
+		&self,
+		dataset_id: DatasetId,
+	) -> impl Iterator<Item = (ErrorSpecificKey, Incident)> {
+		let key_begin = ((dataset_id as u32) << 16).to_be_bytes();
+		let key_end = ((dataset_id as u32) << 16 | 0xFFFF).to_be_bytes();
+		self.tree
+			.range(key_begin..=key_end)
+			.filter_map(Result::ok)
+			.filter_map(|(key, incident)| {
+				let mut bytes = [0u8; 4];
+				bytes.copy_from_slice(&key);
+				bincode::deserialize(&incident)
+					.ok()
+					.map(|incident| (u32::from_be_bytes(bytes), incident))
+			})
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn access_per_field() {
+		use crate::data_store::Authorisation;
+		let access: Access = vec![(3, vec![Authorisation::Reader(0)])]
+			.into_iter()
+			.collect();
+		assert!(may_access(parse_id("3_0_20").unwrap(), &access, false));
+		assert!(may_access(parse_id("3_255_20").unwrap(), &access, false));
+		assert!(!may_access(parse_id("3_1_20").unwrap(), &access, false));
+		assert!(!may_access(parse_id("4_0_20").unwrap(), &access, false));
+		assert!(!may_access(parse_id("0_255_2").unwrap(), &access, false));
+		assert!(may_access(parse_id("0_255_2").unwrap(), &access, true));
+	}
+
+	#[test]
+	fn incident_id_roundtrip() {
+		let key = parse_id("3_255_20").unwrap();
+		assert_eq!(key, (3 << 16) | (255 << 8) | 20);
+		assert_eq!(format_id(key), "3_255_20");
+		assert_eq!(dataset_of(key), 3);
+
+		assert!(parse_id("3_1").is_none());
+		assert!(parse_id("3_1_2_4").is_none());
+		assert!(parse_id("3_256_1").is_none());
+	}
+}
@@ -1,5 +1,5 @@
 use actix::prelude::*;
-use log::{debug, trace};
+use log::{debug, error, info, trace, warn};
 use std::sync::{Arc, RwLock};
 
 use bincode;
@@ -7,11 +7,18 @@ use chrono::{offset::Utc, DateTime};
 use serde::{Deserialize, Serialize};
 use std::collections::{HashMap, HashSet};
 
+use crate::bot::backend::{Address, Backends};
 use crate::data_store::{Data, DatasetId, FieldId};
+use crate::database::{Access, LinkDatabase, UserId};
 use crate::error::DataserverError;
+use crate::notify::{email, webhook::{self, Webhook}};
 
+pub mod incidents;
 mod sensor_errors;
+mod system_errors;
+use incidents::{Incident, IncidentState, Incidents};
 use sensor_errors::RemoteError;
+pub use system_errors::SystemError;
 
 /*
 	Errors for sensors and custom system
@@ -40,8 +47,12 @@ impl ReportedErrors {
 	}
 
 	//return true if this error was reported within a day, if it was not remembers the
-	//error as reported now
-	fn recently_reported(&mut self, msg: &NewError) -> Result<bool, DataserverError> {
+	//error as reported now. System errors are keyed by kind thus deduped per kind
+	fn recently_reported(
+		&mut self,
+		msg: &NewError,
+		now: DateTime<Utc>,
+	) -> Result<bool, DataserverError> {
 		//errors are stored based on 32bit key these are sorted as:
 		//-----3-------2--------------1-----------------0---------- (byte)
 		//-- dataset_id [u16]-- field_id [u16] -- error code [u8]--
@@ -50,18 +61,14 @@ impl ReportedErrors {
 		//to use ranges in database querys
 		let key = msg.to_error_specific_key().to_be_bytes();
 
-		if let Some(last_reported) = self.tree.get(&key).unwrap() {
+		if let Some(last_reported) = self.tree.get(&key)? {
 			let last_reported: DateTime<Utc> = bincode::deserialize(&last_reported)?;
-			if last_reported.signed_duration_since(Utc::now()) > chrono::Duration::days(1) {
-				self.tree.insert(&key, bincode::serialize(&Utc::now())?)?;
-				Ok(true)
-			} else {
-				Ok(false)
+			if now.signed_duration_since(last_reported) < chrono::Duration::days(1) {
+				return Ok(true);
 			}
-		} else {
-			self.tree.insert(&key, bincode::serialize(&Utc::now())?)?;
-			Ok(false)
 		}
+		self.tree.insert(&key, bincode::serialize(&now)?)?;
+		Ok(false)
 	}
 }
 
@@ -69,10 +76,14 @@ struct NotifyChannels {
 	tree: sled::Tree,
 }
 
-#[derive(Serialize, Deserialize)]
-struct NotifyOptions {
-	email: Option<String>,
-	telegram: Option<()>,
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NotifyOptions {
+	pub user_id: UserId,
+	pub email: Option<String>,
+	/// the chat the subscription was made from, the chats the user linked
+	/// are notified too
+	pub chat: Option<Address>,
+	pub webhook: Option<Webhook>,
 }
 
 impl NotifyChannels {
@@ -104,6 +115,52 @@ impl NotifyChannels {
 			Ok(None)
 		}
 	}
+
+	fn subscribe(
+		&mut self,
+		key: FieldSpecificKey,
+		options: NotifyOptions,
+	) -> Result<(), DataserverError> {
+		let key = key.to_be_bytes();
+		let mut to_notify: Vec<NotifyOptions> = if let Some(list) = self.tree.get(&key)? {
+			bincode::deserialize(&list)?
+		} else {
+			Vec::new()
+		};
+		to_notify.retain(|o| o.user_id != options.user_id);
+		to_notify.push(options);
+		self.tree.insert(&key, bincode::serialize(&to_notify)?)?;
+		Ok(())
+	}
+
+	/// everyone subscribed to key
+	fn subscribers(&self, key: FieldSpecificKey) -> Result<Vec<NotifyOptions>, DataserverError> {
+		match self.tree.get(&key.to_be_bytes())? {
+			Some(list) => Ok(bincode::deserialize(&list)?),
+			None => Ok(Vec::new()),
+		}
+	}
+
+	/// replaces everyone subscribed to key by to_notify
+	fn replace(
+		&mut self,
+		key: FieldSpecificKey,
+		to_notify: &[NotifyOptions],
+	) -> Result<(), DataserverError> {
+		let key = key.to_be_bytes();
+		self.tree.insert(&key, bincode::serialize(to_notify)?)?;
+		Ok(())
+	}
+
+	fn unsubscribe(&mut self, key: FieldSpecificKey, user_id: UserId) -> Result<(), DataserverError> {
+		let key = key.to_be_bytes();
+		if let Some(list) = self.tree.get(&key)? {
+			let mut to_notify: Vec<NotifyOptions> = bincode::deserialize(&list)?;
+			to_notify.retain(|o| o.user_id != user_id);
+			self.tree.insert(&key, bincode::serialize(&to_notify)?)?;
+		}
+		Ok(())
+	}
 }
 
 pub struct Clientinfo {
@@ -122,8 +179,13 @@ pub struct ErrorRouter {
 	clients_to_notify: NotifyChannels,     //keys = dataset_id+field_id
 	client_undisplayed_errors: sled::Tree, // display as soon as client loads/connects
 	reported_errors: ReportedErrors,
+	incidents: Incidents,
 
 	data: Arc<RwLock<Data>>,
+	backends: Backends,
+	link_db: LinkDatabase,
+	email: Option<email::Mailer>,
+	webhooks: webhook::Poster,
 }
 
 #[derive(Message, Clone)]
@@ -142,9 +204,17 @@ pub fn to_field_specific_key(dataset_id: DatasetId, field_id: FieldId) -> FieldS
 	key
 }
 
-type ErrorSpecificKey = u32;
+pub type ErrorSpecificKey = u32;
 pub type FieldSpecificKey = u32;
 impl NewError {
+	pub fn system(error: SystemError) -> Self {
+		NewError {
+			dataset_id: 0,
+			field_ids: vec![u8::max_value()],
+			error_code: error as ErrorCode,
+			timestamp: Utc::now(),
+		}
+	}
 	fn to_error_specific_key(&self) -> ErrorSpecificKey {
 		let mut key: ErrorSpecificKey = 0;
 		key |= (self.dataset_id as u32) << 16;
@@ -167,14 +237,14 @@ impl NewError {
 
 fn format_error_code(data: &Arc<RwLock<Data>>, msg: &NewError) -> Result<String, ()> {
 	//TODO add timestamp
-	let error = RemoteError::from(msg.error_code);
 	if msg.dataset_id == 0 {
 		return Ok(format!(
 			"{time} system error occured: {error}",
 			time = msg.timestamp,
-			error = error
+			error = SystemError::from(msg.error_code),
 		));
 	}
+	let error = RemoteError::from(msg.error_code);
 
 	if let Some(dataset) = data.read().unwrap().sets.get(&msg.dataset_id) {
 		let metadata = &dataset.metadata;
@@ -211,13 +281,34 @@ fn format_error_code(data: &Arc<RwLock<Data>>, msg: &NewError) -> Result<String,
 impl Handler<NewError> for ErrorRouter {
 	type Result = ();
 
-	fn handle(&mut self, msg: NewError, _: &mut Context<Self>) -> Self::Result {
-		if self.reported_errors.recently_reported(&msg).unwrap() {
-			return;
+	fn handle(&mut self, msg: NewError, ctx: &mut Context<Self>) -> Self::Result {
+		let error_msg = match format_error_code(&self.data, &msg) {
+			Ok(error_msg) => error_msg,
+			Err(()) => {
+				warn!(
+					"error {} for unknown dataset {} or field {:?}",
+					msg.error_code, msg.dataset_id, msg.field_ids
+				);
+				return;
+			}
+		};
+		let (incident, opened) = match self.incidents.record(&msg) {
+			Ok(res) => res,
+			Err(e) => {
+				error!("could not record incident: {:?}", e);
+				report_db_error(&msg, ctx);
+				return;
+			}
+		};
+		match self.reported_errors.recently_reported(&msg, Utc::now()) {
+			Ok(true) if !opened => return,
+			Err(e) => {
+				error!("could not check if error was reported recently: {:?}", e);
+				report_db_error(&msg, ctx);
+			}
+			_ => (),
 		}
 
-		let error_msg = format_error_code(&self.data, &msg).unwrap();
-
 		//get a list of clients connected interested in this dataset
 		if let Some(subs) = self.ws_subs.get(&msg.to_field_specific_key()) {
 			debug!("subs: {:?}", subs);
@@ -232,19 +323,275 @@ impl Handler<NewError> for ErrorRouter {
 					.unwrap();
 			}
 		}
+
+		//someone claimed this incident, no need to ping everyone again
+		if let IncidentState::Acknowledged { .. } = incident.state {
+			return;
+		}
+
+		let text = format!(
+			"{}\n[{} incident: {}, use /ack {} to claim it]",
+			error_msg,
+			incident.state,
+			incidents::format_id(msg.to_error_specific_key()),
+			incidents::format_id(msg.to_error_specific_key()),
+		);
 		//fetch the list of notification channels from
-		if let Some(to_notify) = self.clients_to_notify.should_notify(&msg).unwrap() {
-			for notify_option in to_notify {
-				if let Some(_mail_adress) = notify_option.email {
-					unimplemented!();
+		match self.clients_to_notify.should_notify(&msg) {
+			Ok(Some(to_notify)) => {
+				let event = webhook::Event::Error {
+					dataset_id: msg.dataset_id,
+					field_ids: msg.field_ids.clone(),
+					error_code: msg.error_code,
+					incident: incidents::format_id(msg.to_error_specific_key()),
+				};
+				self.notify(to_notify, text, event)
+			}
+			Ok(None) => (),
+			Err(e) => {
+				error!("could not load notification channels: {:?}", e);
+				report_db_error(&msg, ctx);
+			}
+		}
+	}
+}
+
+/// reports a database failure as system error, unless handling a system
+/// error caused it, then the database is likely unusable and we would loop
+fn report_db_error(msg: &NewError, ctx: &mut Context<ErrorRouter>) {
+	if msg.dataset_id != 0 {
+		ctx.notify(NewError::system(SystemError::Database));
+	}
+}
+
+impl ErrorRouter {
+	fn notify(&self, to_notify: Vec<NotifyOptions>, text: String, event: webhook::Event) {
+		for notify_option in to_notify {
+			if let Some(hook) = notify_option.webhook {
+				let payload = webhook::Payload {
+					text: text.clone(),
+					time: Utc::now(),
+					event: event.clone(),
+				};
+				self.webhooks.send(&hook, &payload);
+			}
+			if let Some(address) = notify_option.email {
+				if let Some(mailer) = &self.email {
+					let time = Utc::now().to_rfc2822();
+					let vars = [
+						("message", text.as_str()),
+						("expression", ""),
+						("time", time.as_str()),
+					];
+					mailer.send(address, &vars);
+				} else {
+					error!("no smtp server configured, can not notify {} of error", address);
+				}
+			}
+
+			let mut addresses: Vec<Address> = notify_option.chat.into_iter().collect();
+			for linked in self.link_db.links(notify_option.user_id) {
+				if !addresses.contains(&linked) {
+					addresses.push(linked);
+				}
+			}
+			let backends = self.backends.clone();
+			let text = text.clone();
+			actix::spawn(async move {
+				for address in &addresses {
+					if let Err(err) = backends.send_text(address, text.clone()).await {
+						error!("could not notify client via {}: {:?}", address, err);
+					}
+				}
+			});
+		}
+	}
+
+	/// everyone subscribed to any of the fields of the incident
+	fn subscribers_for(&mut self, key: ErrorSpecificKey) -> Vec<NotifyOptions> {
+		let msg = NewError {
+			dataset_id: incidents::dataset_of(key),
+			field_ids: vec![((key >> 8) & 0xFF) as FieldId],
+			error_code: (key & 0xFF) as ErrorCode,
+			timestamp: Utc::now(),
+		};
+		self.clients_to_notify
+			.should_notify(&msg)
+			.unwrap()
+			.unwrap_or_default()
+	}
+}
+
+#[derive(Message)]
+#[rtype(result = "Result<Incident, incidents::Error>")]
+pub struct AckIncident {
+	pub key: ErrorSpecificKey,
+	pub user_id: UserId,
+	pub user_name: String,
+}
+
+impl Handler<AckIncident> for ErrorRouter {
+	type Result = Result<Incident, incidents::Error>;
+
+	fn handle(&mut self, msg: AckIncident, _: &mut Context<Self>) -> Self::Result {
+		let incident = self
+			.incidents
+			.acknowledge(msg.key, msg.user_id, msg.user_name.clone())?;
+
+		let others = self
+			.subscribers_for(msg.key)
+			.into_iter()
+			.filter(|o| o.user_id != msg.user_id)
+			.collect();
+		let text = format!(
+			"incident {} was claimed by {}",
+			incidents::format_id(msg.key),
+			msg.user_name
+		);
+		let event = webhook::Event::Claimed {
+			incident: incidents::format_id(msg.key),
+			by: msg.user_name,
+		};
+		self.notify(others, text, event);
+		Ok(incident)
+	}
+}
+
+#[derive(Message)]
+#[rtype(result = "Result<Incident, incidents::Error>")]
+pub struct ResolveIncident {
+	pub key: ErrorSpecificKey,
+}
+
+impl Handler<ResolveIncident> for ErrorRouter {
+	type Result = Result<Incident, incidents::Error>;
+
+	fn handle(&mut self, msg: ResolveIncident, _: &mut Context<Self>) -> Self::Result {
+		self.incidents.resolve(msg.key)
+	}
+}
+
+pub struct IncidentInfo {
+	pub id: String,
+	pub dataset_id: DatasetId,
+	pub description: String,
+	pub incident: Incident,
+}
+
+#[derive(Message)]
+#[rtype(result = "Vec<IncidentInfo>")]
+pub struct ListIncidents {
+	/// the fields of each dataset to list the incidents of
+	pub access: Access,
+	/// also list the system errors
+	pub admin: bool,
+	pub include_resolved: bool,
+}
+
+impl Handler<ListIncidents> for ErrorRouter {
+	type Result = MessageResult<ListIncidents>;
+
+	fn handle(&mut self, msg: ListIncidents, _: &mut Context<Self>) -> Self::Result {
+		let mut list = Vec::new();
+		let mut sets: Vec<DatasetId> = msg.access.keys().copied().collect();
+		if msg.admin {
+			sets.push(0);
+		}
+		for set_id in sets {
+			for (key, incident) in self.incidents.for_dataset(set_id) {
+				if !incidents::may_access(key, &msg.access, msg.admin) {
+					continue;
 				}
-				if let Some(_telegram) = notify_option.telegram {
-					unimplemented!();
+				if !msg.include_resolved {
+					if let IncidentState::Resolved(_) = incident.state {
+						continue;
+					}
 				}
+				let error = NewError {
+					dataset_id: set_id,
+					field_ids: vec![((key >> 8) & 0xFF) as FieldId],
+					error_code: (key & 0xFF) as ErrorCode,
+					timestamp: incident.last_seen,
+				};
+				let description = format_error_code(&self.data, &error)
+					.unwrap_or_else(|_| String::from("unknown error"));
+				list.push(IncidentInfo {
+					id: incidents::format_id(key),
+					dataset_id: set_id,
+					description,
+					incident,
+				});
+			}
+		}
+		MessageResult(list)
+	}
+}
+
+#[derive(Message)]
+#[rtype(result = "Result<(), DataserverError>")]
+pub struct Subscribe {
+	pub keys: Vec<FieldSpecificKey>,
+	pub options: NotifyOptions,
+}
+
+impl Handler<Subscribe> for ErrorRouter {
+	type Result = Result<(), DataserverError>;
+
+	fn handle(&mut self, msg: Subscribe, _: &mut Context<Self>) -> Self::Result {
+		for key in msg.keys {
+			self.clients_to_notify.subscribe(key, msg.options.clone())?;
+		}
+		Ok(())
+	}
+}
+
+/// the admins to notify of system errors (dataset zero), replaces the admins
+/// subscribed before
+#[derive(Message)]
+#[rtype(result = "Result<(), DataserverError>")]
+pub struct SetAdmins {
+	pub admins: Vec<NotifyOptions>,
+}
+
+impl Handler<SetAdmins> for ErrorRouter {
+	type Result = Result<(), DataserverError>;
+
+	fn handle(&mut self, msg: SetAdmins, _: &mut Context<Self>) -> Self::Result {
+		let key = to_field_specific_key(0, u8::max_value());
+		for former in self.clients_to_notify.subscribers(key)? {
+			if !msg
+				.admins
+				.iter()
+				.any(|admin| admin.user_id == former.user_id)
+			{
+				info!(
+					"user {} is no longer an admin, unsubscribing from system errors",
+					former.user_id
+				);
 			}
 		}
+		self.clients_to_notify.replace(key, &msg.admins)
 	}
 }
+
+#[derive(Message)]
+#[rtype(result = "Result<(), DataserverError>")]
+pub struct Unsubscribe {
+	pub keys: Vec<FieldSpecificKey>,
+	pub user_id: UserId,
+}
+
+impl Handler<Unsubscribe> for ErrorRouter {
+	type Result = Result<(), DataserverError>;
+
+	fn handle(&mut self, msg: Unsubscribe, _: &mut Context<Self>) -> Self::Result {
+		for key in msg.keys {
+			self.clients_to_notify.unsubscribe(key, msg.user_id)?;
+		}
+		Ok(())
+	}
+}
+
 #[derive(Message)]
 #[rtype(result = "()")]
 pub struct NewFormattedError {
@@ -313,7 +660,14 @@ impl Handler<Disconnect> for ErrorRouter {
 }
 
 impl ErrorRouter {
-	pub fn load(db: &sled::Db, data: Arc<RwLock<Data>>) -> Result<ErrorRouter, DataserverError> {
+	pub fn load(
+		db: &sled::Db,
+		data: Arc<RwLock<Data>>,
+		backends: Backends,
+		link_db: LinkDatabase,
+		email: Option<email::Mailer>,
+		webhooks: webhook::Poster,
+	) -> Result<ErrorRouter, DataserverError> {
 		Ok(ErrorRouter {
 			sessions: HashMap::new(),
 			ws_subs: HashMap::new(),
@@ -321,7 +675,12 @@ impl ErrorRouter {
 			client_undisplayed_errors: db.open_tree("undisplayed errors")?,
 
 			reported_errors: ReportedErrors::load(db)?,
+			incidents: Incidents::load(db)?,
 			data,
+			backends,
+			link_db,
+			email,
+			webhooks,
 		})
 	}
 }
@@ -332,3 +691,48 @@ impl Actor for ErrorRouter {
 	/// with other actors.
 	type Context = Context<Self>;
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn reported_once_a_day_per_kind() {
+		let db = sled::Config::new().temporary(true).open().unwrap();
+		let mut reported = ReportedErrors::load(&db).unwrap();
+		let now = Utc::now();
+		let append = NewError::system(SystemError::DataAppend);
+		assert!(!reported.recently_reported(&append, now).unwrap());
+		assert!(reported.recently_reported(&append, now).unwrap());
+		let database = NewError::system(SystemError::Database);
+		assert!(!reported.recently_reported(&database, now).unwrap());
+
+		let tomorrow = now + chrono::Duration::days(1);
+		assert!(!reported.recently_reported(&append, tomorrow).unwrap());
+		assert!(reported.recently_reported(&append, tomorrow).unwrap());
+	}
+
+	#[test]
+	fn former_admins_are_dropped() {
+		let db = sled::Config::new().temporary(true).open().unwrap();
+		let mut channels = NotifyChannels::load(&db).unwrap();
+		let key = to_field_specific_key(0, u8::max_value());
+		let admin = |user_id| NotifyOptions {
+			user_id,
+			email: None,
+			chat: None,
+			webhook: None,
+		};
+		channels.subscribe(key, admin(1)).unwrap();
+		channels.subscribe(key, admin(2)).unwrap();
+		channels.replace(key, &[admin(2), admin(3)]).unwrap();
+
+		let ids: Vec<_> = channels
+			.subscribers(key)
+			.unwrap()
+			.iter()
+			.map(|o| o.user_id)
+			.collect();
+		assert_eq!(ids, vec![2, 3]);
+	}
+}
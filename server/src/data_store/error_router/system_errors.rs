@@ -0,0 +1,37 @@
+use num_derive::FromPrimitive;
+use num_traits::FromPrimitive;
+
+/// errors the server itself runs into, these are reported using
+/// dataset_id zero, details end up in the log
+#[repr(u8)]
+#[derive(FromPrimitive, Debug, Clone, Copy)]
+pub enum SystemError {
+	Unknown = 0,
+
+	DataAppend = 1,
+	Database = 2,
+	TelegramNotify = 3,
+	AlarmEvaluation = 4,
+}
+
+impl std::convert::From<u8> for SystemError {
+	fn from(raw: u8) -> Self {
+		Self::from_u8(raw).unwrap_or(SystemError::Unknown)
+	}
+}
+
+impl std::fmt::Display for SystemError {
+	fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+		match self {
+			SystemError::Unknown => write!(f, "Unknown error occured"),
+			SystemError::DataAppend => write!(f, "could not append new data to a dataset"),
+			SystemError::Database => write!(f, "internal database error"),
+			SystemError::TelegramNotify => {
+				write!(f, "could not notify a user of an alarm via telegram")
+			}
+			SystemError::AlarmEvaluation => {
+				write!(f, "an alarm repeatedly failed to evaluate")
+			}
+		}
+	}
+}
@@ -22,6 +22,7 @@ use std::collections::HashMap;
 //pub mod specifications;
 pub mod data_router;
 pub mod error_router;
+pub mod names;
 
 use std::f64;
 
@@ -66,6 +67,14 @@ pub enum Error {
 	MalformedSpec,
 }
 
+#[derive(thiserror::Error, Debug)]
+pub enum NewDataError {
+	#[error("data packet is malformed or has an invalid key")]
+	Rejected,
+	#[error("could not append to dataset {0}: {1:?}")]
+	Append(DatasetId, byteseries::Error),
+}
+
 impl DataSet {
 	pub fn get_decode_info(&self, allowed_fields: &[FieldId]) -> SetSliceDecodeInfo {
 		let mut offset_in_dataset = SmallVec::<[u8; 8]>::new();
@@ -300,13 +309,13 @@ impl Data {
 		&mut self,
 		mut data_string: Bytes,
 		time: DateTime<Utc>,
-	) -> Result<(DatasetId, Vec<u8>), ()> {
+	) -> Result<(DatasetId, Vec<u8>), NewDataError> {
 		if data_string.len() < 11 {
 			warn!(
 				"data_string size (={}) to small for key, datasetid and any data (min 11 bytes)",
 				data_string.len()
 			);
-			return Err(());
+			return Err(NewDataError::Rejected);
 		}
 
 		let dataset_id = LittleEndian::read_u16(&data_string[..2]);
@@ -320,11 +329,11 @@ impl Data {
 					dataset_id,
 					set.metadata.fieldsum() + 10
 				);
-				return Err(());
+				return Err(NewDataError::Rejected);
 			}
 			if key != set.metadata.key {
 				warn!("invalid key: {}, on store new data", key);
-				return Err(());
+				return Err(NewDataError::Rejected);
 			}
 			const PRINTVALUES: bool = false; //for debugging
 			if PRINTVALUES {
@@ -339,13 +348,13 @@ impl Data {
 			if let Err(error) = set.timeseries.append(time, &data_string[10..]) {
 				//if let Err(error) = set.timeseries.append_fast(time, &data_string[10..]){
 				warn!("error on data append: {:?}", error);
-				return Err(());
+				return Err(NewDataError::Append(dataset_id, error));
 			}
 
 			Ok((dataset_id, data_string.split_off(10).to_vec()))
 		} else {
 			warn!("could not find dataset with id: {}", dataset_id);
-			Err(())
+			Err(NewDataError::Rejected)
 		}
 	}
 }
@@ -0,0 +1,130 @@
+use bitspec::FieldId;
+use error_level::ErrorLevel;
+use regex::{Captures, Regex};
+
+use super::{Data, DatasetId};
+use crate::database::Access;
+
+#[derive(ErrorLevel, thiserror::Error, Debug)]
+pub enum Error {
+	#[report(debug)]
+	#[error("There is no dataset named: {0}")]
+	UnknownDataset(String),
+	#[report(debug)]
+	#[error("Dataset {0} has no field named: {1}")]
+	UnknownField(String, String),
+	#[report(debug)]
+	#[error("More then one dataset is named: {0}, use its id instead")]
+	Ambiguous(String),
+}
+
+/// names are matched case insensitive, spaces and dashes match underscores
+fn normalize(name: &str) -> String {
+	name.trim()
+		.to_lowercase()
+		.chars()
+		.map(|c| if c.is_whitespace() || c == '-' { '_' } else { c })
+		.collect()
+}
+
+fn name_re() -> Regex {
+	Regex::new(r#"\b([A-Za-z_]\w*)\.([A-Za-z_]\w*)\b"#).unwrap()
+}
+
+impl Data {
+	/// finds the ids for a field refered to as dataset_name.field_name, only
+	/// the datasets and fields in access are considered
+	pub fn field_by_name(
+		&self,
+		set: &str,
+		field: &str,
+		access: &Access,
+	) -> Result<(DatasetId, FieldId), Error> {
+		let set_name = normalize(set);
+		let mut matching = self
+			.sets
+			.iter()
+			.filter(|(set_id, _)| access.contains_key(set_id))
+			.filter(|(_, dataset)| normalize(&dataset.metadata.name) == set_name);
+		let (set_id, dataset) = matching
+			.next()
+			.ok_or_else(|| Error::UnknownDataset(set.to_owned()))?;
+		if matching.next().is_some() {
+			return Err(Error::Ambiguous(set.to_owned()));
+		}
+
+		let field_name = normalize(field);
+		let accessible = &access[set_id];
+		let field_id = dataset
+			.metadata
+			.fields
+			.iter()
+			.enumerate()
+			.filter(|(id, _)| accessible.iter().any(|auth| *auth.as_ref() as usize == *id))
+			.find(|(_, f)| normalize(&f.name) == field_name)
+			.map(|(id, _)| id)
+			.ok_or_else(|| Error::UnknownField(set.to_owned(), field.to_owned()))?;
+		Ok((*set_id, field_id as FieldId))
+	}
+
+	/// the dataset_name.field_name form of a field, spaces are replaced by
+	/// underscores so the name can be used in an expression
+	pub fn name_of_field(&self, set_id: DatasetId, field_id: FieldId) -> Option<String> {
+		let dataset = self.sets.get(&set_id)?;
+		let field = dataset.metadata.fields.get(field_id as usize)?;
+		let name = format!("{}.{}", dataset.metadata.name, field.name);
+		Some(name.split_whitespace().collect::<Vec<_>>().join("_"))
+	}
+}
+
+/// replaces every dataset_name.field_name in the text by its id: set_field,
+/// only names of fields in access are replaced
+pub fn resolve(text: &str, data: &Data, access: &Access) -> Result<String, Error> {
+	let mut error = None;
+	let resolved = name_re().replace_all(text, |caps: &Captures| {
+		match data.field_by_name(&caps[1], &caps[2], access) {
+			Ok((set_id, field_id)) => format!("{}_{}", set_id, field_id),
+			Err(e) => {
+				error = Some(e);
+				String::new()
+			}
+		}
+	});
+	match error {
+		Some(e) => Err(e),
+		None => Ok(resolved.to_string()),
+	}
+}
+
+/// replaces every set_field id in the text by the fields name, ids for which
+/// no field exists are left as is
+pub fn to_names(text: &str, data: &Data) -> String {
+	let re = Regex::new(r#"\b(\d+)_(\d+)\b"#).unwrap();
+	re.replace_all(text, |caps: &Captures| {
+		let ids = caps[1].parse().ok().zip(caps[2].parse().ok());
+		ids.and_then(|(set_id, field_id)| data.name_of_field(set_id, field_id))
+			.unwrap_or_else(|| caps[0].to_owned())
+	})
+	.to_string()
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn normalize_names() {
+		assert_eq!(normalize("Living Room"), "living_room");
+		assert_eq!(normalize("co2-level"), "co2_level");
+	}
+
+	#[test]
+	fn match_name_syntax() {
+		let re = name_re();
+		let names: Vec<_> = re
+			.captures_iter("kitchen.temperature > 21.5 && t > 300 && 3_0 < hall.Humidity")
+			.map(|caps| format!("{}.{}", &caps[1], &caps[2]))
+			.collect();
+		assert_eq!(names, vec!["kitchen.temperature", "hall.Humidity"]);
+	}
+}
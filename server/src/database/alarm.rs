@@ -3,12 +3,71 @@ use sled::{Db, Tree};
 use byteorder::{BigEndian, ByteOrder};
 
 use super::UserId;
-use crate::data_store::data_router::Alarm;
+use chrono::{DateTime, TimeZone, Utc};
+use std::collections::HashMap;
+
+use crate::data_store::data_router::{
+	Acknowledged, Alarm, AlarmState, Escalation, EscalationStep, FiringRecord, LegacyAlarm,
+	Maintenance,
+};
+use crate::data_store::DatasetId;
 
 #[derive(Debug, Clone)]
 pub struct AlarmDatabase {
 	pub db: Db,
 	pub storage: Tree,
+	/// runtime state of the alarms, uses the same keys as storage
+	pub state: Tree,
+	/// snoozes per alarm (prefix a), per user (prefix u) and the global mute
+	pub silences: Tree,
+	/// maintenance windows keyed by dataset id then start time
+	pub maintenance: Tree,
+	/// user chosen alarm names, uses the same keys as storage. Kept after an
+	/// alarm with a history is removed
+	pub names: Tree,
+	/// firings keyed by user, alarm and time, kept after an alarm is removed
+	pub history: Tree,
+	/// escalation chains, uses the same keys as storage
+	pub escalation: Tree,
+	/// escalations in progress and their acknowledgement, uses the same keys
+	/// as storage
+	pub escalating: Tree,
+}
+
+const MUTE_KEY: &[u8] = b"mute";
+
+fn snooze_key(user_id: UserId, alarm_id: Option<AlarmId>) -> Vec<u8> {
+	let mut key = Vec::with_capacity(17);
+	if let Some(alarm_id) = alarm_id {
+		key.push(b'a');
+		key.extend_from_slice(&user_id.to_be_bytes());
+		key.extend_from_slice(&alarm_id.to_be_bytes());
+	} else {
+		key.push(b'u');
+		key.extend_from_slice(&user_id.to_be_bytes());
+	}
+	key
+}
+
+/// alarms stored before the switch to named timezones are converted
+fn deserialize_alarm(bytes: &[u8]) -> Result<Alarm, bincode::Error> {
+	bincode::deserialize::<Alarm>(bytes).or_else(|e| {
+		bincode::deserialize::<LegacyAlarm>(bytes)
+			.map(Alarm::from)
+			.map_err(|_| e)
+	})
+}
+
+/// maintenance windows are keyed by dataset id then start time
+fn maintenance_key(set_id: DatasetId, from: &DateTime<Utc>) -> [u8; 10] {
+	let mut key = [0u8; 10];
+	BigEndian::write_u16(&mut key[0..], set_id);
+	BigEndian::write_i64(&mut key[2..], from.timestamp());
+	key
+}
+
+fn read_time(bytes: &[u8]) -> DateTime<Utc> {
+	Utc.timestamp(BigEndian::read_i64(bytes), 0)
 }
 
 #[derive(thiserror::Error, Debug)]
@@ -17,18 +76,439 @@ pub enum AlarmDbError {
 	DatabaseError(#[from] sled::Error),
 	#[error("already removed this alarm")]
 	AlreadyRemoved,
+	#[error("you already have an alarm named: {0}")]
+	NameTaken(String),
+	#[error("there is no alarm named or numbered: {0}")]
+	NotFound(String),
+	#[error("this alarm is not escalating")]
+	NotEscalating,
+	#[error("already acknowledged by {0}")]
+	AlreadyAcknowledged(String),
 }
 
-pub type AlarmList = Vec<(usize, Alarm)>;
+pub type AlarmList = Vec<(AlarmId, Alarm)>;
 pub type AlarmId = u64;
 impl AlarmDatabase {
 	pub fn from_db(db: &Db) -> Result<Self, sled::Error> {
 		Ok(Self {
 			db: db.clone(),
 			storage: db.open_tree("alarms")?, //created it not exist
+			state: db.open_tree("alarm_state")?,
+			silences: db.open_tree("alarm_silences")?,
+			maintenance: db.open_tree("maintenance")?,
+			names: db.open_tree("alarm_names")?,
+			history: db.open_tree("alarm_history")?,
+			escalation: db.open_tree("alarm_escalation")?,
+			escalating: db.open_tree("alarm_escalating")?,
 		})
 	}
 
+	/// an empty chain removes escalation for the alarm
+	pub fn set_escalation_chain(
+		&self,
+		user_id: UserId,
+		alarm_id: AlarmId,
+		chain: &[EscalationStep],
+	) -> Result<(), AlarmDbError> {
+		let key = Self::key(user_id, alarm_id);
+		if chain.is_empty() {
+			self.escalation.remove(key)?;
+			self.escalating.remove(key)?;
+		} else {
+			self.escalation.insert(key, bincode::serialize(chain).unwrap())?;
+		}
+		Ok(())
+	}
+
+	pub fn escalation_chain(
+		&self,
+		user_id: UserId,
+		alarm_id: AlarmId,
+	) -> Result<Vec<EscalationStep>, AlarmDbError> {
+		let chain = self
+			.escalation
+			.get(Self::key(user_id, alarm_id))?
+			.and_then(|chain| bincode::deserialize(&chain).ok())
+			.unwrap_or_default();
+		Ok(chain)
+	}
+
+	/// replaces any previous escalation of the alarm
+	pub fn start_escalation(
+		&self,
+		user_id: UserId,
+		alarm_id: AlarmId,
+		escalation: &Escalation,
+	) -> Result<(), AlarmDbError> {
+		let data = bincode::serialize(escalation).unwrap();
+		self.escalating.insert(Self::key(user_id, alarm_id), data)?;
+		Ok(())
+	}
+
+	pub fn stop_escalation(&self, user_id: UserId, alarm_id: AlarmId) -> Result<(), AlarmDbError> {
+		self.escalating.remove(Self::key(user_id, alarm_id))?;
+		Ok(())
+	}
+
+	pub fn escalations(&self) -> impl Iterator<Item = (UserId, AlarmId, Escalation)> {
+		self.escalating
+			.iter()
+			.filter_map(Result::ok)
+			.filter_map(|(key, escalation)| {
+				bincode::deserialize(&escalation).ok().map(|escalation| {
+					(
+						BigEndian::read_u64(&key[0..]),
+						BigEndian::read_u64(&key[8..]),
+						escalation,
+					)
+				})
+			})
+	}
+
+	/// updates the escalation atomically so an acknowledgement is never lost
+	fn update_escalation(
+		&self,
+		user_id: UserId,
+		alarm_id: AlarmId,
+		mut f: impl FnMut(&mut Escalation),
+	) -> Result<Escalation, AlarmDbError> {
+		let updated = self
+			.escalating
+			.update_and_fetch(Self::key(user_id, alarm_id), |old| {
+				let mut escalation: Escalation = bincode::deserialize(old?).ok()?;
+				f(&mut escalation);
+				Some(bincode::serialize(&escalation).unwrap())
+			})?
+			.ok_or(AlarmDbError::NotEscalating)?;
+		Ok(bincode::deserialize(&updated).unwrap())
+	}
+
+	pub fn set_notified(
+		&self,
+		user_id: UserId,
+		alarm_id: AlarmId,
+		notified: usize,
+	) -> Result<(), AlarmDbError> {
+		self.update_escalation(user_id, alarm_id, |e| e.notified = notified)?;
+		Ok(())
+	}
+
+	/// stops further escalation, returns the escalation as it was before
+	pub fn acknowledge(
+		&self,
+		user_id: UserId,
+		alarm_id: AlarmId,
+		ack: Acknowledged,
+	) -> Result<Escalation, AlarmDbError> {
+		let mut previous = None;
+		self.update_escalation(user_id, alarm_id, |e| {
+			previous = Some(e.clone());
+			if e.acknowledged.is_none() {
+				e.acknowledged = Some(ack.clone());
+			}
+		})?;
+		let previous = previous.unwrap();
+		match previous.acknowledged {
+			Some(ack) => Err(AlarmDbError::AlreadyAcknowledged(ack.name)),
+			None => Ok(previous),
+		}
+	}
+
+	pub fn record_firing(
+		&self,
+		user_id: UserId,
+		alarm_id: AlarmId,
+		record: &FiringRecord,
+	) -> Result<(), AlarmDbError> {
+		let mut key = [0u8; 24];
+		key[..16].copy_from_slice(&Self::key(user_id, alarm_id));
+		BigEndian::write_i64(&mut key[16..], record.at.timestamp_millis());
+		let data = bincode::serialize(record).unwrap();
+		self.history.insert(key, data)?;
+		Ok(())
+	}
+
+	/// the most recent firings of an alarm, newest first
+	pub fn history(&self, user_id: UserId, alarm_id: AlarmId, max: usize) -> Vec<FiringRecord> {
+		self.history
+			.scan_prefix(Self::key(user_id, alarm_id))
+			.values()
+			.rev()
+			.filter_map(Result::ok)
+			.filter_map(|record| bincode::deserialize(&record).ok())
+			.take(max)
+			.collect()
+	}
+
+	/// ids of the users alarms that have a history, including removed alarms
+	pub fn alarms_with_history(&self, user_id: UserId) -> Vec<AlarmId> {
+		let mut ids: Vec<AlarmId> = self
+			.history
+			.scan_prefix(user_id.to_be_bytes())
+			.keys()
+			.filter_map(Result::ok)
+			.map(|key| BigEndian::read_u64(&key[8..]))
+			.collect();
+		ids.dedup();
+		ids
+	}
+
+	/// names of the users alarms by their id, not every alarm has a name
+	pub fn names(&self, user_id: UserId) -> HashMap<AlarmId, String> {
+		self.history_names(user_id)
+			.into_iter()
+			.filter(|(alarm_id, _)| {
+				self.storage
+					.contains_key(Self::key(user_id, *alarm_id))
+					.unwrap_or(false)
+			})
+			.collect()
+	}
+
+	/// like names but including removed alarms that have a history, their
+	/// names are kept so the history can still be found
+	pub fn history_names(&self, user_id: UserId) -> HashMap<AlarmId, String> {
+		self.names
+			.scan_prefix(user_id.to_be_bytes())
+			.filter_map(Result::ok)
+			.map(|(key, name)| {
+				let name = String::from_utf8_lossy(&name).into_owned();
+				(BigEndian::read_u64(&key[8..]), name)
+			})
+			.collect()
+	}
+
+	fn has_history(&self, user_id: UserId, alarm_id: AlarmId) -> bool {
+		self.history
+			.scan_prefix(Self::key(user_id, alarm_id))
+			.next()
+			.is_some()
+	}
+
+	/// names must be unique for a user
+	pub fn set_name(
+		&self,
+		user_id: UserId,
+		alarm_id: AlarmId,
+		name: &str,
+	) -> Result<(), AlarmDbError> {
+		let taken = self
+			.names(user_id)
+			.into_iter()
+			.any(|(id, existing)| id != alarm_id && existing == name);
+		if taken {
+			return Err(AlarmDbError::NameTaken(name.to_owned()));
+		}
+		self.names
+			.insert(Self::key(user_id, alarm_id), name.as_bytes())?;
+		Ok(())
+	}
+
+	/// looks up an alarm by its name or otherwise its id
+	pub fn find(&self, user_id: UserId, name_or_id: &str) -> Result<AlarmId, AlarmDbError> {
+		let not_found = || AlarmDbError::NotFound(name_or_id.to_owned());
+		let alarm_id = match self
+			.names(user_id)
+			.into_iter()
+			.find(|(_, name)| name == name_or_id)
+		{
+			Some((alarm_id, _)) => alarm_id,
+			None => name_or_id.parse().map_err(|_| not_found())?,
+		};
+		if self.storage.contains_key(Self::key(user_id, alarm_id))? {
+			Ok(alarm_id)
+		} else {
+			Err(not_found())
+		}
+	}
+
+	/// looks up an alarm by its name or otherwise its id, unlike find this
+	/// also finds removed alarms as long as they have a history
+	pub fn find_history(&self, user_id: UserId, name_or_id: &str) -> Result<AlarmId, AlarmDbError> {
+		if let Ok(alarm_id) = self.find(user_id, name_or_id) {
+			return Ok(alarm_id);
+		}
+		let not_found = || AlarmDbError::NotFound(name_or_id.to_owned());
+		let alarm_id = match self
+			.history_names(user_id)
+			.into_iter()
+			.find(|(_, name)| name == name_or_id)
+		{
+			Some((alarm_id, _)) => alarm_id,
+			None => name_or_id.parse().map_err(|_| not_found())?,
+		};
+		if self.has_history(user_id, alarm_id) {
+			Ok(alarm_id)
+		} else {
+			Err(not_found())
+		}
+	}
+
+	/// swaps in a new definition for an alarm, its runtime state is reset.
+	/// Returns the previous definition
+	pub fn replace(
+		&self,
+		user_id: UserId,
+		alarm_id: AlarmId,
+		alarm: &Alarm,
+	) -> Result<Alarm, AlarmDbError> {
+		let key = Self::key(user_id, alarm_id);
+		let data = bincode::serialize(alarm).unwrap();
+		let old = self
+			.storage
+			.insert(key, data)?
+			.ok_or(AlarmDbError::AlreadyRemoved)?;
+		self.state.remove(key)?;
+		self.escalating.remove(key)?;
+		Ok(deserialize_alarm(&old).unwrap())
+	}
+
+	/// silences an alarm, or all of a users alarms if no alarm_id is given,
+	/// until the given time. Passing None for until removes the snooze
+	pub fn snooze(
+		&self,
+		user_id: UserId,
+		alarm_id: Option<AlarmId>,
+		until: Option<DateTime<Utc>>,
+	) -> Result<(), AlarmDbError> {
+		let key = snooze_key(user_id, alarm_id);
+		if let Some(until) = until {
+			self.silences.insert(key, until.timestamp().to_be_bytes().to_vec())?;
+		} else {
+			self.silences.remove(key)?;
+		}
+		Ok(())
+	}
+
+	pub fn snoozes(&self) -> impl Iterator<Item = (UserId, Option<AlarmId>, DateTime<Utc>)> {
+		self.silences
+			.iter()
+			.filter_map(Result::ok)
+			.filter_map(|(key, until)| {
+				let until = read_time(&until);
+				match (key.first(), key.len()) {
+					(Some(b'a'), 17) => Some((
+						BigEndian::read_u64(&key[1..]),
+						Some(BigEndian::read_u64(&key[9..])),
+						until,
+					)),
+					(Some(b'u'), 9) => Some((BigEndian::read_u64(&key[1..]), None, until)),
+					_ => None,
+				}
+			})
+	}
+
+	/// silences every alarm of every user, passing None unmutes
+	pub fn set_mute(&self, until: Option<DateTime<Utc>>) -> Result<(), AlarmDbError> {
+		if let Some(until) = until {
+			self.silences
+				.insert(MUTE_KEY, until.timestamp().to_be_bytes().to_vec())?;
+		} else {
+			self.silences.remove(MUTE_KEY)?;
+		}
+		Ok(())
+	}
+
+	pub fn mute(&self) -> Option<DateTime<Utc>> {
+		match self.silences.get(MUTE_KEY) {
+			Ok(until) => until.map(|until| read_time(&until)),
+			Err(e) => {
+				error!("could not read global mute: {:?}", e);
+				None
+			}
+		}
+	}
+
+	pub fn add_maintenance(
+		&self,
+		set_id: DatasetId,
+		window: &Maintenance,
+	) -> Result<(), AlarmDbError> {
+		self.maintenance.insert(
+			maintenance_key(set_id, &window.from),
+			window.until.timestamp().to_be_bytes().to_vec(),
+		)?;
+		Ok(())
+	}
+
+	/// removes the window on the dataset starting at from, without from all
+	/// windows on the dataset. Returns the number of windows removed
+	pub fn remove_maintenance(
+		&self,
+		set_id: DatasetId,
+		from: Option<DateTime<Utc>>,
+	) -> Result<usize, AlarmDbError> {
+		let keys: Vec<Vec<u8>> = match from {
+			Some(from) => vec![maintenance_key(set_id, &from).to_vec()],
+			None => self
+				.maintenance
+				.scan_prefix(set_id.to_be_bytes())
+				.keys()
+				.map(|key| key.map(|key| key.to_vec()))
+				.collect::<Result<_, _>>()?,
+		};
+		let mut removed = 0;
+		for key in keys {
+			if self.maintenance.remove(key)?.is_some() {
+				removed += 1;
+			}
+		}
+		Ok(removed)
+	}
+
+	/// drops the windows that ended before now
+	pub fn purge_maintenance(&self, now: &DateTime<Utc>) -> Result<(), AlarmDbError> {
+		for (key, until) in self.maintenance.iter().filter_map(Result::ok) {
+			if read_time(&until) <= *now {
+				self.maintenance.remove(key)?;
+			}
+		}
+		Ok(())
+	}
+
+	pub fn maintenance(&self) -> impl Iterator<Item = (DatasetId, Maintenance)> {
+		self.maintenance
+			.iter()
+			.filter_map(Result::ok)
+			.map(|(key, until)| {
+				let window = Maintenance {
+					from: read_time(&key[2..]),
+					until: read_time(&until),
+				};
+				(BigEndian::read_u16(&key[0..]), window)
+			})
+	}
+
+	fn key(user_id: UserId, alarm_id: AlarmId) -> [u8; 16] {
+		let mut key = [0; 16];
+		BigEndian::write_u64(&mut key[0..], user_id);
+		BigEndian::write_u64(&mut key[8..], alarm_id);
+		key
+	}
+
+	/// an alarm that has not yet been evaluated has no stored state
+	pub fn get_state(&self, user_id: UserId, alarm_id: AlarmId) -> Option<AlarmState> {
+		match self.state.get(Self::key(user_id, alarm_id)) {
+			Ok(Some(state)) => bincode::deserialize(&state).ok(),
+			Ok(None) => None,
+			Err(e) => {
+				error!("could not read alarm state: {:?}", e);
+				None
+			}
+		}
+	}
+
+	pub fn set_state(
+		&self,
+		user_id: UserId,
+		alarm_id: AlarmId,
+		state: &AlarmState,
+	) -> Result<(), AlarmDbError> {
+		let data = bincode::serialize(state).unwrap();
+		self.state.insert(Self::key(user_id, alarm_id), data)?;
+		Ok(())
+	}
+
 	pub async fn remove_user(&self, user_id: UserId) -> Result<(), sled::Error> {
 		let mut key_begin = [std::u8::MIN; 16];
 		let mut key_end = [std::u8::MAX; 16];
@@ -43,6 +523,30 @@ impl AlarmDatabase {
 			.filter_map(Result::ok)
 		{
 			self.storage.remove(&key)?;
+			self.state.remove(&key)?;
+			self.names.remove(&key)?;
+			self.escalation.remove(&key)?;
+			self.escalating.remove(&key)?;
+			let alarm_id = BigEndian::read_u64(&key[8..]);
+			self.silences.remove(snooze_key(user_id, Some(alarm_id)))?;
+		}
+		self.silences.remove(snooze_key(user_id, None))?;
+		for key in self
+			.history
+			.scan_prefix(user_id.to_be_bytes())
+			.keys()
+			.filter_map(Result::ok)
+		{
+			self.history.remove(key)?;
+		}
+		// names of removed alarms with a history
+		for key in self
+			.names
+			.scan_prefix(user_id.to_be_bytes())
+			.keys()
+			.filter_map(Result::ok)
+		{
+			self.names.remove(key)?;
 		}
 		self.storage.flush_async().await?;
 		Ok(())
@@ -54,7 +558,7 @@ impl AlarmDatabase {
 			.iter()
 			.filter_map(Result::ok)
 			.map(|(id, alarm)| {
-				bincode::deserialize(&alarm).map(|alarm| {
+				deserialize_alarm(&alarm).map(|alarm| {
 					(
 						BigEndian::read_u64(&id[0..]),
 						BigEndian::read_u64(&id[8..]),
@@ -75,50 +579,118 @@ impl AlarmDatabase {
 		let alarm_list: AlarmList = self
 			.storage
 			.range(key_begin..key_end)
-			.values()
 			.filter_map(Result::ok)
-			.map(|entry| bincode::deserialize::<Alarm>(&entry))
-			.filter_map(Result::ok)
-			.enumerate()
+			.filter_map(|(key, entry)| {
+				deserialize_alarm(&entry)
+					.ok()
+					.map(|alarm| (BigEndian::read_u64(&key[8..]), alarm))
+			})
 			.collect();
 		alarm_list
 	}
 
-	pub fn remove(
-		&self,
-		user_id: UserId,
-		counter: usize,
-	) -> Result<(Alarm, AlarmId), AlarmDbError> {
-		let mut key_begin = [std::u8::MIN; 16];
-		let mut key_end = [std::u8::MAX; 16];
-
-		BigEndian::write_u64(&mut key_begin[0..], user_id);
-		BigEndian::write_u64(&mut key_end[0..], user_id);
-
-		let keys: Result<Vec<sled::IVec>, sled::Error> =
-			self.storage.range(key_begin..key_end).keys().collect();
-		let keys = keys?;
-
-		let key = keys.get(counter).ok_or(AlarmDbError::AlreadyRemoved)?;
-
+	pub fn remove(&self, user_id: UserId, alarm_id: AlarmId) -> Result<Alarm, AlarmDbError> {
+		let key = Self::key(user_id, alarm_id);
 		let entry = self
 			.storage
 			.remove(&key)?
 			.ok_or(AlarmDbError::AlreadyRemoved)?;
-		let alarm = bincode::deserialize::<Alarm>(&entry).unwrap();
-		let alarm_id = BigEndian::read_u64(&key[8..]);
-		Ok((alarm, alarm_id))
+		self.state.remove(&key)?;
+		if !self.has_history(user_id, alarm_id) {
+			self.names.remove(&key)?;
+		}
+		self.escalation.remove(&key)?;
+		self.escalating.remove(&key)?;
+		self.silences.remove(snooze_key(user_id, Some(alarm_id)))?;
+		let alarm = deserialize_alarm(&entry).unwrap();
+		Ok(alarm)
 	}
 
 	pub fn add(&self, alarm: &Alarm, user_id: UserId) -> Result<AlarmId, AlarmDbError> {
 		let id = self.db.generate_id()?;
-		let mut key = [0; 16];
-
-		BigEndian::write_u64(&mut key[0..], user_id);
-		BigEndian::write_u64(&mut key[8..], id);
+		let key = Self::key(user_id, id);
 		let data = bincode::serialize(alarm).unwrap();
 
 		self.storage.insert(key, data)?;
 		Ok(id)
 	}
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::data_store::data_router::NotifyVia;
+
+	fn alarm() -> Alarm {
+		Alarm {
+			expression: String::from("3_0 > 5"),
+			inv_expr: None,
+			weekday: None,
+			period: None,
+			message: None,
+			command: None,
+			timezone: chrono_tz::Tz::UTC,
+			notify: NotifyVia {
+				email: None,
+				telegram: None,
+				webhook: None,
+			},
+		}
+	}
+
+	#[test]
+	fn history_outlives_alarm() {
+		let db = sled::Config::new().temporary(true).open().unwrap();
+		let alarm_db = AlarmDatabase::from_db(&db).unwrap();
+		let fired = alarm_db.add(&alarm(), 1).unwrap();
+		let silent = alarm_db.add(&alarm(), 1).unwrap();
+		alarm_db.set_name(1, fired, "fridge").unwrap();
+		alarm_db.set_name(1, silent, "freezer").unwrap();
+		let record = FiringRecord {
+			at: Utc::now(),
+			inverted: false,
+			expression: String::from("3_0 > 5"),
+			values: vec![(String::from("3_0"), 6.0)],
+		};
+		alarm_db.record_firing(1, fired, &record).unwrap();
+
+		alarm_db.remove(1, fired).unwrap();
+		alarm_db.remove(1, silent).unwrap();
+		assert!(alarm_db.find(1, "fridge").is_err());
+		assert!(alarm_db.names(1).is_empty());
+		assert_eq!(alarm_db.find_history(1, "fridge").unwrap(), fired);
+		assert_eq!(alarm_db.find_history(1, &fired.to_string()).unwrap(), fired);
+		assert!(alarm_db.find_history(1, "freezer").is_err());
+		assert_eq!(alarm_db.history(1, fired, 10).len(), 1);
+	}
+
+	#[test]
+	fn acknowledged_once() {
+		let db = sled::Config::new().temporary(true).open().unwrap();
+		let alarm_db = AlarmDatabase::from_db(&db).unwrap();
+		let alarm_id = alarm_db.add(&alarm(), 1).unwrap();
+		let ack = |by, name: &str| Acknowledged {
+			by,
+			name: name.to_owned(),
+			at: Utc::now(),
+		};
+		assert!(alarm_db.acknowledge(1, alarm_id, ack(2, "bob")).is_err());
+
+		let escalation = Escalation {
+			fired_at: Utc::now(),
+			text: String::from("fridge too warm"),
+			notified: 0,
+			acknowledged: None,
+		};
+		alarm_db.start_escalation(1, alarm_id, &escalation).unwrap();
+		alarm_db.set_notified(1, alarm_id, 1).unwrap();
+		let previous = alarm_db.acknowledge(1, alarm_id, ack(2, "bob")).unwrap();
+		assert_eq!(previous.notified, 1);
+		assert!(matches!(
+			alarm_db.acknowledge(1, alarm_id, ack(3, "carol")),
+			Err(AlarmDbError::AlreadyAcknowledged(name)) if name == "bob"
+		));
+		let (_, _, escalation) = alarm_db.escalations().next().unwrap();
+		assert_eq!(escalation.acknowledged.unwrap().by, 2);
+	}
+}
@@ -0,0 +1,164 @@
+use byteorder::{BigEndian, ByteOrder};
+use error_level::ErrorLevel;
+use sled::{Db, Tree};
+
+use super::UserId;
+use crate::bot::backend::Address;
+
+#[derive(ErrorLevel, thiserror::Error, Debug)]
+pub enum LinkDbError {
+	#[report(error)]
+	#[error("internal database error: {0:?}")]
+	DatabaseError(#[from] sled::Error),
+	#[report(debug)]
+	#[error("{0} is already linked to another account")]
+	Taken(Address),
+	#[report(debug)]
+	#[error("{0} is not linked to your account")]
+	NotLinked(Address),
+	#[report(debug)]
+	#[error("this {0} account is already linked to another account")]
+	SenderTaken(&'static str),
+	#[report(debug)]
+	#[error("you have no {0} account linked")]
+	NoSender(&'static str),
+}
+
+/// messaging backends other then telegram users receive notifications on
+/// and send commands from
+#[derive(Debug, Clone)]
+pub struct LinkDatabase {
+	/// the owner of each address
+	owners: Tree,
+	/// the addresses of each user
+	links: Tree,
+	/// the user behind an account on a backend other then telegram, keyed
+	/// by: <backend> <sender id>
+	senders: Tree,
+}
+
+impl LinkDatabase {
+	pub fn from_db(db: &Db) -> Result<Self, sled::Error> {
+		Ok(Self {
+			owners: db.open_tree("link_owners")?,
+			links: db.open_tree("links")?,
+			senders: db.open_tree("link_senders")?,
+		})
+	}
+
+	fn key(address: &Address) -> Vec<u8> {
+		bincode::serialize(address).unwrap()
+	}
+
+	fn sender_key(chat: &Address, sender: &str) -> Vec<u8> {
+		format!("{} {}", chat.backend(), sender).into_bytes()
+	}
+
+	/// commands the sender sends on the backend of chat are run as the user
+	pub fn link_sender(
+		&self,
+		user_id: UserId,
+		chat: &Address,
+		sender: &str,
+	) -> Result<(), LinkDbError> {
+		let owner = Some(user_id.to_be_bytes().to_vec());
+		let previous = self.senders.compare_and_swap(
+			Self::sender_key(chat, sender),
+			None as Option<&[u8]>,
+			owner,
+		)?;
+		if let Err(existing) = previous {
+			let owner = existing.current.map(|id| BigEndian::read_u64(&id));
+			if owner != Some(user_id) {
+				return Err(LinkDbError::SenderTaken(chat.backend()));
+			}
+		}
+		Ok(())
+	}
+
+	/// the user the sender of a message in chat is linked to
+	pub fn sender(&self, chat: &Address, sender: &str) -> Result<Option<UserId>, LinkDbError> {
+		let owner = self
+			.senders
+			.get(Self::sender_key(chat, sender))?
+			.map(|id| BigEndian::read_u64(&id));
+		Ok(owner)
+	}
+
+	/// the linked accounts of the user as: <backend> <sender id>
+	pub fn senders(&self, user_id: UserId) -> Vec<String> {
+		self.senders
+			.iter()
+			.filter_map(Result::ok)
+			.filter(|(_, owner)| BigEndian::read_u64(owner) == user_id)
+			.map(|(key, _)| String::from_utf8_lossy(&key).into_owned())
+			.collect()
+	}
+
+	/// unlinks all the users accounts on the backend of chat
+	pub fn unlink_senders(&self, user_id: UserId, chat: &Address) -> Result<(), LinkDbError> {
+		let prefix = format!("{} ", chat.backend());
+		let mut removed = false;
+		for entry in self.senders.scan_prefix(prefix) {
+			let (key, owner) = entry?;
+			if BigEndian::read_u64(&owner) == user_id {
+				self.senders.remove(key)?;
+				removed = true;
+			}
+		}
+		if !removed {
+			return Err(LinkDbError::NoSender(chat.backend()));
+		}
+		Ok(())
+	}
+
+	pub fn link(&self, user_id: UserId, address: Address) -> Result<(), LinkDbError> {
+		let owner = Some(user_id.to_be_bytes().to_vec());
+		let previous = self
+			.owners
+			.compare_and_swap(Self::key(&address), None as Option<&[u8]>, owner)?;
+		if let Err(existing) = previous {
+			let owner = existing.current.map(|id| BigEndian::read_u64(&id));
+			if owner != Some(user_id) {
+				return Err(LinkDbError::Taken(address));
+			}
+			return Ok(());
+		}
+
+		let mut links = self.links(user_id);
+		links.push(address);
+		self.links
+			.insert(user_id.to_be_bytes(), bincode::serialize(&links).unwrap())?;
+		Ok(())
+	}
+
+	pub fn unlink(&self, user_id: UserId, address: &Address) -> Result<(), LinkDbError> {
+		let mut links = self.links(user_id);
+		let before = links.len();
+		links.retain(|linked| linked != address);
+		if links.len() == before {
+			return Err(LinkDbError::NotLinked(address.clone()));
+		}
+		self.owners.remove(Self::key(address))?;
+		self.links
+			.insert(user_id.to_be_bytes(), bincode::serialize(&links).unwrap())?;
+		Ok(())
+	}
+
+	pub fn owner(&self, address: &Address) -> Result<Option<UserId>, LinkDbError> {
+		let owner = self
+			.owners
+			.get(Self::key(address))?
+			.map(|id| BigEndian::read_u64(&id));
+		Ok(owner)
+	}
+
+	pub fn links(&self, user_id: UserId) -> Vec<Address> {
+		self.links
+			.get(user_id.to_be_bytes())
+			.ok()
+			.flatten()
+			.and_then(|links| bincode::deserialize(&links).ok())
+			.unwrap_or_default()
+	}
+}
@@ -1,10 +1,15 @@
 mod user;
 mod alarm;
+mod link;
 mod passw;
+mod report;
+pub mod timezone;
 
 pub use alarm::{AlarmDatabase, AlarmDbError, AlarmId};
 pub use user::{UserDatabase, UserLookup, User, Access, UserId, UserDbError};
+pub use link::{LinkDatabase, LinkDbError};
 pub use passw::PasswordDatabase;
+pub use report::{Report, ReportDatabase, ReportDbError, ReportId, Schedule};
 
 #[derive(Debug)]
 pub enum LoadDbError {
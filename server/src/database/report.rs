@@ -0,0 +1,210 @@
+use byteorder::{BigEndian, ByteOrder};
+use chrono::{DateTime, Datelike, Duration, NaiveTime, TimeZone, Utc, Weekday};
+use chrono_tz::Tz;
+use error_level::ErrorLevel;
+use serde::{Deserialize, Serialize};
+use sled::{Db, Tree};
+
+use super::UserId;
+
+pub type ReportId = u64;
+
+/// when a report runs, times are in the timezone of its owner
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum Schedule {
+	Daily(NaiveTime),
+	Weekly(Weekday, NaiveTime),
+}
+
+impl Schedule {
+	/// the first moment the schedule runs strictly after `after`. A time
+	/// skipped by a daylight saving change runs an hour later, a time that
+	/// happens twice runs the first time
+	pub fn next_after(&self, after: DateTime<Utc>, tz: Tz) -> DateTime<Utc> {
+		let (weekday, time) = match *self {
+			Schedule::Daily(time) => (None, time),
+			Schedule::Weekly(weekday, time) => (Some(weekday), time),
+		};
+		let mut date = after.with_timezone(&tz).date().naive_local();
+		loop {
+			if weekday.map_or(true, |day| day == date.weekday()) {
+				let naive = date.and_time(time);
+				let moment = tz
+					.from_local_datetime(&naive)
+					.earliest()
+					.or_else(|| tz.from_local_datetime(&(naive + Duration::hours(1))).earliest());
+				if let Some(moment) = moment {
+					let moment = moment.with_timezone(&Utc);
+					if moment > after {
+						return moment;
+					}
+				}
+			}
+			date = date.succ();
+		}
+	}
+}
+
+impl std::fmt::Display for Schedule {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		match self {
+			Schedule::Daily(time) => write!(f, "daily {}", time.format("%H:%M")),
+			Schedule::Weekly(day, time) => write!(f, "weekly {} {}", day, time.format("%H:%M")),
+		}
+	}
+}
+
+/// a command run on a schedule on behalf of a user
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Report {
+	pub schedule: Schedule,
+	pub command: String,
+	/// the last time the report ran, or when it was added
+	pub last_run: DateTime<Utc>,
+}
+
+impl Report {
+	pub fn is_due(&self, now: DateTime<Utc>, tz: Tz) -> bool {
+		self.schedule.next_after(self.last_run, tz) <= now
+	}
+}
+
+#[derive(ErrorLevel, thiserror::Error, Debug)]
+pub enum ReportDbError {
+	#[report(error)]
+	#[error("internal database error: {0:?}")]
+	DatabaseError(#[from] sled::Error),
+	#[report(debug)]
+	#[error("you have no report numbered: {0}")]
+	NotFound(ReportId),
+}
+
+#[derive(Debug, Clone)]
+pub struct ReportDatabase {
+	db: Db,
+	/// keyed by user id then report id
+	storage: Tree,
+}
+
+impl ReportDatabase {
+	pub fn from_db(db: &Db) -> Result<Self, sled::Error> {
+		Ok(Self {
+			db: db.clone(),
+			storage: db.open_tree("reports")?,
+		})
+	}
+
+	fn key(user_id: UserId, report_id: ReportId) -> [u8; 16] {
+		let mut key = [0; 16];
+		BigEndian::write_u64(&mut key[0..], user_id);
+		BigEndian::write_u64(&mut key[8..], report_id);
+		key
+	}
+
+	pub fn add(&self, user_id: UserId, report: &Report) -> Result<ReportId, ReportDbError> {
+		let id = self.db.generate_id()?;
+		let data = bincode::serialize(report).unwrap();
+		self.storage.insert(Self::key(user_id, id), data)?;
+		Ok(id)
+	}
+
+	pub fn remove(&self, user_id: UserId, report_id: ReportId) -> Result<Report, ReportDbError> {
+		let entry = self
+			.storage
+			.remove(Self::key(user_id, report_id))?
+			.ok_or(ReportDbError::NotFound(report_id))?;
+		Ok(bincode::deserialize(&entry).unwrap())
+	}
+
+	pub fn set_last_run(
+		&self,
+		user_id: UserId,
+		report_id: ReportId,
+		at: DateTime<Utc>,
+	) -> Result<(), ReportDbError> {
+		self.storage
+			.update_and_fetch(Self::key(user_id, report_id), |old| {
+				let mut report: Report = bincode::deserialize(old?).ok()?;
+				report.last_run = at;
+				Some(bincode::serialize(&report).unwrap())
+			})?
+			.ok_or(ReportDbError::NotFound(report_id))?;
+		Ok(())
+	}
+
+	pub fn list_users_reports(&self, user_id: UserId) -> Vec<(ReportId, Report)> {
+		self.storage
+			.scan_prefix(user_id.to_be_bytes())
+			.filter_map(Result::ok)
+			.filter_map(|(key, report)| {
+				bincode::deserialize(&report)
+					.ok()
+					.map(|report| (BigEndian::read_u64(&key[8..]), report))
+			})
+			.collect()
+	}
+
+	pub fn iter(&self) -> impl Iterator<Item = (UserId, ReportId, Report)> {
+		self.storage
+			.iter()
+			.filter_map(Result::ok)
+			.filter_map(|(key, report)| {
+				bincode::deserialize(&report).ok().map(|report| {
+					(
+						BigEndian::read_u64(&key[0..]),
+						BigEndian::read_u64(&key[8..]),
+						report,
+					)
+				})
+			})
+	}
+
+	pub fn remove_user(&self, user_id: UserId) -> Result<(), sled::Error> {
+		for key in self
+			.storage
+			.scan_prefix(user_id.to_be_bytes())
+			.keys()
+			.filter_map(Result::ok)
+		{
+			self.storage.remove(key)?;
+		}
+		Ok(())
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn next_daily_run() {
+		let eight = NaiveTime::from_hms(8, 0, 0);
+		let tz = Tz::Europe__Amsterdam;
+		let daily = Schedule::Daily(eight);
+
+		let before = Utc.ymd(2021, 6, 1).and_hms(5, 0, 0);
+		assert_eq!(daily.next_after(before, tz), Utc.ymd(2021, 6, 1).and_hms(6, 0, 0));
+		let after = Utc.ymd(2021, 6, 1).and_hms(6, 0, 0);
+		assert_eq!(daily.next_after(after, tz), Utc.ymd(2021, 6, 2).and_hms(6, 0, 0));
+		// the last day of summer time, 08:00 local is 07:00 UTC the day after
+		let switch = Utc.ymd(2021, 10, 30).and_hms(6, 0, 0);
+		assert_eq!(daily.next_after(switch, tz), Utc.ymd(2021, 10, 31).and_hms(7, 0, 0));
+	}
+
+	#[test]
+	fn next_weekly_run() {
+		let weekly = Schedule::Weekly(Weekday::Mon, NaiveTime::from_hms(9, 30, 0));
+		// 2021-06-01 is a tuesday
+		let now = Utc.ymd(2021, 6, 1).and_hms(12, 0, 0);
+		assert_eq!(weekly.next_after(now, Tz::UTC), Utc.ymd(2021, 6, 7).and_hms(9, 30, 0));
+	}
+
+	#[test]
+	fn skipped_by_dst() {
+		let daily = Schedule::Daily(NaiveTime::from_hms(2, 30, 0));
+		let tz = Tz::Europe__Amsterdam;
+		// 02:30 does not exist on 2021-03-28, it runs at 03:30 summer time
+		let now = Utc.ymd(2021, 3, 27).and_hms(12, 0, 0);
+		assert_eq!(daily.next_after(now, tz), Utc.ymd(2021, 3, 28).and_hms(1, 30, 0));
+	}
+}
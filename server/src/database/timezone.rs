@@ -0,0 +1,38 @@
+use chrono_tz::Tz;
+
+/// stores a timezone by its IANA name
+pub mod serde_tz {
+	use chrono_tz::Tz;
+	use serde::{de::Error, Deserialize, Deserializer, Serializer};
+
+	pub fn serialize<S: Serializer>(tz: &Tz, serializer: S) -> Result<S::Ok, S::Error> {
+		serializer.serialize_str(tz.name())
+	}
+
+	pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Tz, D::Error> {
+		let name = String::deserialize(deserializer)?;
+		name.parse().map_err(D::Error::custom)
+	}
+}
+
+/// timezones used to be stored as whole hours east of UTC, these map to the
+/// fixed Etc/GMT zones (which have their sign inverted)
+pub fn from_legacy_offset(hours: i32) -> Tz {
+	if hours == 0 {
+		return Tz::UTC;
+	}
+	format!("Etc/GMT{:+}", -hours).parse().unwrap_or(Tz::UTC)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn legacy_offsets() {
+		assert_eq!(from_legacy_offset(0), Tz::UTC);
+		assert_eq!(from_legacy_offset(2), Tz::Etc__GMTMinus2);
+		assert_eq!(from_legacy_offset(-5), Tz::Etc__GMTPlus5);
+		assert_eq!(from_legacy_offset(20), Tz::UTC);
+	}
+}
@@ -10,6 +10,7 @@ use std::collections::HashMap;
 use std::sync::{Arc, RwLock};
 
 use chrono::{DateTime, Utc};
+use chrono_tz::Tz;
 use telegram_bot::types::refs::UserId as TelegramUserId;
 
 pub type Access = HashMap<data_store::DatasetId, Vec<data_store::Authorisation>>;
@@ -24,7 +25,45 @@ pub struct User {
 	pub last_login: DateTime<Utc>,
 	pub aliases: HashMap<String, String>,
 	pub keyboard: Option<String>,
-	pub timezone_offset: i32, //hours to the east
+	#[serde(with = "super::timezone::serde_tz")]
+	pub timezone: Tz,
+}
+
+/// layout of User before timezones were stored by name
+#[derive(Deserialize)]
+struct LegacyUser {
+	id: UserId,
+	name: String,
+	telegram_id: Option<TelegramUserId>,
+	timeseries_with_access: Access,
+	last_login: DateTime<Utc>,
+	aliases: HashMap<String, String>,
+	keyboard: Option<String>,
+	timezone_offset: i32, //hours to the east
+}
+
+impl From<LegacyUser> for User {
+	fn from(legacy: LegacyUser) -> Self {
+		User {
+			id: legacy.id,
+			name: legacy.name,
+			telegram_id: legacy.telegram_id,
+			timeseries_with_access: legacy.timeseries_with_access,
+			last_login: legacy.last_login,
+			aliases: legacy.aliases,
+			keyboard: legacy.keyboard,
+			timezone: super::timezone::from_legacy_offset(legacy.timezone_offset),
+		}
+	}
+}
+
+/// users stored before the switch to named timezones are converted
+fn deserialize_user(bytes: &[u8]) -> Result<User, bincode::Error> {
+	bincode::deserialize::<User>(bytes).or_else(|e| {
+		bincode::deserialize::<LegacyUser>(bytes)
+			.map(User::from)
+			.map_err(|_| e)
+	})
 }
 
 #[derive(ErrorLevel, Error, Debug)]
@@ -66,14 +105,14 @@ impl UserDatabase {
 			.iter()
 			.values()
 			.filter_map(Result::ok)
-			.map(|user| bincode::deserialize(&user))
+			.map(|user| deserialize_user(&user))
 			.filter_map(Result::ok)
 	}
 
 	pub fn get_user(&self, id: UserId) -> Result<User, UserDbError> {
 		let key = id.to_be_bytes();
 		if let Some(user) = self.storage.get(key)? {
-			let user = bincode::deserialize(&user)?;
+			let user = deserialize_user(&user)?;
 			Ok(user)
 		} else {
 			Err(UserDbError::UserNotInDb(id))
@@ -106,7 +145,7 @@ impl UserDatabase {
 			telegram_id: None,
 			aliases: HashMap::new(),
 			keyboard: None,
-			timezone_offset: 0, //hours to the east
+			timezone: Tz::UTC,
 		};
 
 		self.set_user(user).await?;
@@ -179,7 +218,7 @@ impl UserLookup {
 		let mut bot_id_to_id = HashMap::new();
 
 		for row in db.storage.iter().values() {
-			let user: User = bincode::deserialize(&row?)?;
+			let user = deserialize_user(&row?)?;
 			//dbg!(&user);
 			let id = user.id;
 			let name = user.name;
@@ -2,25 +2,73 @@ use actix_identity::Identity;
 use actix_web::web::Data;
 use actix_web::{HttpResponse, Responder};
 
+use chrono::Utc;
+use log::error;
 use yarte::Template;
 
+use crate::bot::commands::link::LinkCodes;
 use crate::bot::commands::show::format_to_duration;
 use crate::data_store;
-use data_store::{data_router::DataRouterState, Authorisation};
+use data_store::{data_router::DataRouterState, error_router, Authorisation};
 
 #[derive(Template)]
 #[template(path = "settings.hbs")]
-struct SettingsPage<'a> {
-	telegram_id: &'a str,
+struct SettingsPage {
+	telegram_id: String,
+	has_code: bool,
+	code: String,
+	valid_for: i64,
 }
 
-pub async fn settings_page(_id: Identity, _state: Data<DataRouterState>) -> impl Responder {
+/// the linked telegram account and, if asked for, a code to link one
+fn render_settings(id: Identity, state: &DataRouterState, new_code: bool) -> HttpResponse {
+	let session_id = id
+		.identity()
+		.unwrap()
+		.parse::<data_store::DatasetId>()
+		.unwrap();
+	let user_id = {
+		let sessions = state.sessions.read().unwrap();
+		let session = sessions.get(&session_id).unwrap();
+		let user_id = session.lock().unwrap().db_entry.id;
+		user_id
+	};
+
+	// the session holds the user as it was on login, telegram may have
+	// been linked since
+	let telegram_id = match state.user_db.get_user(user_id) {
+		Ok(user) => user
+			.telegram_id
+			.map(|id| id.to_string())
+			.unwrap_or_else(|| String::from("not linked")),
+		Err(e) => {
+			error!("could not load user for settings page: {:?}", e);
+			return HttpResponse::InternalServerError().finish();
+		}
+	};
+	let code = if new_code {
+		state.link_codes.generate(user_id, Utc::now())
+	} else {
+		String::new()
+	};
+
 	let page = SettingsPage {
-		telegram_id: "test",
+		telegram_id,
+		has_code: new_code,
+		code,
+		valid_for: LinkCodes::VALID_FOR,
 	};
 	HttpResponse::Ok().body(page.call().unwrap())
 }
 
+pub async fn settings_page(id: Identity, state: Data<DataRouterState>) -> impl Responder {
+	render_settings(id, &state, false)
+}
+
+pub async fn new_link_code(id: Identity, state: Data<DataRouterState>) -> impl Responder {
+	render_settings(id, &state, true)
+}
+
 #[derive(Default)]
 struct ListSetInfo {
 	name: String,
@@ -163,3 +211,119 @@ pub async fn plot_data(id: Identity, state: Data<DataRouterState>) -> impl Respo
 	let page = PlotPage { datasets: all_info };
 	HttpResponse::Ok().body(page.call().unwrap())
 }
+
+struct IncidentRow {
+	id: String,
+	state: String,
+	first_seen: String,
+	last_seen: String,
+	occurrences: u32,
+	description: String,
+}
+
+#[derive(Template)]
+#[template(path = "errors.hbs")]
+struct ErrorsPage {
+	incidents: Vec<IncidentRow>,
+}
+
+pub async fn errors(id: Identity, state: Data<DataRouterState>) -> impl Responder {
+	let session_id = id
+		.identity()
+		.unwrap()
+		.parse::<data_store::DatasetId>()
+		.unwrap();
+	let access = {
+		let sessions = state.sessions.read().unwrap();
+		let session = sessions.get(&session_id).unwrap();
+		let access = session
+			.lock()
+			.unwrap()
+			.db_entry
+			.timeseries_with_access
+			.clone();
+		access
+	};
+
+	let incidents = state
+		.error_router_addr
+		.send(error_router::ListIncidents {
+			access,
+			admin: false,
+			include_resolved: true,
+		})
+		.await
+		.unwrap();
+
+	let incidents = incidents
+		.into_iter()
+		.map(|info| IncidentRow {
+			id: info.id,
+			state: info.incident.state.to_string(),
+			first_seen: info.incident.first_seen.to_rfc2822(),
+			last_seen: info.incident.last_seen.to_rfc2822(),
+			occurrences: info.incident.occurrences,
+			description: info.description,
+		})
+		.collect();
+
+	let page = ErrorsPage { incidents };
+	HttpResponse::Ok().body(page.call().unwrap())
+}
+
+struct FiringRow {
+	alarm: String,
+	at: String,
+	event: &'static str,
+	expression: String,
+	values: String,
+}
+
+#[derive(Template)]
+#[template(path = "alarm_history.hbs")]
+struct AlarmHistoryPage {
+	firings: Vec<FiringRow>,
+}
+
+/// number of firings shown per alarm
+const HISTORY_LEN: usize = 500;
+
+pub async fn alarm_history(id: Identity, state: Data<DataRouterState>) -> impl Responder {
+	let session_id = id
+		.identity()
+		.unwrap()
+		.parse::<data_store::DatasetId>()
+		.unwrap();
+	let user_id = {
+		let sessions = state.sessions.read().unwrap();
+		let session = sessions.get(&session_id).unwrap();
+		let user_id = session.lock().unwrap().db_entry.id;
+		user_id
+	};
+
+	let names = state.alarm_db.history_names(user_id);
+	let mut firings = Vec::new();
+	for alarm_id in state.alarm_db.alarms_with_history(user_id) {
+		let alarm = names
+			.get(&alarm_id)
+			.cloned()
+			.unwrap_or_else(|| alarm_id.to_string());
+		for record in state.alarm_db.history(user_id, alarm_id, HISTORY_LEN) {
+			let values: Vec<String> = record
+				.values
+				.iter()
+				.map(|(var, value)| format!("{}={}", var, value))
+				.collect();
+			firings.push(FiringRow {
+				alarm: alarm.clone(),
+				at: record.at.to_rfc2822(),
+				event: if record.inverted { "re-enabled" } else { "fired" },
+				expression: record.expression,
+				values: values.join(", "),
+			});
+		}
+	}
+
+	let page = AlarmHistoryPage { firings };
+	HttpResponse::Ok().body(page.call().unwrap())
+}
@@ -1,4 +1,4 @@
-use log::{info, trace, warn};
+use log::{error, info, trace, warn};
 use serde::Deserialize;
 
 use actix_identity::Identity;
@@ -12,7 +12,7 @@ use std::sync::{atomic::Ordering, Arc, Mutex};
 use chrono::Utc;
 use std::collections::HashMap;
 
-use crate::data_store::{data_router, data_router::DataRouterState, error_router};
+use crate::data_store::{data_router, data_router::DataRouterState, error_router, NewDataError};
 
 use super::{data_router_ws_client, error_router_ws_client, Session};
 
@@ -94,22 +94,6 @@ pub async fn login_get_and_check(
 		.finish())
 }
 
-#[derive(Deserialize)]
-pub struct TelegramId {
-	_id: String,
-}
-
-pub async fn set_telegram_id_post(
-	_id: Identity,
-	_state: Data<DataRouterState>,
-	_params: Form<TelegramId>,
-) -> wResult<HttpResponse> {
-	//needs reimplementation, look at implementation in menu
-	unimplemented!();
-
-	//Ok(HttpResponse::Ok().finish())
-}
-
 pub fn new_data_post(state: Data<DataRouterState>, body: Bytes) -> HttpResponse {
 	let now = Utc::now();
 	let mut data = state.data.write().unwrap();
@@ -123,7 +107,18 @@ pub fn new_data_post(state: Data<DataRouterState>, body: Bytes) -> HttpResponse
 			});
 			HttpResponse::Ok().status(StatusCode::OK).finish()
 		}
-		Err(_) => HttpResponse::Ok().status(StatusCode::FORBIDDEN).finish(),
+		Err(NewDataError::Append(set_id, e)) => {
+			error!("could not store new data for set {}: {:?}", set_id, e);
+			state
+				.error_router_addr
+				.do_send(error_router::NewError::system(
+					error_router::SystemError::DataAppend,
+				));
+			HttpResponse::Ok()
+				.status(StatusCode::INTERNAL_SERVER_ERROR)
+				.finish()
+		}
+		Err(NewDataError::Rejected) => HttpResponse::Ok().status(StatusCode::FORBIDDEN).finish(),
 	}
 }
 
@@ -16,6 +16,7 @@ use crate::data_store::data_router::DataRouterState;
 use crate::database::User;
 
 use crate::bot;
+use crate::bot::backend::matrix;
 // use login_redirect::CheckLogin;
 
 pub struct Session {
@@ -23,13 +24,24 @@ pub struct Session {
 	//add more temporary user specific data as needed
 }
 
+/// replaces the user in all web sessions of that user, for changes such as
+/// dataset access that should apply without logging in again
+pub fn refresh_sessions(state: &DataRouterState, user: &User) {
+	for session in state.sessions.read().unwrap().values() {
+		let mut session = session.lock().unwrap();
+		if session.db_entry.id == user.id {
+			session.db_entry = user.clone();
+		}
+	}
+}
+
 pub fn start_in_thread (
 	data_router_state: DataRouterState,
 	port: u16,
 	domain: String,
 ) {
 	let cookie_key = utility::make_random_cookie_key();
-	let token = data_router_state.bot_token.clone();
+	let token = data_router_state.backends.telegram.endpoint().to_owned();
 
 	thread::spawn(move || {
 		let sys = actix::System::new();
@@ -64,6 +76,11 @@ pub fn start_in_thread (
 				.service(web::resource("/post_data").to(handlers::new_data_post))
 				.service(web::resource("/post_error").to(handlers::new_error_post))
 				.service(web::resource(&format!("/{}", &token)).to(bot::handle_webhook))
+				// the homeserver puts transactions to <registered url>/transactions/<id>
+				.service(
+					web::resource(&format!("/{}/{{tail:.*}}", matrix::ENDPOINT))
+						.route(web::put().to(bot::handle_matrix)),
+				)
 				.service(
 					web::scope("/")
 						// .wrap(CheckLogin {})
@@ -79,10 +96,17 @@ pub fn start_in_thread (
 							web::resource("list_data")
 								.route(web::get().to(dynamic_pages::list_data)),
 						)
+						.service(
+							web::resource("errors.html").route(web::get().to(dynamic_pages::errors)),
+						)
+						.service(
+							web::resource("alarm_history.html")
+								.route(web::get().to(dynamic_pages::alarm_history)),
+						)
 						.service(
 							web::resource("settings.html")
 								.route(web::get().to(dynamic_pages::settings_page))
-								.route(web::post().to(handlers::set_telegram_id_post)),
+								.route(web::post().to(dynamic_pages::new_link_code)),
 						)
 						//for all other urls we try to resolve to static files in the "web" dir
 						.service(fs::Files::new("", "./web/")),
@@ -6,20 +6,29 @@ mod database;
 mod debug_middleware;
 mod error;
 mod httpserver;
+mod notify;
 mod rpc;
 
 use data_store::{
-	data_router::DataRouter, data_router::DataRouterState, error_router::ErrorRouter,
+	data_router::DataRouter, data_router::DataRouterState, error_router,
+	error_router::ErrorRouter,
+};
+use notify::{email, webhook};
+use bot::backend::{telegram, Address, Backends, Matrix, Ntfy, Telegram};
+use database::{
+	AlarmDatabase, LinkDatabase, PasswordDatabase, ReportDatabase, UserDatabase, UserId,
+	UserLookup,
 };
-use database::{AlarmDatabase, PasswordDatabase, UserDatabase, UserLookup};
 
 use std::collections::HashMap;
+use std::path::PathBuf;
 use std::sync::atomic::AtomicUsize;
 use std::sync::{Arc, RwLock};
 
 use actix::prelude::*;
-use log::error;
+use log::{error, warn};
 use structopt::StructOpt;
+use telegram_bot::types::refs::ChatId;
 
 /// A basic example
 #[derive(StructOpt)]
@@ -43,6 +52,15 @@ struct Opt {
 	#[structopt(short = "t", long = "token")]
 	token: String,
 
+	/// get updates from telegram by long polling instead of a webhook, for
+	/// servers telegram can not reach
+	#[structopt(long = "poll")]
+	poll: bool,
+
+	/// base url of the telegram bot api, for a local bot api server or mock
+	#[structopt(long = "telegram-api", default_value = telegram::DEFAULT_API)]
+	telegram_api: String,
+
 	/// domain, for the webserver www will be added automatically
 	#[structopt(short = "d", long = "domain")]
 	domain: String,
@@ -50,6 +68,159 @@ struct Opt {
 	/// upgrade the database from a previous sled version
 	#[structopt(short = "u", long = "upgrade-db")]
 	upgrade_db: bool,
+
+	/// user that is notified of system errors, can be passed multiple times
+	#[structopt(short = "a", long = "admin")]
+	admins: Vec<String>,
+
+	/// smtp server used to send email notifications, without it
+	/// notifications can not be send via email
+	#[structopt(long = "smtp-server")]
+	smtp_server: Option<String>,
+
+	#[structopt(long = "smtp-port", default_value = "587")]
+	smtp_port: u16,
+
+	/// connect to the smtp server using STARTTLS
+	#[structopt(long = "smtp-tls")]
+	smtp_tls: bool,
+
+	#[structopt(long = "smtp-username", requires = "smtp-password")]
+	smtp_username: Option<String>,
+
+	#[structopt(long = "smtp-password", requires = "smtp-username")]
+	smtp_password: Option<String>,
+
+	/// address notification emails are send from
+	#[structopt(long = "email-from", default_value = "dataserver@localhost")]
+	email_from: String,
+
+	/// template for the email subject, {message}, {expression} and {time}
+	/// are replaced by the notification text, alarm condition and time
+	#[structopt(long = "email-subject", default_value = "dataserver: {message}")]
+	email_subject: String,
+
+	/// template for the email body, see --email-subject
+	#[structopt(
+		long = "email-body",
+		default_value = "{message}\n\ncondition: {expression}\ntime: {time}"
+	)]
+	email_body: String,
+
+	/// matrix homeserver the bot runs on as application service, without it
+	/// matrix rooms can not be linked
+	#[structopt(
+		long = "matrix-homeserver",
+		requires_all = &["matrix-user", "matrix-token", "matrix-hs-token"]
+	)]
+	matrix_homeserver: Option<String>,
+
+	/// user id of the bot, for example @dataserver:example.org
+	#[structopt(long = "matrix-user")]
+	matrix_user: Option<String>,
+
+	/// as_token from the application service registration
+	#[structopt(long = "matrix-token")]
+	matrix_token: Option<String>,
+
+	/// hs_token from the application service registration
+	#[structopt(long = "matrix-hs-token")]
+	matrix_hs_token: Option<String>,
+
+	/// ntfy server to send push notifications through, for example
+	/// https://ntfy.sh
+	#[structopt(long = "ntfy-server")]
+	ntfy_server: Option<String>,
+
+	/// access token for ntfy servers that require authentication
+	#[structopt(long = "ntfy-token", requires = "ntfy-server")]
+	ntfy_token: Option<String>,
+
+	/// file with the key webhook secrets are encrypted with, it is created
+	/// if it does not exist
+	#[structopt(long = "webhook-key", default_value = "webhook.key")]
+	webhook_key: PathBuf,
+}
+
+impl Opt {
+	fn email_config(&self) -> Option<email::Config> {
+		let server = self.smtp_server.clone()?;
+		let credentials = self
+			.smtp_username
+			.clone()
+			.zip(self.smtp_password.clone());
+
+		Some(email::Config {
+			server,
+			port: self.smtp_port,
+			tls: self.smtp_tls,
+			credentials,
+			from: self.email_from.clone(),
+			subject: self.email_subject.clone(),
+			body: self.email_body.replace("\\n", "\n"),
+		})
+	}
+
+	fn backends(&self) -> Backends {
+		let matrix = self.matrix_homeserver.as_ref().map(|homeserver| {
+			Matrix::new(
+				homeserver,
+				self.matrix_user.clone().unwrap(),
+				self.matrix_token.clone().unwrap(),
+				self.matrix_hs_token.clone().unwrap(),
+			)
+			.expect("invalid matrix homeserver")
+		});
+		let ntfy = self
+			.ntfy_server
+			.clone()
+			.map(|server| Ntfy::new(server, self.ntfy_token.clone()));
+
+		Backends {
+			telegram: Telegram::new(self.token.clone(), self.telegram_api.clone()),
+			matrix,
+			ntfy,
+			#[cfg(test)]
+			fake: None,
+		}
+	}
+}
+
+/// subscribes the admins to system errors (dataset_id zero), admins no
+/// longer passed on the command line are unsubscribed
+fn register_admins(
+	names: &[String],
+	user_db: &UserDatabase,
+	db_lookup: &UserLookup,
+	error_router_addr: &Addr<ErrorRouter>,
+) -> Vec<UserId> {
+	let mut admins = Vec::new();
+	let mut options = Vec::new();
+	for name in names {
+		let user = match db_lookup.by_name(name).and_then(|id| user_db.get_user(id)) {
+			Ok(user) => user,
+			Err(e) => {
+				error!("could not register admin {}: {}", name, e);
+				continue;
+			}
+		};
+		admins.push(user.id);
+
+		let chat = user
+			.telegram_id
+			.map(|telegram_id| Address::Telegram(ChatId::new(telegram_id.into())));
+		if chat.is_none() {
+			warn!("admin {} has no telegram id, only their linked chats are notified of system errors", name);
+		}
+		options.push(error_router::NotifyOptions {
+			user_id: user.id,
+			email: None,
+			chat,
+			webhook: None,
+		});
+	}
+	error_router_addr.do_send(error_router::SetAdmins { admins: options });
+	admins
 }
 
 #[actix_web::main]
@@ -78,21 +249,49 @@ async fn main() {
 	let passw_db = PasswordDatabase::from_db(&db).unwrap();
 	let user_db = UserDatabase::from_db(&db).unwrap();
 	let alarm_db = AlarmDatabase::from_db(&db).unwrap();
+	let report_db = ReportDatabase::from_db(&db).unwrap();
+	let link_db = LinkDatabase::from_db(&db).unwrap();
 	let db_lookup = UserLookup::from_user_db(&user_db).unwrap();
 
 	let data = Arc::new(RwLock::new(data_store::init("data").unwrap()));
 
 	let sessions = Arc::new(RwLock::new(HashMap::new()));
 
-	let data_router_addr = DataRouter::new(&data, alarm_db.clone(), opt.token.clone()).start();
-	let error_router_addr = ErrorRouter::load(&db, data.clone()).unwrap().start();
+	let email = opt.email_config().map(email::Mailer::start);
+	let webhook_key = webhook::SecretKey::load_or_create(&opt.webhook_key)
+		.expect("could not read or create the webhook key");
+	let webhooks = webhook::Poster::start(webhook_key);
+	let backends = opt.backends();
+	let error_router_addr = ErrorRouter::load(
+		&db,
+		data.clone(),
+		backends.clone(),
+		link_db.clone(),
+		email.clone(),
+		webhooks.clone(),
+	)
+	.unwrap()
+	.start();
+	let admins = register_admins(&opt.admins, &user_db, &db_lookup, &error_router_addr);
+
+	// the data router needs the state to run commands when alarms go off
+	// while the state contains the routers address, thus create its
+	// context (and address) before the router itself
+	let data_router_ctx: Context<DataRouter> = Context::new();
+	let data_router_addr = data_router_ctx.address();
 
 	let data_router_state = DataRouterState {
 		passw_db: passw_db.clone(),
 		user_db: user_db.clone(),
 		alarm_db: alarm_db.clone(),
+		report_db,
+		link_db,
+		link_codes: bot::commands::link::LinkCodes::default(),
 		db_lookup: db_lookup.clone(),
-		bot_token: opt.token.clone(),
+		backends,
+		admins,
+		email,
+		webhooks,
 
 		data_router_addr: data_router_addr.clone(),
 		error_router_addr: error_router_addr.clone(),
@@ -101,6 +300,8 @@ async fn main() {
 		free_session_ids: Arc::new(AtomicUsize::new(0)),
 		free_ws_session_ids: Arc::new(AtomicUsize::new(0)),
 	};
+	data_router_ctx.run(DataRouter::new(data_router_state.clone()));
+	bot::ReportScheduler::new(data_router_state.clone()).start();
 
 	let http_server = httpserver::start_in_thread( // TODO get out of seperate thread into event loop
 		data_router_state.clone(),
@@ -108,9 +309,20 @@ async fn main() {
 		opt.domain.clone(),
 	);
 
-    let res = bot::set_webhook(&opt.domain, &opt.token, opt.external_port).await;
+	let base_url = format!("{}:{}", opt.domain, opt.external_port);
+	let res = data_router_state
+		.backends
+		.register_endpoints(&base_url, opt.poll)
+		.await;
 	if let Err(e) = res {
-		error!("could not start telegram bot: {:?}", e);
+		error!("could not start messaging bot: {:?}", e);
+	}
+
+	if opt.poll {
+		bot::Poller::new(&db, data_router_state.clone())
+			.unwrap()
+			.run()
+			.await;
 	}
 
     // rpc::host(8080).await; // blocks forever
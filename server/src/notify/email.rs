@@ -0,0 +1,280 @@
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::{Message, SmtpTransport, Transport};
+use log::{error, warn};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use super::render;
+
+const ATTEMPTS: u32 = 5;
+const FIRST_RETRY_DELAY: Duration = Duration::from_secs(10);
+
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+	#[error("invalid email address: {0}")]
+	Address(#[from] lettre::address::AddressError),
+	#[error("could not create email: {0}")]
+	Build(#[from] lettre::error::Error),
+	#[error("could not send email: {0}")]
+	Smtp(#[from] lettre::transport::smtp::Error),
+}
+
+/// smtp server to send notifications through, subject and body are
+/// templates, see `Mailer::send` for the available variables
+#[derive(Debug, Clone)]
+pub struct Config {
+	pub server: String,
+	pub port: u16,
+	pub tls: bool,
+	pub credentials: Option<(String, String)>,
+	pub from: String,
+	pub subject: String,
+	pub body: String,
+}
+
+impl Config {
+	fn transport(&self) -> Result<SmtpTransport, Error> {
+		let mut builder = if self.tls {
+			SmtpTransport::starttls_relay(&self.server)?
+		} else {
+			SmtpTransport::builder_dangerous(&self.server)
+		}
+		.port(self.port)
+		.timeout(Some(Duration::from_secs(10)));
+
+		if let Some((username, password)) = &self.credentials {
+			builder = builder.credentials(Credentials::new(username.clone(), password.clone()));
+		}
+		Ok(builder.build())
+	}
+}
+
+struct Mail {
+	to: String,
+	subject: String,
+	body: String,
+	attempt: u32,
+	retry_delay: Duration,
+}
+
+impl Mail {
+	fn message(&self, config: &Config) -> Result<Message, Error> {
+		Ok(Message::builder()
+			.from(config.from.parse()?)
+			.to(self.to.parse()?)
+			.subject(self.subject.clone())
+			.body(self.body.clone())?)
+	}
+
+	fn deliver(&self, config: &Config) -> Result<(), Error> {
+		let message = self.message(config)?;
+		config.transport()?.send(&message)?;
+		Ok(())
+	}
+}
+
+/// sends mails from a dedicated thread. Failures are retried with an
+/// exponential backoff on that thread, a failing smtp server thus never
+/// delays other notifications
+#[derive(Clone)]
+pub struct Mailer {
+	config: Arc<Config>,
+	queue: Arc<Mutex<Sender<Mail>>>,
+	first_retry_delay: Duration,
+}
+
+impl Mailer {
+	pub fn start(config: Config) -> Self {
+		Self::start_with(config, ATTEMPTS, FIRST_RETRY_DELAY)
+	}
+
+	fn start_with(config: Config, attempts: u32, first_retry_delay: Duration) -> Self {
+		let config = Arc::new(config);
+		let (queue, mails) = mpsc::channel();
+		let thread_config = config.clone();
+		thread::spawn(move || run(&thread_config, mails, attempts));
+		Mailer {
+			config,
+			queue: Arc::new(Mutex::new(queue)),
+			first_retry_delay,
+		}
+	}
+
+	/// queue an email to `to`, the subject and body templates in the config
+	/// are filled in using vars. Returns immediately
+	pub fn send(&self, to: String, vars: &[(&str, &str)]) {
+		let mail = Mail {
+			to,
+			subject: render(&self.config.subject, vars),
+			body: render(&self.config.body, vars),
+			attempt: 1,
+			retry_delay: self.first_retry_delay,
+		};
+		if self.queue.lock().unwrap().send(mail).is_err() {
+			error!("email thread stopped, can not send email");
+		}
+	}
+}
+
+/// tries a mail, if that fails it is added to the retries unless it is out
+/// of attempts or can never succeed
+fn attempt(config: &Config, mut mail: Mail, attempts: u32, retries: &mut Vec<(Instant, Mail)>) {
+	match mail.deliver(config) {
+		Ok(()) => (),
+		Err(err @ Error::Smtp(_)) if mail.attempt < attempts => {
+			warn!("attempt {} of {} failed: {:?}", mail.attempt, attempts, err);
+			let retry_at = Instant::now() + mail.retry_delay;
+			mail.attempt += 1;
+			mail.retry_delay *= 2;
+			retries.push((retry_at, mail));
+		}
+		Err(err) => error!("could not notify client via email: {:?}", err),
+	}
+}
+
+fn run(config: &Config, mails: Receiver<Mail>, attempts: u32) {
+	let mut retries: Vec<(Instant, Mail)> = Vec::new();
+	loop {
+		let next_retry = retries.iter().map(|(at, _)| *at).min();
+		let received = match next_retry {
+			Some(at) => mails.recv_timeout(at.saturating_duration_since(Instant::now())),
+			None => mails.recv().map_err(|_| RecvTimeoutError::Disconnected),
+		};
+		match received {
+			Ok(mail) => attempt(config, mail, attempts, &mut retries),
+			Err(RecvTimeoutError::Timeout) => (),
+			Err(RecvTimeoutError::Disconnected) => return,
+		}
+
+		let now = Instant::now();
+		let (due, waiting) = retries.drain(..).partition(|(at, _)| *at <= now);
+		retries = waiting;
+		for (_, mail) in due {
+			attempt(config, mail, attempts, &mut retries);
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use std::io::{BufRead, BufReader, Write};
+	use std::net::{TcpListener, TcpStream};
+	use std::thread;
+
+	/// minimal smtp server, rejects the first `reject` connections then
+	/// accepts one mail and returns its content
+	fn fake_smtp_server(reject: usize) -> (u16, thread::JoinHandle<String>) {
+		let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+		let port = listener.local_addr().unwrap().port();
+
+		let handle = thread::spawn(move || {
+			for _ in 0..reject {
+				let (mut stream, _) = listener.accept().unwrap();
+				stream.write_all(b"421 service not available\r\n").unwrap();
+			}
+			let (stream, _) = listener.accept().unwrap();
+			serve_one_mail(stream)
+		});
+		(port, handle)
+	}
+
+	fn serve_one_mail(mut stream: TcpStream) -> String {
+		let mut reader = BufReader::new(stream.try_clone().unwrap());
+		stream.write_all(b"220 localhost ESMTP fake\r\n").unwrap();
+
+		let mut mail = String::new();
+		let mut in_data = false;
+		loop {
+			let mut line = String::new();
+			if reader.read_line(&mut line).unwrap() == 0 {
+				return mail;
+			}
+			if in_data {
+				if line == ".\r\n" {
+					in_data = false;
+					stream.write_all(b"250 OK queued\r\n").unwrap();
+				} else {
+					mail.push_str(&line);
+				}
+				continue;
+			}
+
+			let command = line.to_uppercase();
+			if command.starts_with("EHLO") {
+				stream.write_all(b"250-localhost\r\n250 8BITMIME\r\n").unwrap();
+			} else if command.starts_with("DATA") {
+				in_data = true;
+				stream.write_all(b"354 go ahead\r\n").unwrap();
+			} else if command.starts_with("QUIT") {
+				stream.write_all(b"221 bye\r\n").unwrap();
+				return mail;
+			} else {
+				stream.write_all(b"250 OK\r\n").unwrap();
+			}
+		}
+	}
+
+	fn test_config(port: u16) -> Config {
+		Config {
+			server: String::from("127.0.0.1"),
+			port,
+			tls: false,
+			credentials: None,
+			from: String::from("dataserver@example.org"),
+			subject: String::from("alarm: {message}"),
+			body: String::from("{message}\ncondition: {expression}"),
+		}
+	}
+
+	#[test]
+	fn sends_templated_mail() {
+		let (port, server) = fake_smtp_server(0);
+		let mailer = Mailer::start_with(test_config(port), 1, Duration::from_millis(1));
+		let vars = [("message", "too hot"), ("expression", "3_0 > 30")];
+		mailer.send(String::from("user@example.org"), &vars);
+
+		let mail = server.join().unwrap();
+		assert!(mail.contains("Subject: alarm: too hot"));
+		assert!(mail.contains("condition: 3_0 > 30"));
+	}
+
+	#[test]
+	fn retries_after_failure() {
+		let (port, server) = fake_smtp_server(2);
+		let mailer = Mailer::start_with(test_config(port), 3, Duration::from_millis(1));
+		let vars = [("message", "too cold"), ("expression", "3_0 < 10")];
+		mailer.send(String::from("user@example.org"), &vars);
+
+		let mail = server.join().unwrap();
+		assert!(mail.contains("Subject: alarm: too cold"));
+	}
+
+	#[test]
+	fn failing_mail_does_not_delay_others() {
+		let (port, server) = fake_smtp_server(1);
+		let mailer = Mailer::start_with(test_config(port), 3, Duration::from_secs(60));
+		mailer.send(String::from("user@example.org"), &[("message", "first")]);
+		mailer.send(String::from("user@example.org"), &[("message", "second")]);
+
+		let mail = server.join().unwrap();
+		assert!(mail.contains("Subject: alarm: second"));
+	}
+
+	#[test]
+	fn invalid_address_is_not_retried() {
+		let mail = Mail {
+			to: String::from("not an address"),
+			subject: String::new(),
+			body: String::new(),
+			attempt: 1,
+			retry_delay: Duration::from_secs(60),
+		};
+		let mut retries = Vec::new();
+		assert!(matches!(mail.deliver(&test_config(1)), Err(Error::Address(_))));
+		attempt(&test_config(1), mail, 3, &mut retries);
+		assert!(retries.is_empty());
+	}
+}
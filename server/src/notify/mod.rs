@@ -0,0 +1,11 @@
+pub mod email;
+pub mod webhook;
+
+/// replaces every {key} in template with its value
+pub fn render(template: &str, vars: &[(&str, &str)]) -> String {
+	let mut rendered = template.to_owned();
+	for (key, value) in vars {
+		rendered = rendered.replace(&format!("{{{}}}", key), value);
+	}
+	rendered
+}
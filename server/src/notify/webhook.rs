@@ -0,0 +1,580 @@
+use bitspec::FieldId;
+use chrono::{DateTime, Utc};
+use log::{error, warn};
+use reqwest::blocking::Client;
+use reqwest::header::CONTENT_TYPE;
+use reqwest::redirect::Policy;
+use reqwest::Url;
+use ring::rand::{SecureRandom, SystemRandom};
+use ring::{aead, hmac};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{self, Write};
+use std::net::{IpAddr, SocketAddr, ToSocketAddrs};
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::Path;
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::data_store::DatasetId;
+
+const ATTEMPTS: u32 = 5;
+const FIRST_RETRY_DELAY: Duration = Duration::from_secs(10);
+const TIMEOUT: Duration = Duration::from_secs(10);
+/// holds the hex encoded HMAC-SHA256 of the body as: sha256=<signature>
+pub const SIGNATURE_HEADER: &str = "X-Dataserver-Signature";
+
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+	#[error("could not encode payload: {0}")]
+	Encode(#[from] serde_json::Error),
+	#[error("could not reach webhook: {0}")]
+	Request(#[from] reqwest::Error),
+	#[error("webhook responded with: {0}")]
+	Status(reqwest::StatusCode),
+	#[error("\"{0}\" is not a valid url")]
+	InvalidUrl(String),
+	#[error("webhooks need to use https")]
+	NotHttps,
+	#[error("webhooks can not post to local or private addresses: {0}")]
+	Internal(String),
+	#[error("could not look up the webhooks host: {0}")]
+	Resolve(io::Error),
+	#[error("could not decrypt the webhooks secret")]
+	Secret,
+}
+
+/// key the webhook secrets are encrypted with before they are stored, it
+/// is kept in a file outside the database
+#[derive(Clone)]
+pub struct SecretKey([u8; 32]);
+
+impl SecretKey {
+	/// reads the key from path, if there is no such file a new key is
+	/// generated and written to it
+	pub fn load_or_create(path: &Path) -> io::Result<Self> {
+		if let Ok(bytes) = fs::read(path) {
+			let mut key = [0u8; 32];
+			if bytes.len() != key.len() {
+				return Err(io::Error::new(
+					io::ErrorKind::InvalidData,
+					"webhook key file should be 32 bytes",
+				));
+			}
+			key.copy_from_slice(&bytes);
+			return Ok(SecretKey(key));
+		}
+
+		let key = Self::generate();
+		fs::OpenOptions::new()
+			.write(true)
+			.create_new(true)
+			.mode(0o600)
+			.open(path)?
+			.write_all(&key.0)?;
+		Ok(key)
+	}
+
+	/// a new random key
+	pub fn generate() -> Self {
+		let mut key = [0u8; 32];
+		SystemRandom::new().fill(&mut key).unwrap();
+		SecretKey(key)
+	}
+
+	fn aead_key(&self) -> aead::LessSafeKey {
+		let key = aead::UnboundKey::new(&aead::CHACHA20_POLY1305, &self.0).unwrap();
+		aead::LessSafeKey::new(key)
+	}
+
+	fn seal(&self, secret: &str) -> SealedSecret {
+		let mut nonce = [0u8; aead::NONCE_LEN];
+		SystemRandom::new().fill(&mut nonce).unwrap();
+		let mut data = secret.as_bytes().to_vec();
+		self.aead_key()
+			.seal_in_place_append_tag(
+				aead::Nonce::assume_unique_for_key(nonce),
+				aead::Aad::empty(),
+				&mut data,
+			)
+			.unwrap();
+		SealedSecret { nonce, data }
+	}
+
+	fn open(&self, sealed: &SealedSecret) -> Result<String, Error> {
+		let mut data = sealed.data.clone();
+		let secret = self
+			.aead_key()
+			.open_in_place(
+				aead::Nonce::assume_unique_for_key(sealed.nonce),
+				aead::Aad::empty(),
+				&mut data,
+			)
+			.map_err(|_| Error::Secret)?;
+		String::from_utf8(secret.to_vec()).map_err(|_| Error::Secret)
+	}
+}
+
+/// a webhook secret encrypted with the servers SecretKey
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+pub struct SealedSecret {
+	nonce: [u8; aead::NONCE_LEN],
+	data: Vec<u8>,
+}
+
+/// url to post notifications to, if a secret is set the payload is signed
+/// with it
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+pub struct Webhook {
+	pub url: String,
+	pub secret: Option<SealedSecret>,
+}
+
+impl Webhook {
+	/// checks the url can be posted to, DNS names are checked again before
+	/// every post as they can change
+	pub fn new(url: &str, secret: Option<&str>, key: &SecretKey) -> Result<Self, Error> {
+		let parsed = parse_url(url)?;
+		let host = host(&parsed);
+		let internal = match host.parse() {
+			Ok(ip) => is_internal(ip),
+			Err(_) => is_local_name(host),
+		};
+		if internal {
+			return Err(Error::Internal(host.to_owned()));
+		}
+		Ok(Webhook {
+			url: url.to_owned(),
+			secret: secret.map(|secret| key.seal(secret)),
+		})
+	}
+}
+
+fn parse_url(url: &str) -> Result<Url, Error> {
+	let parsed = Url::parse(url).map_err(|_| Error::InvalidUrl(url.to_owned()))?;
+	if parsed.scheme() != "https" {
+		return Err(Error::NotHttps);
+	}
+	if parsed.host().is_none() {
+		return Err(Error::InvalidUrl(url.to_owned()));
+	}
+	Ok(parsed)
+}
+
+/// the host without the brackets around ipv6 addresses
+fn host(url: &Url) -> &str {
+	url.host_str()
+		.unwrap()
+		.trim_start_matches('[')
+		.trim_end_matches(']')
+}
+
+fn is_local_name(domain: &str) -> bool {
+	let domain = domain.trim_end_matches('.').to_lowercase();
+	domain == "localhost" || domain.ends_with(".localhost") || domain.ends_with(".local")
+}
+
+/// addresses of the server itself or the network it is in
+fn is_internal(ip: IpAddr) -> bool {
+	match ip {
+		IpAddr::V4(ip) => {
+			let [a, b, ..] = ip.octets();
+			ip.is_loopback()
+				|| ip.is_private()
+				|| ip.is_link_local()
+				|| ip.is_unspecified()
+				|| ip.is_broadcast()
+				|| ip.is_multicast()
+				|| ip.is_documentation()
+				// shared address space used by carrier grade NAT
+				|| (a == 100 && (64..128).contains(&b))
+		}
+		IpAddr::V6(ip) => {
+			let first = ip.segments()[0];
+			ip.is_loopback()
+				|| ip.is_unspecified()
+				|| ip.is_multicast()
+				// unique local and link local
+				|| first & 0xfe00 == 0xfc00
+				|| first & 0xffc0 == 0xfe80
+				|| ip.to_ipv4().map(|ip| is_internal(IpAddr::V4(ip))).unwrap_or(false)
+		}
+	}
+}
+
+/// the address to post to, fails if the host resolves to any internal
+/// address
+fn public_address(url: &Url) -> Result<SocketAddr, Error> {
+	let host = host(url);
+	if is_local_name(host) {
+		return Err(Error::Internal(host.to_owned()));
+	}
+	let port = url.port_or_known_default().unwrap_or(443);
+	let addresses: Vec<SocketAddr> = (host, port)
+		.to_socket_addrs()
+		.map_err(Error::Resolve)?
+		.collect();
+	if let Some(internal) = addresses.iter().find(|addr| is_internal(addr.ip())) {
+		return Err(Error::Internal(internal.ip().to_string()));
+	}
+	addresses
+		.first()
+		.copied()
+		.ok_or_else(|| Error::Resolve(io::Error::new(io::ErrorKind::NotFound, "no addresses")))
+}
+
+/// the json body posted to a webhook
+#[derive(Debug, Serialize)]
+pub struct Payload {
+	/// the notification as it would be send over telegram, named text as
+	/// that makes the payload work with Slack compatible endpoints
+	pub text: String,
+	pub time: DateTime<Utc>,
+	#[serde(flatten)]
+	pub event: Event,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+pub enum Event {
+	Alarm {
+		expression: String,
+		inverted: bool,
+		values: BTreeMap<String, f64>,
+	},
+	Error {
+		dataset_id: DatasetId,
+		field_ids: Vec<FieldId>,
+		error_code: u8,
+		incident: String,
+	},
+	/// someone acknowledged the incident
+	Claimed { incident: String, by: String },
+}
+
+fn sign(secret: &str, body: &[u8]) -> String {
+	let key = hmac::Key::new(hmac::HMAC_SHA256, secret.as_bytes());
+	let tag = hmac::sign(&key, body);
+	let hex: String = tag.as_ref().iter().map(|b| format!("{:02x}", b)).collect();
+	format!("sha256={}", hex)
+}
+
+type Connect = fn(&str) -> Result<(Client, Url), Error>;
+
+/// a client that only connects to the address the urls host resolves to
+/// right now, if that is public, and does not follow redirects
+fn public_client(url: &str) -> Result<(Client, Url), Error> {
+	let url = parse_url(url)?;
+	let address = public_address(&url)?;
+	let client = Client::builder()
+		.timeout(TIMEOUT)
+		.redirect(Policy::none())
+		.resolve(url.host_str().unwrap(), address)
+		.build()?;
+	Ok((client, url))
+}
+
+struct Post {
+	url: String,
+	body: Vec<u8>,
+	signature: Option<String>,
+	attempt: u32,
+	retry_delay: Duration,
+}
+
+impl Post {
+	fn deliver(&self, connect: Connect) -> Result<(), Error> {
+		let (client, url) = connect(&self.url)?;
+		let mut request = client
+			.post(url)
+			.header(CONTENT_TYPE, "application/json")
+			.body(self.body.clone());
+		if let Some(signature) = &self.signature {
+			request = request.header(SIGNATURE_HEADER, signature);
+		}
+		let response = request.send()?;
+		if response.status().is_success() {
+			Ok(())
+		} else {
+			Err(Error::Status(response.status()))
+		}
+	}
+}
+
+/// posts to webhooks from a dedicated thread. Failures and responses other
+/// then 2xx are retried with an exponential backoff on that thread, a dead
+/// webhook thus never delays other notifications
+#[derive(Clone)]
+pub struct Poster {
+	key: SecretKey,
+	queue: Arc<Mutex<Sender<Post>>>,
+	first_retry_delay: Duration,
+}
+
+impl Poster {
+	pub fn start(key: SecretKey) -> Self {
+		Self::start_with(key, public_client, ATTEMPTS, FIRST_RETRY_DELAY)
+	}
+
+	fn start_with(
+		key: SecretKey,
+		connect: Connect,
+		attempts: u32,
+		first_retry_delay: Duration,
+	) -> Self {
+		let (queue, posts) = mpsc::channel();
+		thread::spawn(move || run(connect, posts, attempts));
+		Poster {
+			key,
+			queue: Arc::new(Mutex::new(queue)),
+			first_retry_delay,
+		}
+	}
+
+	/// the key webhook secrets are encrypted with
+	pub fn key(&self) -> &SecretKey {
+		&self.key
+	}
+
+	/// queue posting the payload to the webhook, signed with its secret if
+	/// it has one. Returns immediately
+	pub fn send(&self, webhook: &Webhook, payload: &Payload) {
+		let post = match self.post(webhook, payload) {
+			Ok(post) => post,
+			Err(err) => {
+				error!("could not notify client via webhook: {:?}", err);
+				return;
+			}
+		};
+		if self.queue.lock().unwrap().send(post).is_err() {
+			error!("webhook thread stopped, can not post to webhook");
+		}
+	}
+
+	fn post(&self, webhook: &Webhook, payload: &Payload) -> Result<Post, Error> {
+		let body = serde_json::to_vec(payload)?;
+		let secret = webhook
+			.secret
+			.as_ref()
+			.map(|sealed| self.key.open(sealed))
+			.transpose()?;
+		Ok(Post {
+			url: webhook.url.clone(),
+			signature: secret.map(|secret| sign(&secret, &body)),
+			body,
+			attempt: 1,
+			retry_delay: self.first_retry_delay,
+		})
+	}
+}
+
+/// tries a post, if that fails it is added to the retries unless it is out
+/// of attempts or can never succeed
+fn attempt(connect: Connect, mut post: Post, attempts: u32, retries: &mut Vec<(Instant, Post)>) {
+	match post.deliver(connect) {
+		Ok(()) => (),
+		Err(err @ Error::Request(_))
+		| Err(err @ Error::Status(_))
+		| Err(err @ Error::Resolve(_))
+			if post.attempt < attempts =>
+		{
+			warn!("attempt {} of {} failed: {:?}", post.attempt, attempts, err);
+			let retry_at = Instant::now() + post.retry_delay;
+			post.attempt += 1;
+			post.retry_delay *= 2;
+			retries.push((retry_at, post));
+		}
+		Err(err) => error!("could not notify client via webhook: {:?}", err),
+	}
+}
+
+fn run(connect: Connect, posts: Receiver<Post>, attempts: u32) {
+	let mut retries: Vec<(Instant, Post)> = Vec::new();
+	loop {
+		let next_retry = retries.iter().map(|(at, _)| *at).min();
+		let received = match next_retry {
+			Some(at) => posts.recv_timeout(at.saturating_duration_since(Instant::now())),
+			None => posts.recv().map_err(|_| RecvTimeoutError::Disconnected),
+		};
+		match received {
+			Ok(post) => attempt(connect, post, attempts, &mut retries),
+			Err(RecvTimeoutError::Timeout) => (),
+			Err(RecvTimeoutError::Disconnected) => return,
+		}
+
+		let now = Instant::now();
+		let (due, waiting) = retries.drain(..).partition(|(at, _)| *at <= now);
+		retries = waiting;
+		for (_, post) in due {
+			attempt(connect, post, attempts, &mut retries);
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use std::io::{BufRead, BufReader, Read, Write};
+	use std::net::TcpListener;
+	use std::thread;
+
+	/// minimal http server, answers the first `reject` requests with an
+	/// internal server error then accepts one and returns its headers and body
+	fn fake_http_server(reject: usize) -> (String, thread::JoinHandle<(String, String)>) {
+		let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+		let url = format!("http://{}/hook", listener.local_addr().unwrap());
+
+		let handle = thread::spawn(move || {
+			for attempt in 0..=reject {
+				let (stream, _) = listener.accept().unwrap();
+				let mut reader = BufReader::new(stream.try_clone().unwrap());
+				let mut headers = String::new();
+				let mut length = 0;
+				loop {
+					let mut line = String::new();
+					reader.read_line(&mut line).unwrap();
+					if line == "\r\n" {
+						break;
+					}
+					let lower = line.to_lowercase();
+					if let Some(value) = lower.strip_prefix("content-length:") {
+						length = value.trim().parse().unwrap();
+					}
+					headers.push_str(&lower);
+				}
+				let mut body = vec![0; length];
+				reader.read_exact(&mut body).unwrap();
+
+				let mut stream = stream;
+				if attempt < reject {
+					stream
+						.write_all(b"HTTP/1.1 500 Internal Server Error\r\nContent-Length: 0\r\nConnection: close\r\n\r\n")
+						.unwrap();
+				} else {
+					stream
+						.write_all(
+							b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+						)
+						.unwrap();
+					return (headers, String::from_utf8(body).unwrap());
+				}
+			}
+			unreachable!()
+		});
+		(url, handle)
+	}
+
+	fn test_payload() -> Payload {
+		Payload {
+			text: String::from("too hot"),
+			time: Utc::now(),
+			event: Event::Alarm {
+				expression: String::from("3_0 > 30"),
+				inverted: false,
+				values: vec![(String::from("3_0"), 31.5)].into_iter().collect(),
+			},
+		}
+	}
+
+	#[test]
+	fn signature() {
+		assert_eq!(
+			sign("key", b"The quick brown fox jumps over the lazy dog"),
+			"sha256=f7bc83f430538424b13298e6aa6fb143ef4d59a14946175997479dbc2d1a3cd8"
+		);
+	}
+
+	/// a client for any http url, the fake servers are local
+	fn local_client(url: &str) -> Result<(Client, Url), Error> {
+		let url = Url::parse(url).map_err(|_| Error::InvalidUrl(url.to_owned()))?;
+		Ok((Client::new(), url))
+	}
+
+	fn poster(attempts: u32, first_retry_delay: Duration) -> Poster {
+		Poster::start_with(
+			SecretKey::generate(),
+			local_client,
+			attempts,
+			first_retry_delay,
+		)
+	}
+
+	fn webhook(poster: &Poster, url: String, secret: Option<&str>) -> Webhook {
+		Webhook {
+			url,
+			secret: secret.map(|secret| poster.key().seal(secret)),
+		}
+	}
+
+	#[test]
+	fn posts_signed_json() {
+		let (url, server) = fake_http_server(0);
+		let poster = poster(1, Duration::from_millis(1));
+		poster.send(&webhook(&poster, url, Some("secret")), &test_payload());
+
+		let (headers, body) = server.join().unwrap();
+		let json: serde_json::Value = serde_json::from_str(&body).unwrap();
+		assert_eq!(json["kind"], "alarm");
+		assert_eq!(json["text"], "too hot");
+		assert_eq!(json["values"]["3_0"], 31.5);
+		let expected = format!("{}: {}", SIGNATURE_HEADER, sign("secret", body.as_bytes()));
+		assert!(headers.contains(&expected.to_lowercase()));
+	}
+
+	#[test]
+	fn retries_after_failure() {
+		let (url, server) = fake_http_server(2);
+		let poster = poster(3, Duration::from_millis(1));
+		poster.send(&webhook(&poster, url, None), &test_payload());
+
+		let (headers, _) = server.join().unwrap();
+		assert!(!headers.contains(&SIGNATURE_HEADER.to_lowercase()));
+	}
+
+	#[test]
+	fn failing_webhook_does_not_delay_others() {
+		let (failing, _) = fake_http_server(usize::MAX);
+		let (working, server) = fake_http_server(0);
+		let poster = poster(5, Duration::from_secs(60));
+
+		let start = Instant::now();
+		poster.send(&webhook(&poster, failing, None), &test_payload());
+		poster.send(&webhook(&poster, working, None), &test_payload());
+		server.join().unwrap();
+		assert!(start.elapsed() < Duration::from_secs(30));
+	}
+
+	#[test]
+	fn only_public_https_urls() {
+		let key = SecretKey::generate();
+		let allowed = Webhook::new("https://example.org/hook", Some("secret"), &key).unwrap();
+		let sealed = allowed.secret.unwrap();
+		assert_ne!(sealed.data, b"secret".to_vec());
+		assert_eq!(key.open(&sealed).unwrap(), "secret");
+		assert!(SecretKey::generate().open(&sealed).is_err());
+
+		for url in &[
+			"https://localhost/hook",
+			"https://127.0.0.1/hook",
+			"https://10.0.0.5:8443/hook",
+			"https://192.168.1.1/hook",
+			"https://169.254.169.254/latest/meta-data",
+			"https://[::1]/hook",
+			"https://[fd00::1]/hook",
+			"https://[::ffff:127.0.0.1]/hook",
+		] {
+			assert!(
+				matches!(Webhook::new(url, None, &key), Err(Error::Internal(_))),
+				"{}",
+				url
+			);
+		}
+		assert!(matches!(
+			Webhook::new("http://example.org/hook", None, &key),
+			Err(Error::NotHttps)
+		));
+	}
+}